@@ -18,6 +18,9 @@ fn dates_pluck(c: &mut Criterion) {
     c.bench_function("pluck dates", |b| {
         b.iter(|| db.duck_pluck("SELECT * FROM dates".to_owned()))
     });
+    c.bench_function("pluck dates (rows)", |b| {
+        b.iter(|| db.duck_pluck_by_rows("SELECT * FROM dates".to_owned()))
+    });
 }
 
 fn dates_pluck_to_hash(c: &mut Criterion) {
@@ -27,6 +30,9 @@ fn dates_pluck_to_hash(c: &mut Criterion) {
     c.bench_function("pluck to hash dates", |b| {
         b.iter(|| db.duck_pluck_to_hash("SELECT * FROM dates".to_owned()))
     });
+    c.bench_function("pluck to hash dates (rows)", |b| {
+        b.iter(|| db.duck_pluck_to_hash_by_rows("SELECT * FROM dates".to_owned()))
+    });
 }
 
 fn timestamps_pluck(c: &mut Criterion) {
@@ -35,6 +41,9 @@ fn timestamps_pluck(c: &mut Criterion) {
     c.bench_function("pluck timestamps", |b| {
         b.iter(|| db.duck_pluck("SELECT * FROM times".to_owned()))
     });
+    c.bench_function("pluck timestamps (rows)", |b| {
+        b.iter(|| db.duck_pluck_by_rows("SELECT * FROM times".to_owned()))
+    });
 }
 
 fn timestamps_pluck_to_hash(c: &mut Criterion) {
@@ -43,6 +52,9 @@ fn timestamps_pluck_to_hash(c: &mut Criterion) {
     c.bench_function("pluck to hash timestamps", |b| {
         b.iter(|| db.duck_pluck_to_hash("SELECT * FROM times".to_owned()))
     });
+    c.bench_function("pluck to hash timestamps (rows)", |b| {
+        b.iter(|| db.duck_pluck_to_hash_by_rows("SELECT * FROM times".to_owned()))
+    });
 }
 
 fn timestamps_tz_pluck(c: &mut Criterion) {
@@ -51,6 +63,9 @@ fn timestamps_tz_pluck(c: &mut Criterion) {
     c.bench_function("pluck timestamps with timezones", |b| {
         b.iter(|| db.duck_pluck("SELECT * FROM times".to_owned()))
     });
+    c.bench_function("pluck timestamps with timezones (rows)", |b| {
+        b.iter(|| db.duck_pluck_by_rows("SELECT * FROM times".to_owned()))
+    });
 }
 
 fn timestamps_tz_pluck_to_hash(c: &mut Criterion) {
@@ -59,6 +74,9 @@ fn timestamps_tz_pluck_to_hash(c: &mut Criterion) {
     c.bench_function("pluck to hash timestamps with timezones", |b| {
         b.iter(|| db.duck_pluck_to_hash("SELECT * FROM times".to_owned()))
     });
+    c.bench_function("pluck to hash timestamps with timezones (rows)", |b| {
+        b.iter(|| db.duck_pluck_to_hash_by_rows("SELECT * FROM times".to_owned()))
+    });
 }
 
 fn i32_pluck(c: &mut Criterion) {
@@ -67,6 +85,9 @@ fn i32_pluck(c: &mut Criterion) {
     c.bench_function("pluck i32", |b| {
         b.iter(|| db.duck_pluck("SELECT * FROM some_ints".to_owned()))
     });
+    c.bench_function("pluck i32 (rows)", |b| {
+        b.iter(|| db.duck_pluck_by_rows("SELECT * FROM some_ints".to_owned()))
+    });
 }
 
 fn i32_pluck_to_hash(c: &mut Criterion) {
@@ -75,6 +96,9 @@ fn i32_pluck_to_hash(c: &mut Criterion) {
     c.bench_function("pluck to hash i32", |b| {
         b.iter(|| db.duck_pluck_to_hash("SELECT * FROM some_ints".to_owned()))
     });
+    c.bench_function("pluck to hash i32 (rows)", |b| {
+        b.iter(|| db.duck_pluck_to_hash_by_rows("SELECT * FROM some_ints".to_owned()))
+    });
 }
 
 fn decimal_pluck(c: &mut Criterion) {
@@ -84,6 +108,9 @@ fn decimal_pluck(c: &mut Criterion) {
     c.bench_function("pluck decimal", |b| {
         b.iter(|| db.duck_pluck("SELECT * FROM some_decimals".to_owned()))
     });
+    c.bench_function("pluck decimal (rows)", |b| {
+        b.iter(|| db.duck_pluck_by_rows("SELECT * FROM some_decimals".to_owned()))
+    });
 }
 
 fn decimal_pluck_to_hash(c: &mut Criterion) {
@@ -93,6 +120,9 @@ fn decimal_pluck_to_hash(c: &mut Criterion) {
     c.bench_function("pluck to hash decimal", |b| {
         b.iter(|| db.duck_pluck_to_hash("SELECT * FROM some_decimals".to_owned()))
     });
+    c.bench_function("pluck to hash decimal (rows)", |b| {
+        b.iter(|| db.duck_pluck_to_hash_by_rows("SELECT * FROM some_decimals".to_owned()))
+    });
 }
 
 fn small_string_pluck(c: &mut Criterion) {
@@ -101,6 +131,9 @@ fn small_string_pluck(c: &mut Criterion) {
     c.bench_function("pluck small string", |b| {
         b.iter(|| db.duck_pluck("SELECT * FROM some_texts".to_owned()))
     });
+    c.bench_function("pluck small string (rows)", |b| {
+        b.iter(|| db.duck_pluck_by_rows("SELECT * FROM some_texts".to_owned()))
+    });
 }
 
 fn small_string_pluck_to_hash(c: &mut Criterion) {
@@ -109,6 +142,9 @@ fn small_string_pluck_to_hash(c: &mut Criterion) {
     c.bench_function("pluck to hash small string", |b| {
         b.iter(|| db.duck_pluck_to_hash("SELECT * FROM some_texts".to_owned()))
     });
+    c.bench_function("pluck to hash small string (rows)", |b| {
+        b.iter(|| db.duck_pluck_to_hash_by_rows("SELECT * FROM some_texts".to_owned()))
+    });
 }
 
 fn large_string_pluck(c: &mut Criterion) {
@@ -117,6 +153,9 @@ fn large_string_pluck(c: &mut Criterion) {
     c.bench_function("pluck large string", |b| {
         b.iter(|| db.duck_pluck("SELECT * FROM some_texts".to_owned()))
     });
+    c.bench_function("pluck large string (rows)", |b| {
+        b.iter(|| db.duck_pluck_by_rows("SELECT * FROM some_texts".to_owned()))
+    });
 }
 
 fn large_string_pluck_to_hash(c: &mut Criterion) {
@@ -125,6 +164,60 @@ fn large_string_pluck_to_hash(c: &mut Criterion) {
     c.bench_function("pluck to hash large string", |b| {
         b.iter(|| db.duck_pluck_to_hash("SELECT * FROM some_texts".to_owned()))
     });
+    c.bench_function("pluck to hash large string (rows)", |b| {
+        b.iter(|| db.duck_pluck_to_hash_by_rows("SELECT * FROM some_texts".to_owned()))
+    });
+}
+
+// 1M row results, converted row by row and column by column (from Arrow record batches)
+fn million_rows_pluck(c: &mut Criterion) {
+    let ruby = get_ruby_vm();
+    ruby.require("date").unwrap();
+    ruby.require("bigdecimal").unwrap();
+    let db = generate_million_rows_data(&ruby);
+    for (name, column) in [("ints", "int_field"), ("strings", "text_field"), ("dates", "date_field"), ("decimals", "decimal_field")] {
+        let query = format!("SELECT {} FROM million_rows", column);
+        let mut group = c.benchmark_group(format!("pluck 1M {}", name));
+        group.sample_size(10);
+        group.bench_function("rows", |b| b.iter(|| db.duck_pluck_by_rows(query.clone())));
+        group.bench_function("arrow", |b| b.iter(|| db.duck_pluck(query.clone())));
+        group.finish();
+    }
+}
+
+fn million_rows_pluck_to_hash(c: &mut Criterion) {
+    let ruby = get_ruby_vm();
+    ruby.require("date").unwrap();
+    ruby.require("bigdecimal").unwrap();
+    let db = generate_million_rows_data(&ruby);
+    let query = "SELECT * FROM million_rows".to_owned();
+    let mut group = c.benchmark_group("pluck to hash 1M rows");
+    group.sample_size(10);
+    group.bench_function("rows", |b| b.iter(|| db.duck_pluck_to_hash_by_rows(query.clone())));
+    group.bench_function("arrow", |b| b.iter(|| db.duck_pluck_to_hash(query.clone())));
+    group.finish();
+}
+
+// rows of every `hash:` flavour, HashWithIndifferentAccess needs ActiveSupport loaded into the embedded ruby
+fn hash_flavours_pluck_to_hash(c: &mut Criterion) {
+    let ruby = get_ruby_vm();
+    ruby.require("date").unwrap();
+    ruby.require("bigdecimal").unwrap();
+    ruby.require("active_support").unwrap();
+    ruby.require("active_support/hash_with_indifferent_access").unwrap();
+    let db = generate_million_rows_data(&ruby);
+    let query = "SELECT * FROM million_rows".to_owned();
+    let mut group = c.benchmark_group("pluck to hash 1M rows flavours");
+    group.sample_size(10);
+    for hash in ["plain", "indifferent", "frozen"] {
+        group.bench_function(format!("rows {}", hash), |b| {
+            b.iter(|| db.duck_pluck_to_hash_as(query.clone(), hash, false))
+        });
+        group.bench_function(format!("arrow {}", hash), |b| {
+            b.iter(|| db.duck_pluck_to_hash_as(query.clone(), hash, true))
+        });
+    }
+    group.finish();
 }
 
 fn get_ruby_vm() -> Ruby {
@@ -139,7 +232,7 @@ fn get_ruby_vm() -> Ruby {
 
 fn generate_timestamp_data(ruby: &Ruby) -> MutDatabase {
     let options = fake_credentials(&ruby);
-    let db = MutDatabase::initialize(options);
+    let db = MutDatabase::initialize(options, None).unwrap();
     db.execute(r"CREATE TABLE times (time_field TIMESTAMP);".to_owned())
         .unwrap();
     db.execute(r"INSERT INTO times (SELECT CURRENT_TIMESTAMP::TIMESTAMP - INTERVAL (d.days) DAY FROM range(0, 20) AS d(days));".to_owned()).unwrap();
@@ -148,7 +241,7 @@ fn generate_timestamp_data(ruby: &Ruby) -> MutDatabase {
 
 fn generate_text_data(ruby: &Ruby, size: usize) -> MutDatabase {
     let options = fake_credentials(&ruby);
-    let db = MutDatabase::initialize(options);
+    let db = MutDatabase::initialize(options, None).unwrap();
     let mut rng = rand::thread_rng();
     let randonm_values = (1..20)
         .map(|_| {
@@ -174,7 +267,7 @@ fn generate_text_data(ruby: &Ruby, size: usize) -> MutDatabase {
 
 fn generate_int_data(ruby: &Ruby) -> MutDatabase {
     let options = fake_credentials(&ruby);
-    let db = MutDatabase::initialize(options);
+    let db = MutDatabase::initialize(options, None).unwrap();
     let mut rng = rand::thread_rng();
     let randonm_num_values = (1..20)
         .map(|_| format!("({})", rng.gen::<i32>()))
@@ -193,7 +286,7 @@ fn generate_int_data(ruby: &Ruby) -> MutDatabase {
 
 fn generate_decimal_data(ruby: &Ruby) -> MutDatabase {
     let options = fake_credentials(&ruby);
-    let db = MutDatabase::initialize(options);
+    let db = MutDatabase::initialize(options, None).unwrap();
     let mut rng = rand::thread_rng();
     let randonm_num_values = (1..20)
         .map(|_| format!("({})", rng.gen::<f64>()))
@@ -212,7 +305,7 @@ fn generate_decimal_data(ruby: &Ruby) -> MutDatabase {
 
 fn generate_timestamp_tz_data(ruby: &Ruby) -> MutDatabase {
     let options = fake_credentials(&ruby);
-    let db = MutDatabase::initialize(options);
+    let db = MutDatabase::initialize(options, None).unwrap();
     db.execute(r"CREATE TABLE times (time_tz_field TIMESTAMPTZ);".to_owned())
         .unwrap();
     db.execute(r"INSERT INTO times (SELECT * from generate_series(TIMESTAMP '2024-12-11', TIMESTAMP '2024-12-30', INTERVAL 1 DAY));".to_owned()).unwrap();
@@ -221,13 +314,29 @@ fn generate_timestamp_tz_data(ruby: &Ruby) -> MutDatabase {
 
 fn generate_dates_data(ruby: &Ruby) -> MutDatabase {
     let options = fake_credentials(&ruby);
-    let db = MutDatabase::initialize(options);
+    let db = MutDatabase::initialize(options, None).unwrap();
     db.execute(r"CREATE TABLE dates (date_field DATE);".to_owned())
         .unwrap();
     db.execute(r"INSERT INTO dates (SELECT CURRENT_DATE - INTERVAL (d.days) DAY FROM range(0, 20) AS d(days));".to_owned()).unwrap();
     db
 }
 
+fn generate_million_rows_data(ruby: &Ruby) -> MutDatabase {
+    let options = fake_credentials(&ruby);
+    let db = MutDatabase::initialize(options, None).unwrap();
+    db.execute(
+        r"CREATE TABLE million_rows AS
+          SELECT (random() * 1000000000)::INTEGER AS int_field,
+                 md5(i::VARCHAR) AS text_field,
+                 DATE '2000-01-01' + (i % 10000)::INTEGER AS date_field,
+                 (random() * 100000)::DECIMAL(18, 3) AS decimal_field
+          FROM range(1000000) t(i);"
+            .to_owned(),
+    )
+    .unwrap();
+    db
+}
+
 fn fake_credentials(ruby: &Ruby) -> RHash {
     let options = RHash::new();
     options
@@ -258,5 +367,8 @@ criterion_group!(
     // small_string_pluck_to_hash,
     // large_string_pluck,
     // large_string_pluck_to_hash
+    million_rows_pluck,
+    million_rows_pluck_to_hash,
+    hash_flavours_pluck_to_hash,
 );
 criterion_main!(benches);
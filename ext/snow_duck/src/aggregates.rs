@@ -0,0 +1,341 @@
+use std::{
+    collections::HashMap,
+    ffi::c_void,
+    mem,
+    panic::{self, AssertUnwindSafe},
+    slice,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError,
+    },
+};
+
+use duckdb::{ffi, Connection};
+use magnus::{gc::Marker, prelude::*, value::Opaque, RArray, Ruby, Value};
+
+use crate::{
+    callbacks::CallbackQueue,
+    column_type::ColumnType,
+    conversions::{self, to_argument_error, to_standard_error},
+    functions::{call_with_arguments, drop_extra_info, to_c_error, RubyFunctions},
+    options::Keywords,
+    raw::{self, ConnectionHandle, RawLogicalType},
+    vector,
+};
+
+type StateId = u64;
+
+// What DuckDB keeps in its aggregate state memory, ruby objects themselves live in AggregateFunction#states
+// where GC can see them. Destructor does not get function info, so every state points to its function
+// (which lives as long as the database, it is kept alive by RubyFunctions).
+#[repr(C)]
+struct AggregateState {
+    function: *const AggregateFunction,
+    id: StateId,
+}
+
+// create_aggregate(..., init:, update:, combine:, finalize: nil)
+//   init:     -> { initial_state }
+//   update:   ->(state, *arguments) { new_state }
+//   combine:  ->(state, other_state) { merged_state }, merges states DuckDB aggregated in parallel
+//   finalize: ->(state) { result }, result is the state itself when not given
+pub(crate) struct AggregateCallables {
+    init: Value,
+    update: Value,
+    combine: Value,
+    finalize: Option<Value>,
+}
+
+impl AggregateCallables {
+    pub(crate) fn from_keywords(keywords: &Keywords) -> Result<Self, magnus::Error> {
+        let required = |name: &str| -> Result<Value, magnus::Error> {
+            let callable = keywords
+                .take::<Value>(name)?
+                .ok_or_else(|| to_argument_error(format!("missing keyword: :{}", name)))?;
+            callable_from_ruby(name, callable)
+        };
+        let init = required("init")?;
+        let update = required("update")?;
+        let combine = required("combine")?;
+        let finalize = keywords
+            .take::<Value>("finalize")?
+            .map(|finalize| callable_from_ruby("finalize", finalize))
+            .transpose()?;
+        Ok(Self {
+            init,
+            update,
+            combine,
+            finalize,
+        })
+    }
+
+    fn all(&self) -> impl Iterator<Item = Value> {
+        [Some(self.init), Some(self.update), Some(self.combine), self.finalize]
+            .into_iter()
+            .flatten()
+    }
+}
+
+fn callable_from_ruby(name: &str, callable: Value) -> Result<Value, magnus::Error> {
+    if callable.respond_to("call", false)? {
+        Ok(callable)
+    } else {
+        Err(to_argument_error(format!("{} must respond to call, got {}", name, callable.inspect())))
+    }
+}
+
+pub(crate) struct AggregateFunction {
+    init: Opaque<Value>,
+    update: Opaque<Value>,
+    combine: Opaque<Value>,
+    finalize: Option<Opaque<Value>>,
+    argument_types: Vec<ColumnType>,
+    return_type: ColumnType,
+    callbacks: Arc<CallbackQueue>,
+    next_state_id: AtomicU64,
+    // ruby states of groups that are being aggregated. Only created (and changed) on ruby thread, but
+    // removed by DuckDB threads once it is done with them. Lock is never held while calling ruby.
+    states: Mutex<HashMap<StateId, Opaque<Value>>>,
+}
+
+impl AggregateFunction {
+    fn states(&self) -> MutexGuard<'_, HashMap<StateId, Opaque<Value>>> {
+        self.states.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub(crate) fn mark_states(&self, ruby: &Ruby, marker: &Marker) {
+        for state in self.states().values() {
+            marker.mark(ruby.get_inner(*state));
+        }
+    }
+
+    // Runs on ruby thread. Groups get their initial state lazily, when first value is added to them
+    fn state(&self, ruby: &Ruby, state_id: StateId) -> Result<Value, magnus::Error> {
+        let state = self.states().get(&state_id).copied();
+        match state {
+            Some(state) => Ok(ruby.get_inner(state)),
+            None => ruby.get_inner(self.init).funcall("call", ()),
+        }
+    }
+
+    fn set_state(&self, state_id: StateId, state: Value) {
+        self.states().insert(state_id, Opaque::from(state));
+    }
+
+    fn update_states(&self, ruby: &Ruby, rows: Vec<(StateId, Vec<duckdb::types::Value>)>) -> Result<(), magnus::Error> {
+        let update = ruby.get_inner(self.update);
+        for (state_id, arguments) in rows {
+            let call_arguments = RArray::with_capacity(arguments.len() + 1);
+            call_arguments.push(self.state(ruby, state_id)?)?;
+            for argument in arguments {
+                call_arguments.push(conversions::duck_to_ruby(argument))?;
+            }
+            let state = call_with_arguments(update, call_arguments)?;
+            self.set_state(state_id, state);
+        }
+        Ok(())
+    }
+
+    fn combine_states(&self, ruby: &Ruby, pairs: Vec<(StateId, StateId)>) -> Result<(), magnus::Error> {
+        let combine = ruby.get_inner(self.combine);
+        for (source_id, target_id) in pairs {
+            let source = self.states().get(&source_id).copied();
+            // source group never got any value, there is nothing to merge
+            let Some(source) = source else {
+                continue;
+            };
+            let target = self.state(ruby, target_id)?;
+            let state: Value = combine.funcall("call", (target, ruby.get_inner(source)))?;
+            self.set_state(target_id, state);
+        }
+        Ok(())
+    }
+
+    fn finalize_states(&self, ruby: &Ruby, state_ids: Vec<StateId>) -> Result<Vec<duckdb::types::Value>, magnus::Error> {
+        state_ids
+            .into_iter()
+            .map(|state_id| {
+                let state = self.state(ruby, state_id)?;
+                let result = match self.finalize {
+                    Some(finalize) => ruby.get_inner(finalize).funcall("call", (state,))?,
+                    None => state,
+                };
+                conversions::ruby_to_duck(result, self.return_type)
+            })
+            .collect()
+    }
+
+    fn call_ruby<T, F>(self: &Arc<Self>, callback: F) -> Result<T, String>
+    where
+        T: Send + 'static,
+        F: FnOnce(&AggregateFunction, &Ruby) -> Result<T, magnus::Error> + Send + 'static,
+    {
+        let function = self.clone();
+        self.callbacks.call_ruby(move |ruby| {
+            callback(&function, ruby).map_err(|error| format!("Ruby aggregate failed: {}", error))
+        })
+    }
+}
+
+impl RubyFunctions {
+    pub(crate) fn create_aggregate_function(
+        &self,
+        connection: &Connection,
+        name: &str,
+        argument_types: Vec<ColumnType>,
+        return_type: ColumnType,
+        callables: AggregateCallables,
+    ) -> Result<(), magnus::Error> {
+        let c_name = raw::to_c_string(name).map_err(to_argument_error)?;
+        let function = Arc::new(AggregateFunction {
+            init: Opaque::from(callables.init),
+            update: Opaque::from(callables.update),
+            combine: Opaque::from(callables.combine),
+            finalize: callables.finalize.map(Opaque::from),
+            argument_types,
+            return_type,
+            callbacks: self.callback_queue(),
+            next_state_id: AtomicU64::new(1),
+            states: Mutex::default(),
+        });
+        let registered = unsafe {
+            let mut aggregate_function = ffi::duckdb_create_aggregate_function();
+            ffi::duckdb_aggregate_function_set_name(aggregate_function, c_name.as_ptr());
+            for argument_type in &function.argument_types {
+                let logical_type = RawLogicalType::new(argument_type.duckdb_type());
+                ffi::duckdb_aggregate_function_add_parameter(aggregate_function, logical_type.handle());
+            }
+            let logical_type = RawLogicalType::new(return_type.duckdb_type());
+            ffi::duckdb_aggregate_function_set_return_type(aggregate_function, logical_type.handle());
+            ffi::duckdb_aggregate_function_set_functions(
+                aggregate_function,
+                Some(state_size),
+                Some(init_state),
+                Some(update_states),
+                Some(combine_states),
+                Some(finalize_states),
+            );
+            ffi::duckdb_aggregate_function_set_destructor(aggregate_function, Some(destroy_states));
+            ffi::duckdb_aggregate_function_set_extra_info(
+                aggregate_function,
+                Box::into_raw(Box::new(function.clone())) as *mut c_void,
+                Some(drop_extra_info::<Arc<AggregateFunction>>),
+            );
+            let state = ffi::duckdb_register_aggregate_function(ConnectionHandle::of(connection).raw(), aggregate_function);
+            ffi::duckdb_destroy_aggregate_function(&mut aggregate_function);
+            state == ffi::DuckDBSuccess
+        };
+        if !registered {
+            return Err(to_standard_error(
+                format!("Could not register aggregate {}, does function with the same name exist already?", name).into(),
+            ));
+        }
+        for callable in callables.all() {
+            self.remember(callable);
+        }
+        self.remember_aggregate(function);
+        Ok(())
+    }
+}
+
+// Callbacks below are called by DuckDB, from its own threads
+
+unsafe fn aggregate_function<'a>(info: ffi::duckdb_function_info) -> &'a Arc<AggregateFunction> {
+    &*(ffi::duckdb_aggregate_function_get_extra_info(info) as *const Arc<AggregateFunction>)
+}
+
+unsafe fn state_id(state: ffi::duckdb_aggregate_state) -> StateId {
+    (*(state as *const AggregateState)).id
+}
+
+unsafe fn states<'a>(states: *mut ffi::duckdb_aggregate_state, count: usize) -> &'a [ffi::duckdb_aggregate_state] {
+    slice::from_raw_parts(states, count)
+}
+
+// unwinding into DuckDB is not allowed, errors (and panics) fail the query
+unsafe fn report_outcome<F>(info: ffi::duckdb_function_info, callback: F)
+where
+    F: FnOnce() -> Result<(), String>,
+{
+    let outcome = panic::catch_unwind(AssertUnwindSafe(callback)).unwrap_or_else(|_| Err("Ruby aggregate panicked".to_owned()));
+    if let Err(message) = outcome {
+        ffi::duckdb_aggregate_function_set_error(info, to_c_error(message).as_ptr());
+    }
+}
+
+unsafe extern "C" fn state_size(_info: ffi::duckdb_function_info) -> ffi::idx_t {
+    mem::size_of::<AggregateState>() as ffi::idx_t
+}
+
+unsafe extern "C" fn init_state(info: ffi::duckdb_function_info, state: ffi::duckdb_aggregate_state) {
+    let function = aggregate_function(info);
+    (state as *mut AggregateState).write(AggregateState {
+        function: Arc::as_ptr(function),
+        id: function.next_state_id.fetch_add(1, Ordering::Relaxed),
+    });
+}
+
+unsafe extern "C" fn update_states(
+    info: ffi::duckdb_function_info,
+    input: ffi::duckdb_data_chunk,
+    states_pointer: *mut ffi::duckdb_aggregate_state,
+) {
+    report_outcome(info, || {
+        let function = aggregate_function(info);
+        let arguments = vector::read_arguments(input, &function.argument_types);
+        let states = states(states_pointer, arguments.len());
+        // rows with NULL argument are skipped, same as built in aggregates do
+        let rows: Vec<(StateId, Vec<duckdb::types::Value>)> = states
+            .iter()
+            .zip(arguments)
+            .filter_map(|(state, arguments)| arguments.map(|arguments| (state_id(*state), arguments)))
+            .collect();
+        if rows.is_empty() {
+            return Ok(());
+        }
+        function.call_ruby(move |function, ruby| function.update_states(ruby, rows))
+    });
+}
+
+unsafe extern "C" fn combine_states(
+    info: ffi::duckdb_function_info,
+    sources: *mut ffi::duckdb_aggregate_state,
+    targets: *mut ffi::duckdb_aggregate_state,
+    count: ffi::idx_t,
+) {
+    report_outcome(info, || {
+        let function = aggregate_function(info);
+        let pairs: Vec<(StateId, StateId)> = states(sources, count as usize)
+            .iter()
+            .zip(states(targets, count as usize))
+            .map(|(source, target)| (state_id(*source), state_id(*target)))
+            .collect();
+        function.call_ruby(move |function, ruby| function.combine_states(ruby, pairs))
+    });
+}
+
+unsafe extern "C" fn finalize_states(
+    info: ffi::duckdb_function_info,
+    sources: *mut ffi::duckdb_aggregate_state,
+    result: ffi::duckdb_vector,
+    count: ffi::idx_t,
+    offset: ffi::idx_t,
+) {
+    report_outcome(info, || {
+        let function = aggregate_function(info);
+        let state_ids: Vec<StateId> = states(sources, count as usize).iter().map(|state| state_id(*state)).collect();
+        let results = function.call_ruby(move |function, ruby| function.finalize_states(ruby, state_ids))?;
+        for (index, value) in results.iter().enumerate() {
+            vector::write_value(result, function.return_type, offset as usize + index, value)?;
+        }
+        Ok(())
+    });
+}
+
+// Ruby states are released once DuckDB is done with the groups, GC collects them afterwards
+unsafe extern "C" fn destroy_states(states_pointer: *mut ffi::duckdb_aggregate_state, count: ffi::idx_t) {
+    for state in states(states_pointer, count as usize) {
+        let state = &*(*state as *const AggregateState);
+        (*state.function).states().remove(&state.id);
+    }
+}
@@ -0,0 +1,130 @@
+use std::{error::Error, io::Cursor};
+
+use duckdb::{
+    arrow::{
+        datatypes::{Schema, SchemaRef},
+        error::ArrowError,
+        ipc::{
+            reader::{FileReader, StreamReader},
+            writer::StreamWriter,
+        },
+        record_batch::RecordBatch,
+    },
+    vtab::{arrow_recordbatch_to_query_params, ArrowVTab},
+    Connection,
+};
+use magnus::{prelude::*, RString, Value};
+
+use crate::conversions::{to_sql_identifier, to_standard_error};
+
+// duckdb-rs table function scanning a record batch, `FROM snow_duck_arrow(?, ?)` with pointers to the batch
+// (arrow_recordbatch_to_query_params) as parameters
+const ARROW_SCAN_FUNCTION: &str = "snow_duck_arrow";
+
+// first bytes of IPC file format, stream format has no magic
+const IPC_FILE_MAGIC: &[u8] = b"ARROW1";
+
+// Registers the arrow scan, once per database
+pub(crate) fn install(connection: &Connection) -> Result<(), magnus::Error> {
+    connection
+        .register_table_function::<ArrowVTab>(ARROW_SCAN_FUNCTION)
+        .map_err(|err| to_standard_error(Box::new(err)))
+}
+
+// Arrow IPC stream of a query result, written one record batch at a time
+pub(crate) struct IpcStream {
+    writer: StreamWriter<Vec<u8>>,
+}
+
+impl IpcStream {
+    pub(crate) fn new(schema: &Schema) -> Result<Self, ArrowError> {
+        Ok(Self {
+            writer: StreamWriter::try_new(Vec::new(), schema)?,
+        })
+    }
+
+    pub(crate) fn write(&mut self, batch: &RecordBatch) -> Result<(), ArrowError> {
+        self.writer.write(batch)
+    }
+
+    // bytes written since they were taken last time
+    pub(crate) fn take_bytes(&mut self) -> Vec<u8> {
+        std::mem::take(self.writer.get_mut())
+    }
+
+    // rest of the stream, with its end of stream marker
+    pub(crate) fn finish(mut self) -> Result<Vec<u8>, ArrowError> {
+        self.writer.finish()?;
+        self.writer.into_inner()
+    }
+}
+
+// IPC data given as binary String, or IO it is read from
+pub(crate) fn bytes_from_ruby(source: Value) -> Result<Vec<u8>, magnus::Error> {
+    let bytes = match RString::from_value(source) {
+        Some(bytes) => bytes,
+        None if source.respond_to("read", false)? => source.funcall("read", ())?,
+        None => {
+            return Err(magnus::Error::new(
+                magnus::exception::type_error(),
+                format!("Arrow IPC data has to be a String or IO, got {}", source.inspect()),
+            ))
+        }
+    };
+    // copied right away, before any ruby code can run and change the string
+    Ok(unsafe { bytes.as_slice() }.to_vec())
+}
+
+// IPC stream or file, whichever `bytes` are
+fn read_batches(bytes: &[u8]) -> Result<(SchemaRef, Vec<RecordBatch>), ArrowError> {
+    if bytes.starts_with(IPC_FILE_MAGIC) {
+        let reader = FileReader::try_new(Cursor::new(bytes), None)?;
+        let schema = reader.schema();
+        return Ok((schema, reader.collect::<Result<_, _>>()?));
+    }
+    let reader = StreamReader::try_new(Cursor::new(bytes), None)?;
+    let schema = reader.schema();
+    Ok((schema, reader.collect::<Result<_, _>>()?))
+}
+
+// Runs without GVL. Creates the table (from IPC schema) unless it exists, and inserts every record batch
+// into it by column name. Returns number of inserted rows. Outside of a transaction the load runs in one,
+// so failed load leaves nothing behind.
+pub(crate) fn load(connection: &Connection, table_name: &str, bytes: &[u8]) -> Result<usize, Box<dyn Error>> {
+    let (schema, batches) = read_batches(bytes)?;
+    let table = to_sql_identifier(table_name);
+    if !connection.is_autocommit() {
+        return load_batches(connection, &table, schema, batches);
+    }
+    connection.execute_batch("BEGIN TRANSACTION")?;
+    match load_batches(connection, &table, schema, batches) {
+        Ok(rows) => {
+            connection.execute_batch("COMMIT")?;
+            Ok(rows)
+        }
+        Err(error) => {
+            // error of the load is the interesting one
+            let _ = connection.execute_batch("ROLLBACK");
+            Err(error)
+        }
+    }
+}
+
+fn load_batches(
+    connection: &Connection,
+    table: &str,
+    schema: SchemaRef,
+    batches: Vec<RecordBatch>,
+) -> Result<usize, Box<dyn Error>> {
+    let scan = format!("SELECT * FROM {}(?, ?)", ARROW_SCAN_FUNCTION);
+    connection.execute(
+        &format!("CREATE TABLE IF NOT EXISTS {} AS {} LIMIT 0", table, scan),
+        arrow_recordbatch_to_query_params(RecordBatch::new_empty(schema)),
+    )?;
+    let mut insert = connection.prepare(&format!("INSERT INTO {} BY NAME {}", table, scan))?;
+    let mut rows = 0;
+    for batch in batches {
+        rows += insert.execute(arrow_recordbatch_to_query_params(batch))?;
+    }
+    Ok(rows)
+}
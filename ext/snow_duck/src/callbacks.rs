@@ -0,0 +1,296 @@
+use std::{
+    collections::VecDeque,
+    mem, panic,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Condvar, Mutex, MutexGuard, PoisonError,
+    },
+    thread::{self, ThreadId},
+    time::{Duration, Instant},
+};
+
+use magnus::Ruby;
+
+use crate::{
+    gvl,
+    interrupt::{InterruptReason, QueryInterrupt},
+};
+
+// Ruby code can only run on a ruby thread holding the GVL, while DuckDB calls functions from its own threads.
+// So queries that can call ruby functions run on a helper thread, and ruby thread that started the query
+// runs ruby callbacks DuckDB asks for in the meantime. Job gets None when it has to be cancelled instead.
+struct Job {
+    run: Box<dyn FnOnce(Option<&Ruby>) + Send>,
+    // some ruby objects (enumerators, their fibers) can only be used from the thread that created them
+    thread: Option<ThreadId>,
+}
+
+impl Job {
+    fn runs_on(&self, thread: ThreadId) -> bool {
+        self.thread.map_or(true, |job_thread| job_thread == thread)
+    }
+}
+
+// One queue per database, functions are registered in its catalog and any of its connections can call them
+#[derive(Default)]
+pub(crate) struct CallbackQueue {
+    state: Mutex<QueueState>,
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct QueueState {
+    jobs: VecDeque<Job>,
+    // ruby threads currently waiting for queries (and running their callbacks)
+    servers: Vec<ThreadId>,
+}
+
+enum Next {
+    Job(Job),
+    Finished,
+    TimedOut,
+}
+
+impl CallbackQueue {
+    fn state(&self) -> MutexGuard<'_, QueueState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // Called from DuckDB threads, blocks until one of ruby threads serving the queue runs `callback`
+    pub(crate) fn call_ruby<T, F>(&self, callback: F) -> Result<T, String>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Ruby) -> Result<T, String> + Send + 'static,
+    {
+        self.call_ruby_on(None, callback)
+    }
+
+    // Same as `call_ruby`, but when `thread` is given only that ruby thread can run `callback`
+    pub(crate) fn call_ruby_on<T, F>(&self, thread: Option<ThreadId>, callback: F) -> Result<T, String>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Ruby) -> Result<T, String> + Send + 'static,
+    {
+        let (reply_sender, reply) = mpsc::channel();
+        let job = Job {
+            run: Box::new(move |ruby| {
+                let outcome = match ruby {
+                    Some(ruby) => callback(ruby),
+                    None => Err("Ruby function call was cancelled, query is being interrupted".to_owned()),
+                };
+                let _ = reply_sender.send(outcome);
+            }),
+            thread,
+        };
+        {
+            let mut state = self.state();
+            if state.servers.is_empty() {
+                return Err("Ruby functions can only be called from queries run through DuckDatabase".to_owned());
+            }
+            if let Some(thread) = thread.filter(|thread| !state.servers.contains(thread)) {
+                return Err(format!("Ruby thread {:?} that started this call is not waiting for the query anymore", thread));
+            }
+            state.jobs.push_back(job);
+        }
+        self.changed.notify_all();
+        reply
+            .recv()
+            .unwrap_or_else(|_| Err("Ruby function call was dropped without running".to_owned()))
+    }
+
+    fn finish(&self, finished: &AtomicBool) {
+        {
+            // flag is set while holding the lock, so server can not miss the wake up
+            let _state = self.state();
+            finished.store(true, Ordering::SeqCst);
+        }
+        self.changed.notify_all();
+    }
+}
+
+// Registered ruby thread serving the queue. When it leaves, jobs only it could run are cancelled,
+// and when the last one leaves all left over jobs are.
+struct Server<'a> {
+    queue: &'a CallbackQueue,
+    thread: ThreadId,
+}
+
+impl<'a> Server<'a> {
+    fn register(queue: &'a CallbackQueue) -> Self {
+        let thread = thread::current().id();
+        queue.state().servers.push(thread);
+        Self { queue, thread }
+    }
+
+    // Blocks (has to be called without GVL) until there is a job to run, query finished or `deadline` passed
+    fn next(&self, finished: &AtomicBool, deadline: Option<Instant>) -> Next {
+        let mut state = self.queue.state();
+        loop {
+            if let Some(position) = state.jobs.iter().position(|job| job.runs_on(self.thread)) {
+                return Next::Job(state.jobs.remove(position).expect("job position is within the queue"));
+            }
+            if finished.load(Ordering::SeqCst) {
+                return Next::Finished;
+            }
+            state = match deadline {
+                None => self.queue.changed.wait(state).unwrap_or_else(PoisonError::into_inner),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Next::TimedOut;
+                    }
+                    self.queue
+                        .changed
+                        .wait_timeout(state, deadline - now)
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .0
+                }
+            };
+        }
+    }
+}
+
+impl Drop for Server<'_> {
+    fn drop(&mut self) {
+        let orphaned_jobs = {
+            let mut state = self.queue.state();
+            if let Some(position) = state.servers.iter().position(|thread| *thread == self.thread) {
+                state.servers.remove(position);
+            }
+            if state.servers.is_empty() {
+                mem::take(&mut state.jobs)
+            } else {
+                let (orphaned, others) = mem::take(&mut state.jobs)
+                    .into_iter()
+                    .partition(|job| job.thread == Some(self.thread));
+                state.jobs = others;
+                orphaned
+            }
+        };
+        for job in orphaned_jobs {
+            (job.run)(None);
+        }
+    }
+}
+
+// Called (with GVL) every `interval` while waiting for the query, e.g. to report its progress
+pub(crate) struct Tick<'a> {
+    pub(crate) interval: Duration,
+    pub(crate) callback: &'a mut dyn FnMut() -> Result<(), magnus::Error>,
+}
+
+// DuckDB statements and rows are not Send, but helper thread is the only one touching them while it runs,
+// ruby thread does not use them until the helper thread is done with them
+struct AssertSend<T>(T);
+
+unsafe impl<T> Send for AssertSend<T> {}
+
+impl<T> AssertSend<T> {
+    fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+type HelperJob = Box<dyn FnOnce() + Send>;
+
+// Long-lived thread running queries of one connection while its ruby thread serves their callbacks, so queries
+// do not spawn a thread each. Started on first use, it exits once the connection (and its sender) is dropped.
+#[derive(Default)]
+pub(crate) struct HelperThread {
+    jobs: Mutex<Option<mpsc::Sender<HelperJob>>>,
+}
+
+impl HelperThread {
+    fn run(&self, job: HelperJob) {
+        let mut jobs = self.jobs.lock().unwrap_or_else(PoisonError::into_inner);
+        let sender = jobs.get_or_insert_with(|| {
+            let (sender, receiver) = mpsc::channel::<HelperJob>();
+            thread::spawn(move || {
+                for job in receiver {
+                    job();
+                }
+            });
+            sender
+        });
+        sender.send(job).expect("helper thread runs as long as the connection")
+    }
+}
+
+// Runs `func` without GVL on the helper thread, while this ruby thread runs ruby callbacks of the query.
+// Ruby interrupt (or failing tick) interrupts the query, and we wait for it to stop before returning the error.
+pub(crate) fn without_gvl_serving<F, R>(
+    queue: &CallbackQueue,
+    helper: &HelperThread,
+    interrupt: &QueryInterrupt,
+    tick: Option<Tick<'_>>,
+    func: F,
+) -> Result<R, magnus::Error>
+where
+    F: FnOnce() -> R,
+{
+    let server = Server::register(queue);
+    let finished = AtomicBool::new(false);
+    let func = AssertSend(func);
+    let (result_sender, result) = mpsc::channel();
+    let finished_ref = &finished;
+    let job: Box<dyn FnOnce() + Send + '_> = Box::new(move || {
+        let outcome = panic::catch_unwind(panic::AssertUnwindSafe(|| AssertSend(func.into_inner()())));
+        queue.finish(finished_ref);
+        // sent last, once it is received the job does not touch anything borrowed from this frame
+        let _ = result_sender.send(outcome);
+    });
+    // SAFETY: job only borrows from this frame, and we do not leave it before receiving the job's result below
+    // (`serve` returns only once the job finished, `stop` waits for it as well)
+    let job = unsafe { mem::transmute::<Box<dyn FnOnce() + Send + '_>, HelperJob>(job) };
+    helper.run(job);
+    let served = serve(&server, &finished, interrupt, tick);
+    let result = match result.recv().expect("helper thread sends result of every job") {
+        Ok(result) => result.into_inner(),
+        Err(panic_payload) => panic::resume_unwind(panic_payload),
+    };
+    served.map(|_| result)
+}
+
+fn serve(
+    server: &Server<'_>,
+    finished: &AtomicBool,
+    interrupt: &QueryInterrupt,
+    mut tick: Option<Tick<'_>>,
+) -> Result<(), magnus::Error> {
+    let ruby = Ruby::get().expect("Ruby not initialized!");
+    let mut next_tick = tick.as_ref().map(|tick| Instant::now() + tick.interval);
+    loop {
+        match gvl::without_gvl_interruptible(|| server.next(finished, next_tick), interrupt) {
+            Ok(Next::Job(job)) => (job.run)(Some(&ruby)),
+            Ok(Next::Finished) => return Ok(()),
+            Ok(Next::TimedOut) => {
+                if let Some(tick) = tick.as_mut() {
+                    if let Err(tick_error) = (tick.callback)() {
+                        stop(server, finished, interrupt, InterruptReason::Request);
+                        return Err(tick_error);
+                    }
+                    next_tick = Some(Instant::now() + tick.interval);
+                }
+            }
+            Err(pending_interrupt) => {
+                stop(server, finished, interrupt, InterruptReason::Ruby);
+                return Err(pending_interrupt);
+            }
+        }
+    }
+}
+
+// Interrupts the query and waits for it to stop, cancelling its callbacks in the meantime
+fn stop(server: &Server<'_>, finished: &AtomicBool, interrupt: &QueryInterrupt, reason: InterruptReason) {
+    interrupt.interrupt(reason);
+    loop {
+        // error means another ruby interrupt came in before we could release GVL, we are raising one already
+        if let Ok(next) = gvl::without_gvl(|| server.next(finished, None)) {
+            match next {
+                Next::Job(job) => (job.run)(None),
+                Next::Finished => return,
+                Next::TimedOut => {}
+            }
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use duckdb::{core::LogicalTypeId, ffi};
+use magnus::{prelude::*, RString, Symbol, Value};
+
+use crate::conversions::to_argument_error;
+
+// DuckDB types values can be passed in, between DuckDB and ruby functions
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ColumnType {
+    Boolean,
+    TinyInt,
+    SmallInt,
+    Integer,
+    BigInt,
+    UTinyInt,
+    USmallInt,
+    UInteger,
+    UBigInt,
+    Float,
+    Double,
+    Varchar,
+    Blob,
+    Date,
+    Timestamp,
+}
+
+// names (and common aliases) types can be given with from ruby, e.g. :integer or 'VARCHAR'
+const COLUMN_TYPES: [(&str, ColumnType); 19] = [
+    ("boolean", ColumnType::Boolean),
+    ("bool", ColumnType::Boolean),
+    ("tinyint", ColumnType::TinyInt),
+    ("smallint", ColumnType::SmallInt),
+    ("integer", ColumnType::Integer),
+    ("int", ColumnType::Integer),
+    ("bigint", ColumnType::BigInt),
+    ("utinyint", ColumnType::UTinyInt),
+    ("usmallint", ColumnType::USmallInt),
+    ("uinteger", ColumnType::UInteger),
+    ("ubigint", ColumnType::UBigInt),
+    ("float", ColumnType::Float),
+    ("double", ColumnType::Double),
+    ("varchar", ColumnType::Varchar),
+    ("string", ColumnType::Varchar),
+    ("text", ColumnType::Varchar),
+    ("blob", ColumnType::Blob),
+    ("date", ColumnType::Date),
+    ("timestamp", ColumnType::Timestamp),
+];
+
+impl ColumnType {
+    pub(crate) fn from_ruby(value: Value) -> Result<Self, magnus::Error> {
+        let name = if let Some(symbol) = Symbol::from_value(value) {
+            symbol.name()?.into_owned()
+        } else if let Some(string) = RString::from_value(value) {
+            string.to_string()?
+        } else {
+            return Err(to_argument_error(format!("Type must be a String or Symbol, got {}", value.inspect())));
+        };
+        let name = name.to_lowercase();
+        COLUMN_TYPES
+            .iter()
+            .find(|(type_name, _)| *type_name == name)
+            .map(|(_, column_type)| *column_type)
+            .ok_or_else(|| {
+                let known_types: Vec<&str> = COLUMN_TYPES.iter().map(|(type_name, _)| *type_name).collect();
+                to_argument_error(format!("Unsupported type {:?}, supported types are {:?}", name, known_types))
+            })
+    }
+
+    pub(crate) fn sql_name(self) -> &'static str {
+        match self {
+            ColumnType::Boolean => "BOOLEAN",
+            ColumnType::TinyInt => "TINYINT",
+            ColumnType::SmallInt => "SMALLINT",
+            ColumnType::Integer => "INTEGER",
+            ColumnType::BigInt => "BIGINT",
+            ColumnType::UTinyInt => "UTINYINT",
+            ColumnType::USmallInt => "USMALLINT",
+            ColumnType::UInteger => "UINTEGER",
+            ColumnType::UBigInt => "UBIGINT",
+            ColumnType::Float => "FLOAT",
+            ColumnType::Double => "DOUBLE",
+            ColumnType::Varchar => "VARCHAR",
+            ColumnType::Blob => "BLOB",
+            ColumnType::Date => "DATE",
+            ColumnType::Timestamp => "TIMESTAMP",
+        }
+    }
+
+    pub(crate) fn duckdb_type(self) -> ffi::duckdb_type {
+        match self {
+            ColumnType::Boolean => ffi::DUCKDB_TYPE_DUCKDB_TYPE_BOOLEAN,
+            ColumnType::TinyInt => ffi::DUCKDB_TYPE_DUCKDB_TYPE_TINYINT,
+            ColumnType::SmallInt => ffi::DUCKDB_TYPE_DUCKDB_TYPE_SMALLINT,
+            ColumnType::Integer => ffi::DUCKDB_TYPE_DUCKDB_TYPE_INTEGER,
+            ColumnType::BigInt => ffi::DUCKDB_TYPE_DUCKDB_TYPE_BIGINT,
+            ColumnType::UTinyInt => ffi::DUCKDB_TYPE_DUCKDB_TYPE_UTINYINT,
+            ColumnType::USmallInt => ffi::DUCKDB_TYPE_DUCKDB_TYPE_USMALLINT,
+            ColumnType::UInteger => ffi::DUCKDB_TYPE_DUCKDB_TYPE_UINTEGER,
+            ColumnType::UBigInt => ffi::DUCKDB_TYPE_DUCKDB_TYPE_UBIGINT,
+            ColumnType::Float => ffi::DUCKDB_TYPE_DUCKDB_TYPE_FLOAT,
+            ColumnType::Double => ffi::DUCKDB_TYPE_DUCKDB_TYPE_DOUBLE,
+            ColumnType::Varchar => ffi::DUCKDB_TYPE_DUCKDB_TYPE_VARCHAR,
+            ColumnType::Blob => ffi::DUCKDB_TYPE_DUCKDB_TYPE_BLOB,
+            ColumnType::Date => ffi::DUCKDB_TYPE_DUCKDB_TYPE_DATE,
+            ColumnType::Timestamp => ffi::DUCKDB_TYPE_DUCKDB_TYPE_TIMESTAMP,
+        }
+    }
+
+    // for table functions, which go through duckdb-rs vtab instead of C API directly
+    pub(crate) fn logical_type_id(self) -> LogicalTypeId {
+        match self {
+            ColumnType::Boolean => LogicalTypeId::Boolean,
+            ColumnType::TinyInt => LogicalTypeId::Tinyint,
+            ColumnType::SmallInt => LogicalTypeId::Smallint,
+            ColumnType::Integer => LogicalTypeId::Integer,
+            ColumnType::BigInt => LogicalTypeId::Bigint,
+            ColumnType::UTinyInt => LogicalTypeId::UTinyint,
+            ColumnType::USmallInt => LogicalTypeId::USmallint,
+            ColumnType::UInteger => LogicalTypeId::UInteger,
+            ColumnType::UBigInt => LogicalTypeId::UBigint,
+            ColumnType::Float => LogicalTypeId::Float,
+            ColumnType::Double => LogicalTypeId::Double,
+            ColumnType::Varchar => LogicalTypeId::Varchar,
+            ColumnType::Blob => LogicalTypeId::Blob,
+            ColumnType::Date => LogicalTypeId::Date,
+            ColumnType::Timestamp => LogicalTypeId::Timestamp,
+        }
+    }
+}
+
+// Types given to ruby functions, like [:integer, :varchar]
+pub(crate) fn column_types_from_ruby(types: magnus::RArray) -> Result<Vec<ColumnType>, magnus::Error> {
+    types.to_vec::<Value>()?.into_iter().map(ColumnType::from_ruby).collect()
+}
@@ -0,0 +1,355 @@
+use std::sync::Arc;
+
+use duckdb::{
+    arrow::{
+        array::{
+            Array, AsArray, BinaryArray, BooleanArray, FixedSizeListArray, LargeBinaryArray, LargeListArray,
+            LargeStringArray, ListArray, MapArray, StringArray, StructArray, UnionArray,
+        },
+        buffer::NullBuffer,
+        datatypes::{
+            ArrowPrimitiveType, DataType, Date32Type, Decimal128Type, Field, FieldRef, Float32Type, Float64Type,
+            Int16Type, Int32Type, Int64Type, Int8Type, IntervalMonthDayNano, IntervalMonthDayNanoType, IntervalUnit,
+            Schema, SchemaRef, Time64MicrosecondType, Time64NanosecondType, TimeUnit as ArrowTimeUnit,
+            TimestampMicrosecondType, TimestampMillisecondType, TimestampNanosecondType, TimestampSecondType,
+            UInt16Type, UInt32Type, UInt64Type, UInt8Type,
+        },
+        record_batch::RecordBatch,
+    },
+    core::{LogicalTypeHandle, LogicalTypeId},
+    types::TimeUnit,
+    Statement,
+};
+use magnus::{prelude::*, IntoValue, RArray, RHash, Ruby, Symbol, Value};
+
+use crate::conversions::{self, to_standard_error};
+
+// Converting results column by column, straight from Arrow record batches DuckDB produces. Type of a column
+// is matched once per batch, instead of every cell going through a name lookup and duckdb::types::Value.
+// Values are the same ones row by row conversion (conversions::duck_to_ruby) returns.
+
+// Arrow column of one record batch, downcast once so reading a cell is indexing into typed values.
+// Also used by serialize module, so CSV/JSON output reads every type the same way.
+//
+// DuckDB exports both HUGEINT and DECIMAL(38, 0) as Arrow Decimal128(38, 0). DECIMAL(38, 0) result columns
+// and values nested in them have DECIMAL_METADATA on their field (see `mark_decimal_columns`). Columns read with
+// `for_field` and their nested values read with `child` tell them apart, anything else is read as HUGEINT.
+pub(crate) struct ColumnReader<'a> {
+    nulls: Option<&'a NullBuffer>,
+    values: Values<'a>,
+    // type of the field the column was read for, its nested fields are the marked ones
+    data_type: &'a DataType,
+}
+
+pub(crate) enum Values<'a> {
+    Boolean(&'a BooleanArray),
+    Int8(&'a [i8]),
+    Int16(&'a [i16]),
+    Int32(&'a [i32]),
+    Int64(&'a [i64]),
+    UInt8(&'a [u8]),
+    UInt16(&'a [u16]),
+    UInt32(&'a [u32]),
+    UInt64(&'a [u64]),
+    Float32(&'a [f32]),
+    Float64(&'a [f64]),
+    // DuckDB exports HUGEINT as DECIMAL(38, 0), so that one is read as Integer unless told otherwise
+    HugeInt(&'a [i128]),
+    Decimal(&'a [i128], i8),
+    Utf8(&'a StringArray),
+    LargeUtf8(&'a LargeStringArray),
+    Binary(&'a BinaryArray),
+    LargeBinary(&'a LargeBinaryArray),
+    // days since epoch
+    Date32(&'a [i32]),
+    Time(&'a [i64], TimeUnit),
+    Interval(&'a [IntervalMonthDayNano]),
+    List(&'a ListArray),
+    LargeList(&'a LargeListArray),
+    FixedSizeList(&'a FixedSizeListArray),
+    Struct(&'a StructArray),
+    Map(&'a MapArray),
+    // DuckDB ENUM, values are read as symbols
+    Enum(Vec<usize>, &'a StringArray),
+    Union(&'a UnionArray),
+    Null,
+    Unsupported(&'a DataType),
+}
+
+fn primitive<T: ArrowPrimitiveType>(array: &dyn Array) -> &[T::Native] {
+    array.as_primitive::<T>().values()
+}
+
+impl<'a> ColumnReader<'a> {
+    pub(crate) fn new(array: &'a dyn Array) -> Self {
+        let values = match array.data_type() {
+            DataType::Boolean => Values::Boolean(array.as_boolean()),
+            DataType::Int8 => Values::Int8(primitive::<Int8Type>(array)),
+            DataType::Int16 => Values::Int16(primitive::<Int16Type>(array)),
+            DataType::Int32 => Values::Int32(primitive::<Int32Type>(array)),
+            DataType::Int64 => Values::Int64(primitive::<Int64Type>(array)),
+            DataType::UInt8 => Values::UInt8(primitive::<UInt8Type>(array)),
+            DataType::UInt16 => Values::UInt16(primitive::<UInt16Type>(array)),
+            DataType::UInt32 => Values::UInt32(primitive::<UInt32Type>(array)),
+            DataType::UInt64 => Values::UInt64(primitive::<UInt64Type>(array)),
+            DataType::Float32 => Values::Float32(primitive::<Float32Type>(array)),
+            DataType::Float64 => Values::Float64(primitive::<Float64Type>(array)),
+            DataType::Decimal128(38, 0) => Values::HugeInt(primitive::<Decimal128Type>(array)),
+            DataType::Decimal128(_, scale) => Values::Decimal(primitive::<Decimal128Type>(array), *scale),
+            DataType::Utf8 => Values::Utf8(array.as_string::<i32>()),
+            DataType::LargeUtf8 => Values::LargeUtf8(array.as_string::<i64>()),
+            DataType::Binary => Values::Binary(array.as_binary::<i32>()),
+            DataType::LargeBinary => Values::LargeBinary(array.as_binary::<i64>()),
+            DataType::Date32 => Values::Date32(primitive::<Date32Type>(array)),
+            DataType::Timestamp(ArrowTimeUnit::Second, _) => {
+                Values::Time(primitive::<TimestampSecondType>(array), TimeUnit::Second)
+            }
+            DataType::Timestamp(ArrowTimeUnit::Millisecond, _) => {
+                Values::Time(primitive::<TimestampMillisecondType>(array), TimeUnit::Millisecond)
+            }
+            DataType::Timestamp(ArrowTimeUnit::Microsecond, _) => {
+                Values::Time(primitive::<TimestampMicrosecondType>(array), TimeUnit::Microsecond)
+            }
+            DataType::Timestamp(ArrowTimeUnit::Nanosecond, _) => {
+                Values::Time(primitive::<TimestampNanosecondType>(array), TimeUnit::Nanosecond)
+            }
+            DataType::Time64(ArrowTimeUnit::Microsecond) => {
+                Values::Time(primitive::<Time64MicrosecondType>(array), TimeUnit::Microsecond)
+            }
+            DataType::Time64(ArrowTimeUnit::Nanosecond) => {
+                Values::Time(primitive::<Time64NanosecondType>(array), TimeUnit::Nanosecond)
+            }
+            DataType::Interval(IntervalUnit::MonthDayNano) => {
+                Values::Interval(primitive::<IntervalMonthDayNanoType>(array))
+            }
+            DataType::List(_) => Values::List(array.as_list::<i32>()),
+            DataType::LargeList(_) => Values::LargeList(array.as_list::<i64>()),
+            DataType::FixedSizeList(_, _) => Values::FixedSizeList(array.as_fixed_size_list()),
+            DataType::Struct(_) => Values::Struct(array.as_struct()),
+            DataType::Map(_, _) => Values::Map(array.as_map()),
+            DataType::Dictionary(_, value_type) if **value_type == DataType::Utf8 => {
+                let dictionary = array.as_any_dictionary();
+                Values::Enum(dictionary.normalized_keys(), dictionary.values().as_string::<i32>())
+            }
+            DataType::Union(_, _) => Values::Union(array.as_union()),
+            DataType::Null => Values::Null,
+            data_type => Values::Unsupported(data_type),
+        };
+        Self {
+            nulls: array.nulls(),
+            values,
+            data_type: array.data_type(),
+        }
+    }
+
+    // column of a result (or value nested in it), its field tells DECIMAL(38, 0) from HUGEINT
+    pub(crate) fn for_field(field: &'a Field, array: &'a dyn Array) -> Self {
+        let mut reader = Self::new(array);
+        reader.data_type = field.data_type();
+        if let Values::HugeInt(values) = reader.values {
+            if field.metadata().contains_key(DECIMAL_METADATA) {
+                reader.values = Values::Decimal(values, 0);
+            }
+        }
+        reader
+    }
+
+    // Reader of values nested in this column: `index` is the struct field, map key (0) or value (1), or union
+    // type id; list items ignore it
+    pub(crate) fn child<'b>(&self, index: usize, array: &'b dyn Array) -> ColumnReader<'b>
+    where
+        'a: 'b,
+    {
+        let field = match self.data_type {
+            DataType::List(item) | DataType::LargeList(item) | DataType::FixedSizeList(item, _) => Some(item),
+            DataType::Struct(fields) => fields.get(index),
+            DataType::Map(entries, _) => match entries.data_type() {
+                DataType::Struct(fields) => fields.get(index),
+                _ => None,
+            },
+            DataType::Union(fields, _) => fields
+                .iter()
+                .find(|(type_id, _)| usize::try_from(*type_id).ok() == Some(index))
+                .map(|(_, field)| field),
+            _ => None,
+        };
+        match field {
+            Some(field) => ColumnReader::for_field(field, array),
+            None => ColumnReader::new(array),
+        }
+    }
+
+    pub(crate) fn is_null(&self, row: usize) -> bool {
+        self.nulls.map_or(false, |nulls| nulls.is_null(row))
+    }
+
+    pub(crate) fn values(&self) -> &Values<'a> {
+        &self.values
+    }
+
+    pub(crate) fn value(&self, ruby: &Ruby, row: usize) -> Result<Value, magnus::Error> {
+        if self.is_null(row) {
+            return Ok(ruby.qnil().as_value());
+        }
+        let value = match &self.values {
+            Values::Boolean(array) => array.value(row).into_value_with(ruby),
+            Values::Int8(values) => values[row].into_value_with(ruby),
+            Values::Int16(values) => values[row].into_value_with(ruby),
+            Values::Int32(values) => values[row].into_value_with(ruby),
+            Values::Int64(values) => values[row].into_value_with(ruby),
+            Values::UInt8(values) => values[row].into_value_with(ruby),
+            Values::UInt16(values) => values[row].into_value_with(ruby),
+            Values::UInt32(values) => values[row].into_value_with(ruby),
+            Values::UInt64(values) => values[row].into_value_with(ruby),
+            Values::Float32(values) => values[row].into_value_with(ruby),
+            Values::Float64(values) => values[row].into_value_with(ruby),
+            Values::HugeInt(values) => hugeint_to_ruby(ruby, values[row])?,
+            Values::Decimal(values, scale) => decimal_to_ruby(ruby, values[row], *scale)?,
+            Values::Utf8(array) => ruby.str_new(array.value(row)).as_value(),
+            Values::LargeUtf8(array) => ruby.str_new(array.value(row)).as_value(),
+            // same as row by row conversion, which reads BLOBs as arrays of bytes
+            Values::Binary(array) => array.value(row).to_vec().into_value_with(ruby),
+            Values::LargeBinary(array) => array.value(row).to_vec().into_value_with(ruby),
+            Values::Date32(days) => conversions::convert_duck_date(days[row]),
+            Values::Time(values, unit) => conversions::convert_duck_time(*unit, values[row]),
+            Values::Interval(values) => {
+                let interval = values[row];
+                conversions::convert_duck_interval(interval.months, interval.days, interval.nanoseconds)
+            }
+            Values::List(array) => self.items_to_ruby(ruby, array.value(row).as_ref())?.as_value(),
+            Values::LargeList(array) => self.items_to_ruby(ruby, array.value(row).as_ref())?.as_value(),
+            Values::FixedSizeList(array) => self.items_to_ruby(ruby, array.value(row).as_ref())?.as_value(),
+            Values::Struct(array) => {
+                let hash = RHash::new();
+                for (index, (field, column)) in array.fields().iter().zip(array.columns()).enumerate() {
+                    hash.aset(ruby.str_new(field.name()), self.child(index, column.as_ref()).value(ruby, row)?)?;
+                }
+                hash.as_value()
+            }
+            Values::Map(array) => {
+                let entries = array.value(row);
+                let (keys, values) = (self.child(0, entries.column(0).as_ref()), self.child(1, entries.column(1).as_ref()));
+                let hash = RHash::new();
+                for entry in 0..entries.len() {
+                    hash.aset(keys.value(ruby, entry)?, values.value(ruby, entry)?)?;
+                }
+                hash.as_value()
+            }
+            Values::Enum(keys, names) => Symbol::new(names.value(keys[row])).as_value(),
+            Values::Union(array) => {
+                let type_id = array.type_id(row);
+                let child = array.child(type_id);
+                self.child(type_id as usize, child.as_ref()).value(ruby, array.value_offset(row))?
+            }
+            Values::Null => ruby.qnil().as_value(),
+            Values::Unsupported(data_type) => {
+                return Err(to_standard_error(format!("Unsupported result column type {}", data_type).into()));
+            }
+        };
+        Ok(value)
+    }
+
+    // items of one list value of this column, as ruby Array
+    fn items_to_ruby(&self, ruby: &Ruby, items: &dyn Array) -> Result<RArray, magnus::Error> {
+        let values = RArray::with_capacity(items.len());
+        append_values(ruby, &self.child(0, items), items.len(), values)?;
+        Ok(values)
+    }
+}
+
+fn hugeint_to_ruby(ruby: &Ruby, value: i128) -> Result<Value, magnus::Error> {
+    match i64::try_from(value) {
+        Ok(value) => Ok(value.into_value_with(ruby)),
+        Err(_) => ruby.str_new(&value.to_string()).funcall("to_i", ()),
+    }
+}
+
+// BigDecimal("12345e-2") is 123.45, no need to place decimal point ourselves
+fn decimal_to_ruby(ruby: &Ruby, value: i128, scale: i8) -> Result<Value, magnus::Error> {
+    ruby.module_kernel().funcall("BigDecimal", (format!("{}e{}", value, -i32::from(scale)),))
+}
+
+// Pushes every value of the result column to `values`, e.g. one record batch after another
+pub(crate) fn append_column(ruby: &Ruby, batch: &RecordBatch, column: usize, values: RArray) -> Result<(), magnus::Error> {
+    let reader = ColumnReader::for_field(batch.schema_ref().field(column), batch.column(column).as_ref());
+    append_values(ruby, &reader, batch.num_rows(), values)
+}
+
+fn append_values(ruby: &Ruby, reader: &ColumnReader<'_>, len: usize, values: RArray) -> Result<(), magnus::Error> {
+    for row in 0..len {
+        values.push(reader.value(ruby, row)?)?;
+    }
+    Ok(())
+}
+
+// Readers of every result column of the batch
+pub(crate) fn batch_readers(batch: &RecordBatch) -> Vec<ColumnReader<'_>> {
+    batch
+        .schema_ref()
+        .fields()
+        .iter()
+        .zip(batch.columns())
+        .map(|(field, column)| ColumnReader::for_field(field, column.as_ref()))
+        .collect()
+}
+
+// Columns of the batch (each as ruby Array), kept in ruby Array so GC sees them while rows are built from them
+pub(crate) fn batch_to_ruby_columns(ruby: &Ruby, batch: &RecordBatch) -> Result<RArray, magnus::Error> {
+    let columns = RArray::with_capacity(batch.num_columns());
+    for column in 0..batch.num_columns() {
+        let values = RArray::with_capacity(batch.num_rows());
+        append_column(ruby, batch, column, values)?;
+        columns.push(values)?;
+    }
+    Ok(columns)
+}
+
+// field metadata of DECIMAL(38, 0) result columns and values nested in them
+const DECIMAL_METADATA: &str = "snow_duck.decimal";
+
+// Marks DECIMAL(38, 0) fields of the executed statement's result schema (including fields nested in lists,
+// structs, maps and unions), so they are not read as HUGEINT. DuckDB logical types of the result columns tell
+// them apart.
+pub(crate) fn mark_decimal_columns(statement: &Statement<'_>, schema: SchemaRef) -> SchemaRef {
+    let fields: Vec<Field> = schema
+        .fields()
+        .iter()
+        .enumerate()
+        .map(|(column, field)| mark_decimal_field(field, &statement.column_logical_type(column)))
+        .collect();
+    Arc::new(Schema::new_with_metadata(fields, schema.metadata().clone()))
+}
+
+fn mark_decimal_field(field: &Field, logical_type: &LogicalTypeHandle) -> Field {
+    let mark_child = |child: &FieldRef, index: usize| Arc::new(mark_decimal_field(child, &logical_type.child(index)));
+    let data_type = match (field.data_type(), logical_type.id()) {
+        (DataType::Decimal128(38, 0), LogicalTypeId::Decimal) => {
+            let mut metadata = field.metadata().clone();
+            metadata.insert(DECIMAL_METADATA.to_owned(), "true".to_owned());
+            return field.clone().with_metadata(metadata);
+        }
+        (DataType::List(item), LogicalTypeId::List) => DataType::List(mark_child(item, 0)),
+        (DataType::LargeList(item), LogicalTypeId::List) => DataType::LargeList(mark_child(item, 0)),
+        (DataType::FixedSizeList(item, size), LogicalTypeId::Array) => DataType::FixedSizeList(mark_child(item, 0), *size),
+        (DataType::Struct(fields), LogicalTypeId::Struct) => {
+            DataType::Struct(fields.iter().enumerate().map(|(index, child)| mark_child(child, index)).collect())
+        }
+        // entries are a struct of key and value, which are children 0 and 1 of the map type
+        (DataType::Map(entries, sorted), LogicalTypeId::Map) => match entries.data_type() {
+            DataType::Struct(fields) => {
+                let fields = fields.iter().enumerate().map(|(index, child)| mark_child(child, index)).collect();
+                DataType::Map(Arc::new(entries.as_ref().clone().with_data_type(DataType::Struct(fields))), *sorted)
+            }
+            _ => return field.clone(),
+        },
+        (DataType::Union(fields, mode), LogicalTypeId::Union) => DataType::Union(
+            fields
+                .iter()
+                .map(|(type_id, child)| (type_id, mark_child(child, type_id as usize)))
+                .collect(),
+            *mode,
+        ),
+        _ => return field.clone(),
+    };
+    field.clone().with_data_type(data_type)
+}
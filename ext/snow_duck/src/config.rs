@@ -0,0 +1,130 @@
+use magnus::{class, value::ReprValue, Integer, RHash, RString, Symbol, Value};
+
+use crate::conversions::{to_argument_error, to_sql_string_literal};
+
+#[derive(Clone, Copy)]
+enum SettingKind {
+    PositiveInteger,
+    // accepts '4GB' like strings or number of bytes
+    Size,
+    Text,
+    Boolean,
+    Order,
+}
+
+// DuckDB settings we allow to be set from ruby, both on startup and at runtime
+const SETTINGS: [(&str, SettingKind); 7] = [
+    ("threads", SettingKind::PositiveInteger),
+    ("memory_limit", SettingKind::Size),
+    ("temp_directory", SettingKind::Text),
+    ("max_temp_directory_size", SettingKind::Size),
+    ("preserve_insertion_order", SettingKind::Boolean),
+    ("default_order", SettingKind::Order),
+    ("enable_progress_bar", SettingKind::Boolean),
+];
+
+pub(crate) enum SettingValue {
+    Integer(i64),
+    Boolean(bool),
+    Text(String),
+}
+
+impl SettingValue {
+    fn to_config_string(&self) -> String {
+        match self {
+            SettingValue::Integer(value) => value.to_string(),
+            SettingValue::Boolean(value) => value.to_string(),
+            SettingValue::Text(value) => value.clone(),
+        }
+    }
+
+    fn to_sql_literal(&self) -> String {
+        match self {
+            SettingValue::Text(value) => to_sql_string_literal(value),
+            other => other.to_config_string(),
+        }
+    }
+}
+
+pub(crate) fn setting_name_from_ruby(name: Value) -> Result<String, magnus::Error> {
+    if let Some(symbol) = Symbol::from_value(name) {
+        return Ok(symbol.name()?.into_owned());
+    }
+    RString::from_value(name)
+        .ok_or_else(|| to_argument_error(format!("Setting name must be a String or Symbol, got {}", name.inspect())))?
+        .to_string()
+}
+
+pub(crate) fn setting_value_from_ruby(name: &str, value: Value) -> Result<SettingValue, magnus::Error> {
+    let (_, kind) = SETTINGS
+        .iter()
+        .find(|(setting_name, _)| *setting_name == name)
+        .ok_or_else(|| {
+            let known_settings: Vec<&str> = SETTINGS.iter().map(|(setting_name, _)| *setting_name).collect();
+            to_argument_error(format!("Unknown DuckDB setting {:?}, supported settings are {:?}", name, known_settings))
+        })?;
+    let invalid_value = |expected: &str| to_argument_error(format!("Setting {} expects {}, got {}", name, expected, value.inspect()));
+
+    match kind {
+        SettingKind::PositiveInteger => Integer::from_value(value)
+            .and_then(|integer| integer.to_i64().ok())
+            .filter(|integer| *integer > 0)
+            .map(SettingValue::Integer)
+            .ok_or_else(|| invalid_value("a positive Integer")),
+        SettingKind::Size => {
+            if let Some(bytes) = Integer::from_value(value).and_then(|integer| integer.to_u64().ok()) {
+                Ok(SettingValue::Text(format!("{}B", bytes)))
+            } else {
+                RString::from_value(value)
+                    .map(|size| size.to_string())
+                    .transpose()?
+                    .map(SettingValue::Text)
+                    .ok_or_else(|| invalid_value("a String like '4GB' or a number of bytes"))
+            }
+        }
+        SettingKind::Text => RString::from_value(value)
+            .map(|text| text.to_string())
+            .transpose()?
+            .map(SettingValue::Text)
+            .ok_or_else(|| invalid_value("a String")),
+        SettingKind::Boolean => {
+            if value.is_kind_of(class::true_class()) {
+                Ok(SettingValue::Boolean(true))
+            } else if value.is_kind_of(class::false_class()) {
+                Ok(SettingValue::Boolean(false))
+            } else {
+                Err(invalid_value("true or false"))
+            }
+        }
+        SettingKind::Order => setting_name_from_ruby(value)
+            .ok()
+            .map(|order| order.to_lowercase())
+            .filter(|order| ["asc", "desc"].contains(&order.as_str()))
+            .map(SettingValue::Text)
+            .ok_or_else(|| invalid_value(":asc or :desc")),
+    }
+}
+
+// DuckDB only tracks progress of queries (reported to on_progress) with progress bar enabled, it is never printed
+const DEFAULT_CONFIG: [(&str, &str); 2] = [("enable_progress_bar", "true"), ("enable_progress_bar_print", "false")];
+
+// Validated settings, as (name, value) pairs DuckDB config expects. Defaults come first, so settings given
+// from ruby override them.
+pub(crate) fn database_config(config: Option<RHash>) -> Result<Vec<(String, String)>, magnus::Error> {
+    let defaults = DEFAULT_CONFIG
+        .iter()
+        .map(|(name, value)| -> Result<(String, String), magnus::Error> { Ok((name.to_string(), value.to_string())) });
+    let Some(config) = config else {
+        return defaults.collect();
+    };
+    let settings = config.to_vec::<Value, Value>()?.into_iter().map(|(name, value)| -> Result<(String, String), magnus::Error> {
+        let name = setting_name_from_ruby(name)?;
+        let value = setting_value_from_ruby(&name, value)?;
+        Ok((name, value.to_config_string()))
+    });
+    defaults.chain(settings).collect()
+}
+
+pub(crate) fn set_statement(name: &str, value: &SettingValue) -> String {
+    format!("SET {} = {}", name, value.to_sql_literal())
+}
@@ -2,7 +2,9 @@ use std::error;
 
 use chrono::{NaiveDate, Datelike};
 use duckdb::{types::{OrderedMap, TimeUnit}, ToSql};
-use magnus::{eval, value::ReprValue, Class, IntoValue, RArray, RClass, RHash, RString, Ruby};
+use magnus::{eval, value::ReprValue, Class, Integer, IntoValue, RArray, RClass, RHash, RString, Ruby, Symbol, TryConvert};
+
+use crate::column_type::ColumnType;
 
 static TIME_CLASS: magnus::value::Lazy<RClass> = magnus::value::Lazy::new(|ruby| ruby.class_time());
 static EPOCH_START: once_cell::sync::Lazy<NaiveDate> = once_cell::sync::Lazy::new(|| NaiveDate::from_ymd_opt(1970, 1, 1).unwrap());
@@ -12,28 +14,54 @@ static SECONDS_PER_DAY: once_cell::sync::Lazy<i64> = once_cell::sync::Lazy::new(
 static SECONDS_PER_MONTH: once_cell::sync::Lazy<i64> = once_cell::sync::Lazy::new(|| eval::<i64>("ActiveSupport::Duration::SECONDS_PER_MONTH").unwrap());
 
 const NANOS_PER_SECOND: i64 = 1_000_000_000;
+const MICROS_PER_SECOND: i64 = 1_000_000;
+// Julian day number of 1970-01-01, DuckDB dates are days since then
+const EPOCH_JULIAN_DAY: i64 = 2_440_588;
 
-pub (crate) fn string_from_ruby_hash(input: magnus::RHash, key: &str) -> String {
-    // let better_option: magnus::RHash = input.funcall("with_indifferent_access", ()).unwrap();
-    // println!("Better option hash is {:?}, trying to get {:?} key", better_option, key);
-    RString::from_value(
-        input
-            .get(key)
-            .unwrap_or_else(|| panic!("{:?} key required to instantiate duckdb", key))
-    )
-    .unwrap_or_else(|| panic!("Value provided as {:?} was not string", key))
-    .to_string()
-    .unwrap()
+pub (crate) fn string_from_ruby_hash(input: magnus::RHash, key: &str) -> Result<String, magnus::Error> {
+    optional_string_from_ruby_hash(input, key)?
+        .ok_or_else(|| to_argument_error(format!("{:?} key required to instantiate duckdb", key)))
 }
 
-pub (crate) fn to_standard_column_error(error: &duckdb::Error, column_name: &String) -> magnus::Error {
-    to_standard_error(format!("Error converting value of column {} : {}", column_name, error).into())
+pub (crate) fn optional_string_from_ruby_hash(input: magnus::RHash, key: &str) -> Result<Option<String>, magnus::Error> {
+    match input.get(key) {
+        None => Ok(None),
+        Some(value) if value.is_nil() => Ok(None),
+        Some(value) => RString::from_value(value)
+            .ok_or_else(|| to_argument_error(format!("Value provided as {:?} was not string", key)))?
+            .to_string()
+            .map(Some),
+    }
 }
 
 pub (crate) fn to_standard_error(error: Box<dyn error::Error>) -> magnus::Error {
     magnus::Error::new(magnus::exception::standard_error(), error.to_string())
 }
 
+pub (crate) fn to_argument_error(message: String) -> magnus::Error {
+    magnus::Error::new(magnus::exception::arg_error(), message)
+}
+
+// name of a function, table etc. given as String or Symbol
+pub (crate) fn identifier_from_ruby(value: magnus::Value, what: &str) -> Result<String, magnus::Error> {
+    if let Some(symbol) = Symbol::from_value(value) {
+        return Ok(symbol.name()?.into_owned());
+    }
+    RString::from_value(value)
+        .ok_or_else(|| to_argument_error(format!("{} must be a String or Symbol, got {}", what, value.inspect())))?
+        .to_string()
+}
+
+// escapes value so it can be safely embedded in single quoted SQL string literal
+pub (crate) fn to_sql_string_literal(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+// quotes name so it can be safely embedded in SQL as (a single) identifier
+pub (crate) fn to_sql_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
 #[inline]
 pub fn duck_to_ruby(duck_val: duckdb::types::Value) -> magnus::value::Value {
     match duck_val {
@@ -96,7 +124,7 @@ fn convert_vector_to_array(duck_vec: Vec<duckdb::types::Value>) -> magnus::Value
 }
 
 #[inline]
-fn convert_duck_time(time_unit: TimeUnit, time_value: i64) -> magnus::Value {
+pub (crate) fn convert_duck_time(time_unit: TimeUnit, time_value: i64) -> magnus::Value {
     let ruby = Ruby::get().expect("Ruby not initialized!");
     let time_class_unwrapped = ruby.get_inner(&TIME_CLASS);
     let time_unit_amounts = match time_unit {
@@ -110,20 +138,97 @@ fn convert_duck_time(time_unit: TimeUnit, time_value: i64) -> magnus::Value {
     time_class_unwrapped.funcall("at", (fractional_seconds, )).unwrap()
 }
 
+// DuckDB dates are days since 1970-01-01
 #[inline]
-fn convert_duck_date(number_of_days: i32) -> magnus::Value {
+pub (crate) fn duck_date(number_of_days: i32) -> NaiveDate {
+    *EPOCH_START + chrono::Duration::days(number_of_days.into())
+}
+
+#[inline]
+pub (crate) fn convert_duck_date(number_of_days: i32) -> magnus::Value {
     let ruby = Ruby::get().expect("Ruby not initialized!");
-    let date = *EPOCH_START + chrono::Duration::days(number_of_days.into());
+    let date = duck_date(number_of_days);
     let (day, month, year) = (date.day(), date.month(), date.year());
     ruby.get_inner(&DATE_CLASS).new_instance((year, month, day)).unwrap()
 }
 
+// Seconds of ActiveSupport::Duration interval is converted to, needs GVL (month and day lengths are read
+// from ActiveSupport)
 #[inline]
-fn convert_duck_interval(months: i32, days: i32, nanos: i64) -> magnus::Value {
-    let ruby = Ruby::get().expect("Ruby not initialized!");
+pub (crate) fn duck_interval_seconds(months: i32, days: i32, nanos: i64) -> i64 {
     let month_seconds = months as i64 * *SECONDS_PER_MONTH;
     let day_seconds = days as i64 * *SECONDS_PER_DAY;
     let nano_seconds = nanos / NANOS_PER_SECOND;
-    let total_seconds = month_seconds + day_seconds + nano_seconds;
+    month_seconds + day_seconds + nano_seconds
+}
+
+#[inline]
+pub (crate) fn convert_duck_interval(months: i32, days: i32, nanos: i64) -> magnus::Value {
+    let ruby = Ruby::get().expect("Ruby not initialized!");
+    let total_seconds = duck_interval_seconds(months, days, nanos);
     ruby.get_inner(&DURATION_CLASS).funcall("seconds", (total_seconds,)).unwrap()
-}
\ No newline at end of file
+}
+
+// Converts value returned from ruby (function results, rows of table functions...) to DuckDB value of `column_type`
+pub (crate) fn ruby_to_duck(value: magnus::Value, column_type: ColumnType) -> Result<duckdb::types::Value, magnus::Error> {
+    if value.is_nil() {
+        return Ok(duckdb::types::Value::Null);
+    }
+    let invalid_value = |expected: &str| {
+        magnus::Error::new(
+            magnus::exception::type_error(),
+            format!("Expected {} for {} value, got {}", expected, column_type.sql_name(), value.inspect()),
+        )
+    };
+    let integer = || Integer::from_value(value).ok_or_else(|| invalid_value("Integer"));
+    let duck_value = match column_type {
+        ColumnType::Boolean => {
+            if value.is_kind_of(magnus::class::true_class()) {
+                duckdb::types::Value::Boolean(true)
+            } else if value.is_kind_of(magnus::class::false_class()) {
+                duckdb::types::Value::Boolean(false)
+            } else {
+                return Err(invalid_value("true or false"));
+            }
+        }
+        ColumnType::TinyInt => duckdb::types::Value::TinyInt(integer()?.to_i8()?),
+        ColumnType::SmallInt => duckdb::types::Value::SmallInt(integer()?.to_i16()?),
+        ColumnType::Integer => duckdb::types::Value::Int(integer()?.to_i32()?),
+        ColumnType::BigInt => duckdb::types::Value::BigInt(integer()?.to_i64()?),
+        ColumnType::UTinyInt => duckdb::types::Value::UTinyInt(integer()?.to_u8()?),
+        ColumnType::USmallInt => duckdb::types::Value::USmallInt(integer()?.to_u16()?),
+        ColumnType::UInteger => duckdb::types::Value::UInt(integer()?.to_u32()?),
+        ColumnType::UBigInt => duckdb::types::Value::UBigInt(integer()?.to_u64()?),
+        ColumnType::Float => duckdb::types::Value::Float(f64::try_convert(value)? as f32),
+        ColumnType::Double => duckdb::types::Value::Double(f64::try_convert(value)?),
+        ColumnType::Varchar => match Symbol::from_value(value) {
+            Some(symbol) => duckdb::types::Value::Text(symbol.name()?.into_owned()),
+            None => duckdb::types::Value::Text(
+                RString::from_value(value).ok_or_else(|| invalid_value("String"))?.to_string()?,
+            ),
+        },
+        ColumnType::Blob => {
+            let bytes = RString::from_value(value).ok_or_else(|| invalid_value("String"))?;
+            // copied right away, before any ruby code can run and change the string
+            duckdb::types::Value::Blob(unsafe { bytes.as_slice() }.to_vec())
+        }
+        ColumnType::Date => {
+            if !value.respond_to("jd", false)? {
+                return Err(invalid_value("Date"));
+            }
+            let julian_day: i64 = value.funcall("jd", ())?;
+            let days = i32::try_from(julian_day - EPOCH_JULIAN_DAY).map_err(|_| invalid_value("Date in DuckDB range"))?;
+            duckdb::types::Value::Date32(days)
+        }
+        ColumnType::Timestamp => {
+            if !value.respond_to("to_time", false)? {
+                return Err(invalid_value("Time"));
+            }
+            let time: magnus::Value = value.funcall("to_time", ())?;
+            let seconds: i64 = time.funcall("to_i", ())?;
+            let micros: i64 = time.funcall("usec", ())?;
+            duckdb::types::Value::Timestamp(TimeUnit::Microsecond, seconds * MICROS_PER_SECOND + micros)
+        }
+    };
+    Ok(duck_value)
+}
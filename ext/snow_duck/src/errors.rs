@@ -0,0 +1,55 @@
+use magnus::{define_module, exception, value::Lazy, ExceptionClass, Module, RModule};
+
+static SNOW_DUCK_MODULE: Lazy<RModule> = Lazy::new(|ruby| ruby.class_object().const_get("SnowDuck").unwrap());
+static INTERRUPT_ERROR: Lazy<ExceptionClass> =
+    Lazy::new(|ruby| ruby.get_inner(&SNOW_DUCK_MODULE).const_get("InterruptError").unwrap());
+static TIMEOUT_ERROR: Lazy<ExceptionClass> =
+    Lazy::new(|ruby| ruby.get_inner(&SNOW_DUCK_MODULE).const_get("TimeoutError").unwrap());
+static CONCURRENCY_ERROR: Lazy<ExceptionClass> =
+    Lazy::new(|ruby| ruby.get_inner(&SNOW_DUCK_MODULE).const_get("ConcurrencyError").unwrap());
+static DUPLICATE_COLUMN_ERROR: Lazy<ExceptionClass> =
+    Lazy::new(|ruby| ruby.get_inner(&SNOW_DUCK_MODULE).const_get("DuplicateColumnError").unwrap());
+static SCHEMA_MISMATCH_ERROR: Lazy<ExceptionClass> =
+    Lazy::new(|ruby| ruby.get_inner(&SNOW_DUCK_MODULE).const_get("SchemaMismatchError").unwrap());
+
+// SnowDuck::Error
+//   SnowDuck::InterruptError - running query was interrupted (ruby interrupt or DuckDatabase#interrupt)
+//     SnowDuck::TimeoutError - running query took longer than `timeout:` allowed
+//   SnowDuck::ConcurrencyError - database was re-entered while it is being used by the same thread
+//   SnowDuck::DuplicateColumnError - pluck_to_hash result has several columns with the same name
+//   SnowDuck::SchemaMismatchError - columns of a loaded file do not match the ones it is loaded as
+pub(crate) fn define_errors() -> Result<(), magnus::Error> {
+    let module = define_module("SnowDuck")?;
+    let base_error = module.define_error("Error", exception::standard_error())?;
+    let interrupt_error = module.define_error("InterruptError", base_error)?;
+    module.define_error("TimeoutError", interrupt_error)?;
+    module.define_error("ConcurrencyError", base_error)?;
+    module.define_error("DuplicateColumnError", base_error)?;
+    module.define_error("SchemaMismatchError", base_error)?;
+    Ok(())
+}
+
+pub(crate) fn interrupt_error(message: String) -> magnus::Error {
+    magnus::Error::new(ruby_error(&INTERRUPT_ERROR), message)
+}
+
+pub(crate) fn timeout_error(message: String) -> magnus::Error {
+    magnus::Error::new(ruby_error(&TIMEOUT_ERROR), message)
+}
+
+pub(crate) fn concurrency_error(message: String) -> magnus::Error {
+    magnus::Error::new(ruby_error(&CONCURRENCY_ERROR), message)
+}
+
+pub(crate) fn duplicate_column_error(message: String) -> magnus::Error {
+    magnus::Error::new(ruby_error(&DUPLICATE_COLUMN_ERROR), message)
+}
+
+pub(crate) fn schema_mismatch_error(message: String) -> magnus::Error {
+    magnus::Error::new(ruby_error(&SCHEMA_MISMATCH_ERROR), message)
+}
+
+fn ruby_error(error_class: &Lazy<ExceptionClass>) -> ExceptionClass {
+    let ruby = magnus::Ruby::get().expect("Ruby not initialized!");
+    ruby.get_inner(error_class)
+}
@@ -0,0 +1,157 @@
+use magnus::{prelude::*, RArray, Symbol, Value};
+
+use crate::{
+    conversions::{self, to_argument_error, to_sql_identifier, to_sql_string_literal},
+    options::Keywords,
+};
+
+// File formats of export, each one with COPY options of its own
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum ExportFormat {
+    #[default]
+    Parquet,
+    Csv,
+    // newline delimited JSON, DuckDB's default for COPY ... (FORMAT JSON)
+    Json,
+}
+
+impl ExportFormat {
+    fn from_ruby(value: Symbol) -> Result<Self, magnus::Error> {
+        match value.name()?.as_ref() {
+            "parquet" => Ok(Self::Parquet),
+            "csv" => Ok(Self::Csv),
+            "json" => Ok(Self::Json),
+            name => Err(to_argument_error(format!("format must be :parquet, :csv or :json, got :{}", name))),
+        }
+    }
+
+    fn sql_name(self) -> &'static str {
+        match self {
+            Self::Parquet => "PARQUET",
+            Self::Csv => "CSV",
+            Self::Json => "JSON",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Compression {
+    None,
+    Snappy,
+    Gzip,
+    Zstd,
+    Lz4,
+    Brotli,
+}
+
+impl Compression {
+    fn from_ruby(value: Symbol) -> Result<Self, magnus::Error> {
+        match value.name()?.as_ref() {
+            "none" => Ok(Self::None),
+            "snappy" => Ok(Self::Snappy),
+            "gzip" => Ok(Self::Gzip),
+            "zstd" => Ok(Self::Zstd),
+            "lz4" => Ok(Self::Lz4),
+            "brotli" => Ok(Self::Brotli),
+            name => Err(to_argument_error(format!(
+                "compression must be :none, :snappy, :gzip, :zstd, :lz4 or :brotli, got :{}",
+                name
+            ))),
+        }
+    }
+
+    // COPY's COMPRESSION value, CSV and JSON files can only be compressed as a whole (gzip or zstd)
+    fn sql_name(self, format: ExportFormat) -> Result<&'static str, magnus::Error> {
+        match (format, self) {
+            (ExportFormat::Parquet, Self::None) => Ok("uncompressed"),
+            (_, Self::None) => Ok("none"),
+            (_, Self::Gzip) => Ok("gzip"),
+            (_, Self::Zstd) => Ok("zstd"),
+            (ExportFormat::Parquet, Self::Snappy) => Ok("snappy"),
+            (ExportFormat::Parquet, Self::Lz4) => Ok("lz4"),
+            (ExportFormat::Parquet, Self::Brotli) => Ok("brotli"),
+            (format, compression) => Err(to_argument_error(format!(
+                "{} files can not be compressed with {:?}, only with gzip or zstd",
+                format.sql_name(),
+                compression
+            ))),
+        }
+    }
+}
+
+// export(sql, path, format: :parquet, compression: nil, partition_by: nil, row_group_size: nil)
+pub(crate) struct ExportOptions {
+    format: ExportFormat,
+    // DuckDB's default of the format when not given
+    compression: Option<Compression>,
+    // Hive partitioned directory (`path/species=canine/...`) instead of a single file
+    partition_by: Vec<String>,
+    // rows per Parquet row group
+    row_group_size: Option<u64>,
+}
+
+impl ExportOptions {
+    pub(crate) fn from_keywords(keywords: &Keywords) -> Result<Self, magnus::Error> {
+        let format = keywords
+            .take::<Symbol>("format")?
+            .map(ExportFormat::from_ruby)
+            .transpose()?
+            .unwrap_or_default();
+        let compression = keywords.take::<Symbol>("compression")?.map(Compression::from_ruby).transpose()?;
+        let partition_by = match keywords.take::<RArray>("partition_by")? {
+            None => Vec::new(),
+            Some(columns) if columns.is_empty() => {
+                return Err(to_argument_error("partition_by needs at least one column".to_owned()));
+            }
+            Some(columns) => columns
+                .to_vec::<Value>()?
+                .into_iter()
+                .map(|column| conversions::identifier_from_ruby(column, "Partition column"))
+                .collect::<Result<_, _>>()?,
+        };
+        let row_group_size = keywords.take::<u64>("row_group_size")?;
+        match row_group_size {
+            Some(0) => return Err(to_argument_error("row_group_size must be positive".to_owned())),
+            Some(_) if format != ExportFormat::Parquet => {
+                return Err(to_argument_error("row_group_size is only supported by :parquet format".to_owned()));
+            }
+            _ => {}
+        }
+        // checked here, before the query runs
+        if let Some(compression) = compression {
+            compression.sql_name(format)?;
+        }
+        Ok(Self {
+            format,
+            compression,
+            partition_by,
+            row_group_size,
+        })
+    }
+
+    // COPY (query) TO 'path' (FORMAT ..., ...), every option is validated above and quoted here
+    pub(crate) fn copy_statement(&self, query: &str, path: &str) -> Result<String, magnus::Error> {
+        let query = query.trim_end().trim_end_matches(';');
+        if query.trim().is_empty() {
+            return Err(to_argument_error("Query to export is empty".to_owned()));
+        }
+        let mut copy_options = vec![format!("FORMAT {}", self.format.sql_name())];
+        if let Some(compression) = self.compression {
+            copy_options.push(format!("COMPRESSION {}", compression.sql_name(self.format)?));
+        }
+        if !self.partition_by.is_empty() {
+            let columns: Vec<String> = self.partition_by.iter().map(|column| to_sql_identifier(column)).collect();
+            copy_options.push(format!("PARTITION_BY ({})", columns.join(", ")));
+        }
+        if let Some(row_group_size) = self.row_group_size {
+            copy_options.push(format!("ROW_GROUP_SIZE {}", row_group_size));
+        }
+        // new line ends comment the query could end with, before it swallows the closing parenthesis
+        Ok(format!(
+            "COPY ({}\n) TO {} ({})",
+            query,
+            to_sql_string_literal(path),
+            copy_options.join(", ")
+        ))
+    }
+}
@@ -0,0 +1,295 @@
+use std::error::Error;
+
+use duckdb::{params, Connection};
+use magnus::{prelude::*, RHash, Symbol, Value};
+
+use crate::{
+    column_type::ColumnType,
+    conversions::{self, to_argument_error, to_sql_identifier, to_sql_string_literal},
+    options::Keywords,
+};
+
+// compressed files are read by DuckDB transparently, format is told by the extension before these
+const COMPRESSION_EXTENSIONS: [&str; 2] = [".gz", ".zst"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum FileFormat {
+    Parquet,
+    Csv,
+    Json,
+}
+
+impl FileFormat {
+    fn from_ruby(value: Symbol) -> Result<Self, magnus::Error> {
+        match value.name()?.as_ref() {
+            "parquet" => Ok(Self::Parquet),
+            "csv" => Ok(Self::Csv),
+            "json" => Ok(Self::Json),
+            name => Err(to_argument_error(format!("format must be :parquet, :csv or :json, got :{}", name))),
+        }
+    }
+
+    // visits.parquet, exports/*.csv.gz, events.ndjson
+    fn from_path(path: &str) -> Option<Self> {
+        let path = path.to_lowercase();
+        let path = COMPRESSION_EXTENSIONS
+            .iter()
+            .find_map(|extension| path.strip_suffix(extension))
+            .unwrap_or(&path);
+        match path.rsplit_once('.')?.1 {
+            "parquet" => Some(Self::Parquet),
+            "csv" | "tsv" => Some(Self::Csv),
+            "json" | "ndjson" | "jsonl" => Some(Self::Json),
+            _ => None,
+        }
+    }
+
+    fn scan_function(self) -> &'static str {
+        match self {
+            Self::Parquet => "read_parquet",
+            Self::Csv => "read_csv",
+            Self::Json => "read_json",
+        }
+    }
+}
+
+// csv_options: { col_sep: ';', quote_char: '"', escape_char: '\\', headers: true, null_string: 'NULL', skip: 2,
+//                date_format: '%d/%m/%Y', timestamp_format: '%d/%m/%Y %H:%M' }
+// named like the options of Ruby's CSV where there is one, anything not given is detected by DuckDB
+struct CsvScanOptions {
+    parameters: Vec<String>,
+}
+
+impl CsvScanOptions {
+    fn from_ruby(hash: RHash) -> Result<Self, magnus::Error> {
+        let keywords = Keywords::new(hash)?;
+        let mut parameters = Vec::new();
+        let string_options = [
+            ("col_sep", "delim"),
+            ("quote_char", "quote"),
+            ("escape_char", "escape"),
+            ("null_string", "nullstr"),
+            ("date_format", "dateformat"),
+            ("timestamp_format", "timestampformat"),
+        ];
+        for (name, parameter) in string_options {
+            if let Some(value) = keywords.take::<String>(name)? {
+                parameters.push(format!("{} = {}", parameter, to_sql_string_literal(&value)));
+            }
+        }
+        if let Some(headers) = keywords.take::<bool>("headers")? {
+            parameters.push(format!("header = {}", headers));
+        }
+        if let Some(skip) = keywords.take::<u64>("skip")? {
+            parameters.push(format!("skip = {}", skip));
+        }
+        keywords.finish()?;
+        Ok(Self { parameters })
+    }
+}
+
+// load_file(table_name, path_or_glob, format: nil, columns: nil, csv_options: nil, hive_partitioning: nil,
+//           union_by_name: nil)
+pub(crate) struct LoadFileOptions {
+    // told by the path's extension when not given
+    format: Option<FileFormat>,
+    // columns to load, in this order, cast to these types. Every column of the file is loaded as detected
+    // when not given.
+    columns: Vec<(String, ColumnType)>,
+    csv: Option<CsvScanOptions>,
+    hive_partitioning: Option<bool>,
+    union_by_name: Option<bool>,
+}
+
+impl LoadFileOptions {
+    pub(crate) fn from_keywords(keywords: &Keywords) -> Result<Self, magnus::Error> {
+        let format = keywords.take::<Symbol>("format")?.map(FileFormat::from_ruby).transpose()?;
+        let columns = match keywords.take::<RHash>("columns")? {
+            None => Vec::new(),
+            Some(columns) if columns.is_empty() => {
+                return Err(to_argument_error("columns needs at least one column".to_owned()));
+            }
+            Some(columns) => {
+                let mut column_types = Vec::with_capacity(columns.len());
+                columns.foreach(|name: Value, column_type: Value| {
+                    column_types.push((
+                        conversions::identifier_from_ruby(name, "Column name")?,
+                        ColumnType::from_ruby(column_type)?,
+                    ));
+                    Ok(magnus::r_hash::ForEach::Continue)
+                })?;
+                column_types
+            }
+        };
+        let csv = keywords.take::<RHash>("csv_options")?.map(CsvScanOptions::from_ruby).transpose()?;
+        Ok(Self {
+            format,
+            columns,
+            csv,
+            hive_partitioning: keywords.take::<bool>("hive_partitioning")?,
+            union_by_name: keywords.take::<bool>("union_by_name")?,
+        })
+    }
+
+    pub(crate) fn columns(&self) -> &[(String, ColumnType)] {
+        &self.columns
+    }
+
+    // read_xxx('path', ...) table function call, path and every option value are quoted
+    pub(crate) fn scan(&self, path: &str) -> Result<String, magnus::Error> {
+        let format = match self.format.or_else(|| FileFormat::from_path(path)) {
+            Some(format) => format,
+            None => {
+                return Err(to_argument_error(format!(
+                    "Can not tell format of {:?} from its extension, pass format: :parquet, :csv or :json",
+                    path
+                )))
+            }
+        };
+        let mut parameters = vec![to_sql_string_literal(path)];
+        if let Some(csv) = &self.csv {
+            if format != FileFormat::Csv {
+                return Err(to_argument_error("csv_options are only supported by :csv format".to_owned()));
+            }
+            parameters.extend(csv.parameters.iter().cloned());
+        }
+        if let Some(hive_partitioning) = self.hive_partitioning {
+            parameters.push(format!("hive_partitioning = {}", hive_partitioning));
+        }
+        if let Some(union_by_name) = self.union_by_name {
+            parameters.push(format!("union_by_name = {}", union_by_name));
+        }
+        Ok(format!("{}({})", format.scan_function(), parameters.join(", ")))
+    }
+}
+
+// Failed load, schema mismatches become SnowDuck::SchemaMismatchError, everything else a query error
+pub(crate) enum LoadError {
+    SchemaMismatch(String),
+    Query(Box<dyn Error>),
+}
+
+impl<E: Error + 'static> From<E> for LoadError {
+    fn from(error: E) -> Self {
+        Self::Query(Box::new(error))
+    }
+}
+
+// Runs without GVL. Creates the table (from scanned columns) unless it exists, and inserts rows of the scan
+// into it by column name. Returns number of loaded rows. Outside of a transaction the load runs in one,
+// so failed load leaves nothing behind.
+pub(crate) fn load(
+    connection: &Connection,
+    table_name: &str,
+    scan: &str,
+    columns: &[(String, ColumnType)],
+) -> Result<usize, LoadError> {
+    if !connection.is_autocommit() {
+        return load_scan(connection, table_name, scan, columns);
+    }
+    connection.execute_batch("BEGIN TRANSACTION")?;
+    match load_scan(connection, table_name, scan, columns) {
+        Ok(rows) => {
+            connection.execute_batch("COMMIT")?;
+            Ok(rows)
+        }
+        Err(error) => {
+            // error of the load is the interesting one
+            let _ = connection.execute_batch("ROLLBACK");
+            Err(error)
+        }
+    }
+}
+
+fn load_scan(
+    connection: &Connection,
+    table_name: &str,
+    scan: &str,
+    columns: &[(String, ColumnType)],
+) -> Result<usize, LoadError> {
+    // the only time the scan is bound before loading it, everything else is derived from its columns
+    let file_columns = describe(connection, &format!("SELECT * FROM {}", scan))?;
+    let loaded_columns: Vec<(String, String)> = if columns.is_empty() {
+        file_columns
+    } else {
+        let missing: Vec<&str> = columns
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .filter(|name| !file_columns.iter().any(|(file_column, _)| file_column == name))
+            .collect();
+        if !missing.is_empty() {
+            let file_column_names: Vec<&String> = file_columns.iter().map(|(name, _)| name).collect();
+            return Err(LoadError::SchemaMismatch(format!(
+                "File has no column(s) {:?}, its columns are {:?}",
+                missing, file_column_names
+            )));
+        }
+        columns
+            .iter()
+            .map(|(name, column_type)| (name.clone(), column_type.sql_name().to_owned()))
+            .collect()
+    };
+    let select = if columns.is_empty() {
+        format!("SELECT * FROM {}", scan)
+    } else {
+        let casts: Vec<String> = loaded_columns
+            .iter()
+            .map(|(name, column_type)| {
+                let column = to_sql_identifier(name);
+                format!("CAST({} AS {}) AS {}", column, column_type, column)
+            })
+            .collect();
+        format!("SELECT {} FROM {}", casts.join(", "), scan)
+    };
+
+    let table = to_sql_identifier(table_name);
+    let definitions: Vec<String> = loaded_columns
+        .iter()
+        .map(|(name, column_type)| format!("{} {}", to_sql_identifier(name), column_type))
+        .collect();
+    connection.execute(&format!("CREATE TABLE IF NOT EXISTS {} ({})", table, definitions.join(", ")), params![])?;
+    // table could have been there already, with columns of its own
+    let table_columns: Vec<String> = describe(connection, &format!("SELECT * FROM {}", table))?
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect();
+    let missing: Vec<&String> = loaded_columns
+        .iter()
+        .map(|(name, _)| name)
+        .filter(|column| !table_columns.contains(column))
+        .collect();
+    if !missing.is_empty() {
+        return Err(LoadError::SchemaMismatch(format!(
+            "Table {} has no column(s) {:?}, its columns are {:?}",
+            table_name, missing, table_columns
+        )));
+    }
+
+    connection
+        .execute(&format!("INSERT INTO {} BY NAME {}", table, select), params![])
+        .map_err(|error| {
+            // value that can not be cast to the column's type, given with columns: or the existing table's
+            if is_conversion_error(&error) {
+                LoadError::SchemaMismatch(error.to_string())
+            } else {
+                LoadError::from(error)
+            }
+        })
+}
+
+// duckdb-rs does not expose DuckDB's error type (duckdb_result_error_type), every failed statement is
+// ErrorCode::Unknown. Message starts with the type's name instead, e.g. "Conversion Error: Could not convert
+// string 'abc' to INT32", so that is what we match until the type is available.
+fn is_conversion_error(error: &duckdb::Error) -> bool {
+    match error {
+        duckdb::Error::DuckDBFailure(_, Some(message)) => message.starts_with("Conversion Error:"),
+        _ => false,
+    }
+}
+
+// names and (SQL) types of the query's result columns, without running it
+fn describe(connection: &Connection, query: &str) -> Result<Vec<(String, String)>, duckdb::Error> {
+    let mut describe = connection.prepare(&format!("SELECT column_name, column_type FROM (DESCRIBE {})", query))?;
+    let columns = describe.query_map(params![], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?;
+    columns.collect()
+}
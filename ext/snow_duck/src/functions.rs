@@ -0,0 +1,289 @@
+use std::{
+    collections::HashMap,
+    ffi::{c_void, CString},
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError,
+    },
+};
+
+use duckdb::{ffi, Connection};
+use magnus::{gc::Marker, prelude::*, value::Opaque, Proc, RArray, Ruby, TryConvert, Value};
+
+use crate::{
+    aggregates::AggregateFunction,
+    callbacks::CallbackQueue,
+    column_type::ColumnType,
+    conversions::{self, to_argument_error, to_standard_error},
+    options::Keywords,
+    raw::{self, ConnectionHandle, RawLogicalType},
+    vector,
+};
+
+// Ruby blocks registered as functions of one database, shared by all its connections
+// (DuckDB keeps functions in the catalog, so any connection can call them)
+#[derive(Default)]
+pub(crate) struct RubyFunctions {
+    callbacks: Arc<CallbackQueue>,
+    // blocks (and other callables) have to stay alive as long as DuckDB can call them, every connection marks them
+    callables: Mutex<Vec<Opaque<Value>>>,
+    // aggregates keep ruby states of groups being aggregated, those have to be marked as well
+    aggregates: Mutex<Vec<Arc<AggregateFunction>>>,
+    // ruby objects used while a query runs (e.g. enumerators of table functions)
+    pinned: Arc<PinnedValues>,
+}
+
+impl RubyFunctions {
+    fn callables(&self) -> MutexGuard<'_, Vec<Opaque<Value>>> {
+        self.callables.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn aggregates(&self) -> MutexGuard<'_, Vec<Arc<AggregateFunction>>> {
+        self.aggregates.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub(crate) fn callbacks(&self) -> &CallbackQueue {
+        &self.callbacks
+    }
+
+    pub(crate) fn callback_queue(&self) -> Arc<CallbackQueue> {
+        self.callbacks.clone()
+    }
+
+    pub(crate) fn pinned_values(&self) -> Arc<PinnedValues> {
+        self.pinned.clone()
+    }
+
+    // queries only need ruby thread serving callbacks once some function is registered
+    pub(crate) fn is_empty(&self) -> bool {
+        self.callables().is_empty()
+    }
+
+    pub(crate) fn mark(&self, marker: &Marker) {
+        let ruby = Ruby::get().expect("Ruby not initialized!");
+        for callable in self.callables().iter() {
+            marker.mark(ruby.get_inner(*callable));
+        }
+        for aggregate in self.aggregates().iter() {
+            aggregate.mark_states(&ruby, marker);
+        }
+        self.pinned.mark(&ruby, marker);
+    }
+
+    pub(crate) fn remember(&self, callable: Value) {
+        self.callables().push(Opaque::from(callable));
+    }
+
+    pub(crate) fn remember_aggregate(&self, aggregate: Arc<AggregateFunction>) {
+        self.aggregates().push(aggregate);
+    }
+
+    pub(crate) fn create_scalar_function(
+        &self,
+        connection: &Connection,
+        name: &str,
+        argument_types: Vec<ColumnType>,
+        return_type: ColumnType,
+        options: &ScalarOptions,
+        block: Proc,
+    ) -> Result<(), magnus::Error> {
+        let c_name = raw::to_c_string(name).map_err(to_argument_error)?;
+        let function = Arc::new(ScalarFunction {
+            block: Opaque::from(block),
+            argument_types,
+            return_type,
+            vectorized: options.vectorized,
+            callbacks: self.callbacks.clone(),
+        });
+        let registered = unsafe {
+            let mut scalar_function = ffi::duckdb_create_scalar_function();
+            ffi::duckdb_scalar_function_set_name(scalar_function, c_name.as_ptr());
+            for argument_type in &function.argument_types {
+                let logical_type = RawLogicalType::new(argument_type.duckdb_type());
+                ffi::duckdb_scalar_function_add_parameter(scalar_function, logical_type.handle());
+            }
+            let logical_type = RawLogicalType::new(return_type.duckdb_type());
+            ffi::duckdb_scalar_function_set_return_type(scalar_function, logical_type.handle());
+            if options.volatile {
+                ffi::duckdb_scalar_function_set_volatile(scalar_function);
+            }
+            ffi::duckdb_scalar_function_set_function(scalar_function, Some(invoke_scalar_function));
+            ffi::duckdb_scalar_function_set_extra_info(
+                scalar_function,
+                Box::into_raw(Box::new(function)) as *mut c_void,
+                Some(drop_extra_info::<Arc<ScalarFunction>>),
+            );
+            let state = ffi::duckdb_register_scalar_function(ConnectionHandle::of(connection).raw(), scalar_function);
+            ffi::duckdb_destroy_scalar_function(&mut scalar_function);
+            state == ffi::DuckDBSuccess
+        };
+        if !registered {
+            return Err(to_standard_error(
+                format!("Could not register function {}, does function with the same name exist already?", name).into(),
+            ));
+        }
+        self.remember(block.as_value());
+        Ok(())
+    }
+}
+
+// Ruby values DuckDB threads hold on to (by id) while a query runs, kept visible to GC until they are unpinned.
+// Pinning happens on ruby thread, unpinning can happen on any thread.
+#[derive(Default)]
+pub(crate) struct PinnedValues {
+    next_id: AtomicU64,
+    values: Mutex<HashMap<u64, Opaque<Value>>>,
+}
+
+impl PinnedValues {
+    fn values(&self) -> MutexGuard<'_, HashMap<u64, Opaque<Value>>> {
+        self.values.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub(crate) fn pin(&self, value: Value) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.values().insert(id, Opaque::from(value));
+        id
+    }
+
+    pub(crate) fn get(&self, ruby: &Ruby, id: u64) -> Option<Value> {
+        let value = self.values().get(&id).copied();
+        value.map(|value| ruby.get_inner(value))
+    }
+
+    pub(crate) fn unpin(&self, id: u64) {
+        self.values().remove(&id);
+    }
+
+    fn mark(&self, ruby: &Ruby, marker: &Marker) {
+        for value in self.values().values() {
+            marker.mark(ruby.get_inner(*value));
+        }
+    }
+}
+
+// create_function(..., vectorized: false, volatile: false)
+pub(crate) struct ScalarOptions {
+    // block is called once per chunk with one array per argument, and returns array of results
+    vectorized: bool,
+    // DuckDB will not constant fold calls, use it for blocks that are not deterministic
+    volatile: bool,
+}
+
+impl ScalarOptions {
+    pub(crate) fn from_keywords(keywords: &Keywords) -> Result<Self, magnus::Error> {
+        Ok(Self {
+            vectorized: keywords.take::<bool>("vectorized")?.unwrap_or(false),
+            volatile: keywords.take::<bool>("volatile")?.unwrap_or(false),
+        })
+    }
+}
+
+struct ScalarFunction {
+    block: Opaque<Proc>,
+    argument_types: Vec<ColumnType>,
+    return_type: ColumnType,
+    vectorized: bool,
+    callbacks: Arc<CallbackQueue>,
+}
+
+impl ScalarFunction {
+    // Runs on DuckDB thread. Rows with NULL argument result in NULL without calling the block,
+    // everything else is sent to ruby as a single callback per chunk.
+    unsafe fn invoke(self: &Arc<Self>, input: ffi::duckdb_data_chunk, output: ffi::duckdb_vector) -> Result<(), String> {
+        let mut rows = Vec::new();
+        let mut arguments = Vec::new();
+        for (row, row_arguments) in vector::read_arguments(input, &self.argument_types).into_iter().enumerate() {
+            match row_arguments {
+                Some(row_arguments) => {
+                    rows.push(row);
+                    arguments.push(row_arguments);
+                }
+                None => vector::set_null(output, row),
+            }
+        }
+        if rows.is_empty() {
+            return Ok(());
+        }
+        let function = self.clone();
+        let results = self.callbacks.call_ruby(move |ruby| {
+            function
+                .call_block(ruby, arguments)
+                .map_err(|error| format!("Ruby function failed: {}", error))
+        })?;
+        for (row, result) in rows.into_iter().zip(&results) {
+            vector::write_value(output, self.return_type, row, result)?;
+        }
+        Ok(())
+    }
+
+    // Runs on ruby thread, with GVL
+    fn call_block(
+        &self,
+        ruby: &Ruby,
+        arguments: Vec<Vec<duckdb::types::Value>>,
+    ) -> Result<Vec<duckdb::types::Value>, magnus::Error> {
+        let block = ruby.get_inner(self.block);
+        if !self.vectorized {
+            return arguments
+                .into_iter()
+                .map(|row_arguments| {
+                    let row_arguments = RArray::from_iter(row_arguments.into_iter().map(conversions::duck_to_ruby));
+                    let result = call_with_arguments(block.as_value(), row_arguments)?;
+                    conversions::ruby_to_duck(result, self.return_type)
+                })
+                .collect();
+        }
+
+        let row_count = arguments.len();
+        let columns = RArray::from_iter(self.argument_types.iter().map(|_| RArray::with_capacity(row_count)));
+        for row_arguments in arguments {
+            for (column, argument) in row_arguments.into_iter().enumerate() {
+                columns.entry::<RArray>(column as isize)?.push(conversions::duck_to_ruby(argument))?;
+            }
+        }
+        let results = RArray::try_convert(call_with_arguments(block.as_value(), columns)?)?;
+        if results.len() != row_count {
+            return Err(to_argument_error(format!(
+                "Vectorized function has to return array of {} values, got {}",
+                row_count,
+                results.len()
+            )));
+        }
+        results
+            .to_vec::<Value>()?
+            .into_iter()
+            .map(|result| conversions::ruby_to_duck(result, self.return_type))
+            .collect()
+    }
+}
+
+unsafe extern "C" fn invoke_scalar_function(
+    info: ffi::duckdb_function_info,
+    input: ffi::duckdb_data_chunk,
+    output: ffi::duckdb_vector,
+) {
+    let function = &*(ffi::duckdb_scalar_function_get_extra_info(info) as *const Arc<ScalarFunction>);
+    // unwinding into DuckDB is not allowed
+    let outcome = panic::catch_unwind(AssertUnwindSafe(|| function.invoke(input, output)))
+        .unwrap_or_else(|_| Err("Ruby function panicked".to_owned()));
+    if let Err(message) = outcome {
+        ffi::duckdb_scalar_function_set_error(info, to_c_error(message).as_ptr());
+    }
+}
+
+// Calls `callable` with elements of `arguments` as separate arguments. Ruby values are kept in ruby array
+// (not in a Vec) while they are being created, so GC can see them.
+pub(crate) fn call_with_arguments(callable: Value, arguments: RArray) -> Result<Value, magnus::Error> {
+    // slice is only read while passing arguments, ruby copies them before any ruby code runs
+    callable.funcall("call", unsafe { arguments.as_slice() })
+}
+
+pub(crate) fn to_c_error(message: String) -> CString {
+    CString::new(message.replace('\0', " ")).unwrap_or_default()
+}
+
+pub(crate) unsafe extern "C" fn drop_extra_info<T>(extra_info: *mut c_void) {
+    drop(Box::from_raw(extra_info as *mut T));
+}
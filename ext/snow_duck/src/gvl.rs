@@ -0,0 +1,113 @@
+use std::{
+    ffi::c_void,
+    panic::{self, AssertUnwindSafe},
+    ptr,
+};
+
+use crate::interrupt::{InterruptReason, QueryInterrupt};
+
+struct WithoutGvlCall<F, R> {
+    func: Option<F>,
+    result: Option<std::thread::Result<R>>,
+}
+
+unsafe extern "C" fn run_without_gvl<F, R>(data: *mut c_void) -> *mut c_void
+where
+    F: FnOnce() -> R,
+{
+    let call = &mut *(data as *mut WithoutGvlCall<F, R>);
+    if let Some(func) = call.func.take() {
+        // unwinding over C frames is not allowed, panic is resumed once we are back in rust land
+        call.result = Some(panic::catch_unwind(AssertUnwindSafe(func)));
+    }
+    ptr::null_mut()
+}
+
+// Runs `func` with Ruby's GVL released, so other Ruby threads can run while DuckDB works.
+// `func` must not touch any Ruby object (that includes creating magnus::Error), everything it needs
+// from ruby land has to be converted to rust values before calling this.
+//
+// If ruby thread has pending interrupt, `func` is not called at all and that interrupt is raised instead.
+pub(crate) fn without_gvl<F, R>(func: F) -> Result<R, magnus::Error>
+where
+    F: FnOnce() -> R,
+{
+    call_without_gvl(func, None, ptr::null_mut())
+}
+
+// Same as `without_gvl`, but when ruby wants to interrupt this thread (Ctrl-C, Thread#raise, Timeout.timeout)
+// query running on the connection is interrupted, so `func` returns early with DuckDB error
+pub(crate) fn without_gvl_interruptible<F, R>(func: F, interrupt: &QueryInterrupt) -> Result<R, magnus::Error>
+where
+    F: FnOnce() -> R,
+{
+    call_without_gvl(
+        func,
+        Some(interrupt_query),
+        interrupt as *const QueryInterrupt as *mut c_void,
+    )
+}
+
+unsafe extern "C" fn interrupt_query(data: *mut c_void) {
+    let interrupt = &*(data as *const QueryInterrupt);
+    interrupt.interrupt(InterruptReason::Ruby);
+}
+
+// Same as `without_gvl`, but when ruby wants to interrupt this thread `unblock` is called (from another thread),
+// it has to make `func` return early. Caller then raises the interrupt with `check_interrupts`.
+pub(crate) fn without_gvl_unblocking<F, R, U>(func: F, unblock: &U) -> Result<R, magnus::Error>
+where
+    F: FnOnce() -> R,
+    U: Fn() + Sync,
+{
+    call_without_gvl(func, Some(call_unblock::<U>), unblock as *const U as *mut c_void)
+}
+
+unsafe extern "C" fn call_unblock<U: Fn()>(data: *mut c_void) {
+    let unblock = &*(data as *const U);
+    unblock();
+}
+
+fn call_without_gvl<F, R>(
+    func: F,
+    unblock_function: Option<unsafe extern "C" fn(*mut c_void)>,
+    unblock_data: *mut c_void,
+) -> Result<R, magnus::Error>
+where
+    F: FnOnce() -> R,
+{
+    let mut call = WithoutGvlCall {
+        func: Some(func),
+        result: None,
+    };
+    unsafe {
+        // *2 variant does not check interrupts itself (that can longjmp over our frames), it just bails out
+        rb_sys::rb_thread_call_without_gvl2(
+            Some(run_without_gvl::<F, R>),
+            &mut call as *mut WithoutGvlCall<F, R> as *mut c_void,
+            unblock_function,
+            unblock_data,
+        );
+    }
+    match call.result {
+        Some(Ok(result)) => Ok(result),
+        Some(Err(panic_payload)) => panic::resume_unwind(panic_payload),
+        None => Err(pending_interrupt()),
+    }
+}
+
+// Raises interrupt pending for this ruby thread (Thread#raise, Ctrl-C, Timeout.timeout), if there is one
+pub(crate) fn check_interrupts() -> Result<(), magnus::Error> {
+    magnus::rb_sys::protect(|| {
+        unsafe { rb_sys::rb_thread_check_ints() };
+        rb_sys::Qnil
+    })
+    .map(|_| ())
+}
+
+fn pending_interrupt() -> magnus::Error {
+    match check_interrupts() {
+        Err(error) => error,
+        Ok(()) => magnus::Error::new(magnus::exception::interrupt(), "Interrupted before query could run"),
+    }
+}
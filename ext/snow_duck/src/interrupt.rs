@@ -0,0 +1,139 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU8, Ordering},
+        mpsc, Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use duckdb::InterruptHandle;
+
+use crate::{conversions, errors, gvl};
+
+const NOT_INTERRUPTED: u8 = 0;
+const INTERRUPTED_BY_RUBY: u8 = 1;
+const INTERRUPTED_BY_REQUEST: u8 = 2;
+const INTERRUPTED_BY_TIMEOUT: u8 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum InterruptReason {
+    // ruby wanted to interrupt the thread running the query (Ctrl-C, Thread#raise, Timeout.timeout...)
+    Ruby,
+    // DuckDatabase#interrupt was called
+    Request,
+    Timeout,
+}
+
+// Interrupts query running on a connection, remembering why it was interrupted so that
+// DuckDB's generic "Interrupted!" error can be turned into proper ruby exception.
+// Used from other threads (ruby's unblocking function, timeout watchdog), so it is kept outside of database lock.
+pub(crate) struct QueryInterrupt {
+    handle: Arc<InterruptHandle>,
+    reason: AtomicU8,
+    // DuckDB remembers interrupt of idle connection until next query, so we only forward it while query runs
+    running: AtomicBool,
+}
+
+impl QueryInterrupt {
+    pub(crate) fn new(handle: Arc<InterruptHandle>) -> Self {
+        Self {
+            handle,
+            reason: AtomicU8::new(NOT_INTERRUPTED),
+            running: AtomicBool::new(false),
+        }
+    }
+
+    // Has to be called while holding database lock, returned guard has to be kept alive until query is done
+    pub(crate) fn start(self: &Arc<Self>, timeout: Option<Duration>) -> RunningQuery {
+        self.reason.store(NOT_INTERRUPTED, Ordering::SeqCst);
+        self.running.store(true, Ordering::SeqCst);
+        RunningQuery {
+            interrupt: self.clone(),
+            deadline: timeout.map(|timeout| Deadline::start(self.clone(), timeout)),
+        }
+    }
+
+    pub(crate) fn interrupt(&self, reason: InterruptReason) {
+        if !self.running.load(Ordering::SeqCst) {
+            return;
+        }
+        let reason_code = match reason {
+            InterruptReason::Ruby => INTERRUPTED_BY_RUBY,
+            InterruptReason::Request => INTERRUPTED_BY_REQUEST,
+            InterruptReason::Timeout => INTERRUPTED_BY_TIMEOUT,
+        };
+        // first reason wins, e.g. timeout that fired will not be overwritten by ruby interrupt that followed
+        let _ = self
+            .reason
+            .compare_exchange(NOT_INTERRUPTED, reason_code, Ordering::SeqCst, Ordering::SeqCst);
+        self.handle.interrupt();
+    }
+
+    pub(crate) fn reason(&self) -> Option<InterruptReason> {
+        match self.reason.load(Ordering::SeqCst) {
+            INTERRUPTED_BY_RUBY => Some(InterruptReason::Ruby),
+            INTERRUPTED_BY_REQUEST => Some(InterruptReason::Request),
+            INTERRUPTED_BY_TIMEOUT => Some(InterruptReason::Timeout),
+            _ => None,
+        }
+    }
+
+    // Turns error of the query into ruby exception, taking into account whether we interrupted it
+    pub(crate) fn to_query_error(&self, error: Box<dyn std::error::Error>) -> magnus::Error {
+        match self.reason() {
+            Some(InterruptReason::Timeout) => errors::timeout_error(format!("Query timed out: {}", error)),
+            // exception ruby interrupted the thread with (Thread#raise, Timeout.timeout, Ctrl-C) wins
+            Some(InterruptReason::Ruby) => match gvl::check_interrupts() {
+                Err(exception) => exception,
+                Ok(()) => errors::interrupt_error(format!("Query was interrupted: {}", error)),
+            },
+            Some(_) => errors::interrupt_error(format!("Query was interrupted: {}", error)),
+            None => conversions::to_standard_error(error),
+        }
+    }
+}
+
+pub(crate) struct RunningQuery {
+    interrupt: Arc<QueryInterrupt>,
+    deadline: Option<Deadline>,
+}
+
+impl Drop for RunningQuery {
+    fn drop(&mut self) {
+        // make sure watchdog is gone before we say query is not running anymore
+        drop(self.deadline.take());
+        self.interrupt.running.store(false, Ordering::SeqCst);
+    }
+}
+
+// Watchdog thread that interrupts the query once timeout passes, dropping it cancels the watchdog
+struct Deadline {
+    cancel: Option<mpsc::Sender<()>>,
+    watchdog: Option<JoinHandle<()>>,
+}
+
+impl Deadline {
+    fn start(interrupt: Arc<QueryInterrupt>, timeout: Duration) -> Self {
+        let (cancel, cancelled) = mpsc::channel::<()>();
+        let watchdog = thread::spawn(move || {
+            if let Err(mpsc::RecvTimeoutError::Timeout) = cancelled.recv_timeout(timeout) {
+                interrupt.interrupt(InterruptReason::Timeout);
+            }
+        });
+        Self {
+            cancel: Some(cancel),
+            watchdog: Some(watchdog),
+        }
+    }
+}
+
+impl Drop for Deadline {
+    fn drop(&mut self) {
+        // dropping the sender wakes the watchdog up immediately
+        drop(self.cancel.take());
+        if let Some(watchdog) = self.watchdog.take() {
+            let _ = watchdog.join();
+        }
+    }
+}
@@ -1,27 +1,296 @@
-use crate::conversions::string_from_ruby_hash;
-use duckdb::{params, Connection, Row};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Instant;
+
+use duckdb::{arrow::record_batch::RecordBatch, params, Connection, DropBehavior, Row, Rows};
 use magnus::{
-    class, define_class, function, method, prelude::*, Error, IntoValue, RArray, RHash,
-    StaticSymbol, Value,
+    class, define_class, function, gc::Marker, method, prelude::*, scan_args, DataTypeFunctions, Error, IntoValue,
+    Proc, RArray, RClass, RHash, RString, Ruby, TypedData, Value,
 };
+
+use crate::aggregates::AggregateCallables;
+use crate::arrow_ipc::IpcStream;
+use crate::callbacks::HelperThread;
+use crate::conversions::{optional_string_from_ruby_hash, string_from_ruby_hash, to_sql_string_literal};
+use crate::export::ExportOptions;
+use crate::file_load::{LoadError, LoadFileOptions};
+use crate::functions::{RubyFunctions, ScalarOptions};
+use crate::interrupt::{InterruptReason, QueryInterrupt, RunningQuery};
+use crate::lock::{ConnectionGuard, ConnectionLock};
+use crate::options::{HashFlavour, HashKeys, KeyMode, Keywords, QueryOptions, ResultOptions};
+use crate::packed::PackedColumns;
+use crate::progress::ProgressOptions;
+use crate::raw::ConnectionHandle;
+use crate::relations::{self, Relation, Relations};
+use crate::result_columns::ResultColumns;
+use crate::rows::{RowBuilder, RowHashes, RowObjects};
+use crate::serialize::{CsvOptions, CsvWriter, JsonOptions, JsonWriter};
+
+mod aggregates;
+mod arrow_ipc;
+mod callbacks;
+mod column_type;
+mod columnar;
+mod config;
 mod conversions;
+mod errors;
+mod export;
+mod file_load;
+mod functions;
+mod gvl;
+mod interrupt;
+mod lock;
+mod options;
+mod packed;
+mod progress;
+mod raw;
+mod relations;
+mod result_columns;
+mod rows;
+mod serialize;
+mod table_functions;
+mod vector;
 
 pub struct DuckDatabase {
     database: Connection,
+    // keys of relations attached by this connection, by their lowercased names (DuckDB names are case insensitive)
+    attached_relations: HashMap<String, String>,
+}
+
+const S3_SECRET_NAME: &str = "aws_bucket_secrets";
+
+struct S3Credentials {
+    region: String,
+    access_key_id: String,
+    secret_access_key: String,
+    // present when using temporary (STS) credentials
+    session_token: Option<String>,
+}
+
+impl S3Credentials {
+    fn from_ruby_hash(options: magnus::RHash) -> Result<Self, magnus::Error> {
+        Ok(Self {
+            region: string_from_ruby_hash(options, "s3_region")?,
+            access_key_id: string_from_ruby_hash(options, "s3_access_key_id")?,
+            secret_access_key: string_from_ruby_hash(options, "s3_secret_access_key")?,
+            session_token: optional_string_from_ruby_hash(options, "s3_session_token")?,
+        })
+    }
+
+    // CREATE OR REPLACE swaps the secret in a single statement, so there is no window without a secret
+    fn create_secret_statement(&self) -> String {
+        let session_token = self
+            .session_token
+            .as_ref()
+            .map(|token| format!(", SESSION_TOKEN {}", to_sql_string_literal(token)))
+            .unwrap_or_default();
+        format!(
+            "CREATE OR REPLACE SECRET {} (TYPE S3, KEY_ID {}, SECRET {}, REGION {}{})",
+            S3_SECRET_NAME,
+            to_sql_string_literal(&self.access_key_id),
+            to_sql_string_literal(&self.secret_access_key),
+            to_sql_string_literal(&self.region),
+            session_token
+        )
+    }
+}
+
+// Number of rows fetched from DuckDB (without holding GVL) before converting them to ruby objects
+const FETCH_CHUNK_SIZE: usize = 2048;
+
+type FetchResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+// Fields are dropped in declaration order, connections have to be closed before the database
+#[derive(TypedData)]
+#[magnus(class = "DuckDatabase", free_immediately, mark)]
+pub struct MutDatabase {
+    database: ConnectionLock<DuckDatabase>,
+    interrupt: Arc<QueryInterrupt>,
+    // runs queries that can call ruby functions, while ruby thread serves them
+    helper: HelperThread,
+    functions: Arc<RubyFunctions>,
+    relations: Arc<Relations>,
+    // connection that opened the database, it closes the database when dropped (after all connections cloned from
+    // it). Never runs queries, so new connections can be cloned from it while this one is busy.
+    origin: Arc<Mutex<Connection>>,
 }
 
-#[magnus::wrap(class = "DuckDatabase", free_immediately)]
-pub struct MutDatabase(std::cell::RefCell<DuckDatabase>);
+impl DataTypeFunctions for MutDatabase {
+    fn mark(&self, marker: &Marker) {
+        self.functions.mark(marker);
+    }
+}
 
 impl MutDatabase {
-    fn row_to_ruby_array(&self, row: &Row<'_>) -> Result<Value, magnus::Error> {
-        let column_names = row.as_ref().column_names();
-        if column_names.len() > 1 {
-            let row_result = RArray::with_capacity(column_names.len());
-            for column_name in column_names {
-                let current_column_value = row
-                    .get::<&str, duckdb::types::Value>(&column_name)
-                    .map_err(|err| conversions::to_standard_column_error(&err, &column_name))?;
+    fn lock(&self) -> Result<ConnectionGuard<'_, DuckDatabase>, magnus::Error> {
+        self.database.lock()
+    }
+
+    // Locks the connection for a query and starts watching for its interrupts. Time spent waiting for the lock
+    // counts towards `timeout:`.
+    fn start_query(&self, options: &QueryOptions) -> Result<(ConnectionGuard<'_, DuckDatabase>, RunningQuery), magnus::Error> {
+        let started_at = Instant::now();
+        let database = self.database.lock_within(options.timeout)?;
+        let running = self.interrupt.start(options.after_waiting(started_at).timeout);
+        Ok((database, running))
+    }
+
+    // Runs part of the query without GVL, ruby interrupts (and timeout) interrupt it
+    fn without_gvl<F, R>(&self, func: F) -> Result<R, magnus::Error>
+    where
+        F: FnOnce() -> R,
+    {
+        if self.functions.is_empty() {
+            gvl::without_gvl_interruptible(func, &self.interrupt)
+        } else {
+            // query can call ruby functions, which needs this thread serving them while DuckDB works
+            callbacks::without_gvl_serving(self.functions.callbacks(), &self.helper, &self.interrupt, None, func)
+        }
+    }
+
+    fn to_query_error(&self, error: Box<dyn std::error::Error>) -> magnus::Error {
+        self.interrupt.to_query_error(error)
+    }
+
+    // cells are read by index, names are only needed for error messages
+    fn read_row(row: &Row<'_>, column_names: &[String]) -> FetchResult<Vec<duckdb::types::Value>> {
+        let mut values = Vec::with_capacity(column_names.len());
+        for (index, column_name) in column_names.iter().enumerate() {
+            let current_column_value = row
+                .get::<usize, duckdb::types::Value>(index)
+                .map_err(|err| format!("Error converting value of column {} : {}", column_name, err))?;
+            values.push(current_column_value);
+        }
+        Ok(values)
+    }
+
+    // Runs without GVL, reads next chunk of rows; empty chunk means there are no more rows
+    fn fetch_chunk(rows: &mut Rows<'_>, column_names: &[String]) -> FetchResult<Vec<Vec<duckdb::types::Value>>> {
+        let mut chunk = Vec::with_capacity(FETCH_CHUNK_SIZE);
+        while chunk.len() < FETCH_CHUNK_SIZE {
+            match rows.next()? {
+                Some(row) => chunk.push(Self::read_row(row, column_names)?),
+                None => break,
+            }
+        }
+        Ok(chunk)
+    }
+
+    // Prepares and runs the query without GVL, then calls `convert_row` (with GVL) for every fetched row.
+    // Returns result columns, which are known even when there are no rows.
+    fn query_each_row<F>(
+        &self,
+        query: &str,
+        options: &QueryOptions,
+        hash_keys: Option<HashKeys>,
+        mut convert_row: F,
+    ) -> Result<ResultColumns, magnus::Error>
+    where
+        F: FnMut(&ResultColumns, Vec<duckdb::types::Value>) -> Result<(), magnus::Error>,
+    {
+        let (database, _running) = self.start_query(options)?;
+        let mut stmt = self
+            .without_gvl(|| database.database.prepare_cached(query))?
+            .map_err(|err| self.to_query_error(Box::new(err)))?;
+        let stmt_ref = &mut stmt;
+        let mut rows = self
+            .without_gvl(move || stmt_ref.query([]))?
+            .map_err(|err| self.to_query_error(Box::new(err)))?;
+        let columns = ResultColumns::new(rows.as_ref().map(|stmt| stmt.column_names()).unwrap_or_default(), hash_keys)?;
+        loop {
+            let rows_ref = &mut rows;
+            let column_names = columns.names();
+            let chunk = self
+                .without_gvl(move || Self::fetch_chunk(rows_ref, column_names))?
+                .map_err(|err| self.to_query_error(err))?;
+            if chunk.is_empty() {
+                return Ok(columns);
+            }
+            for row_values in chunk {
+                convert_row(&columns, row_values)?;
+            }
+        }
+    }
+
+    // Same as `query_each_row`, but calls `convert_batch` (with GVL) for every Arrow record batch of the result
+    fn query_each_batch<F>(
+        &self,
+        query: &str,
+        options: &QueryOptions,
+        hash_keys: Option<HashKeys>,
+        mut convert_batch: F,
+    ) -> Result<ResultColumns, magnus::Error>
+    where
+        F: FnMut(&ResultColumns, &RecordBatch) -> Result<(), magnus::Error>,
+    {
+        let (database, _running) = self.start_query(options)?;
+        let mut stmt = self
+            .without_gvl(|| database.database.prepare_cached(query))?
+            .map_err(|err| self.to_query_error(Box::new(err)))?;
+        let stmt_ref = &mut stmt;
+        // same as query_arrow, but statement stays at hand for column types of its result
+        self.without_gvl(move || stmt_ref.execute([]))?
+            .map_err(|err| self.to_query_error(Box::new(err)))?;
+        let arrow_schema = stmt.schema();
+        let schema = columnar::mark_decimal_columns(&stmt, arrow_schema.clone());
+        // batches get the marked schema, DECIMAL(38, 0) columns are told from HUGEINT by their fields
+        let relabel = schema != arrow_schema;
+        let column_names = schema.fields().iter().map(|field| field.name().clone()).collect();
+        let columns = ResultColumns::new(column_names, hash_keys)?.with_arrow_schema(schema.clone());
+        loop {
+            let stmt_ref = &stmt;
+            match self.without_gvl(move || stmt_ref.step())? {
+                Some(chunk) if relabel => {
+                    let batch = RecordBatch::from(chunk)
+                        .with_schema(schema.clone())
+                        .map_err(|err| self.to_query_error(Box::new(err)))?;
+                    convert_batch(&columns, &batch)?
+                }
+                Some(chunk) => convert_batch(&columns, &RecordBatch::from(chunk))?,
+                None => return Ok(columns),
+            }
+        }
+    }
+
+    // Runs statement(s) that do not return rows
+    fn run_statement<F, R>(&self, options: &QueryOptions, statement: F) -> Result<R, magnus::Error>
+    where
+        F: FnOnce(&Connection) -> duckdb::Result<R>,
+    {
+        self.run_statement_with_progress(options, None, statement)
+    }
+
+    // Same as `run_statement`, reporting progress of the statement(s) to `progress` callback when it is given.
+    // Statement runs on this connection either way, so it sees (and changes) its session state and transaction.
+    fn run_statement_with_progress<F, R>(
+        &self,
+        options: &QueryOptions,
+        progress: Option<&ProgressOptions>,
+        statement: F,
+    ) -> Result<R, magnus::Error>
+    where
+        F: FnOnce(&Connection) -> duckdb::Result<R>,
+    {
+        let (database, _running) = self.start_query(options)?;
+        let connection = &database.database;
+        let result = match progress {
+            None => self.without_gvl(|| statement(connection))?,
+            Some(progress) => progress::run_with_progress(
+                ConnectionHandle::of(connection),
+                &self.interrupt,
+                self.functions.callbacks(),
+                &self.helper,
+                progress,
+                || statement(connection),
+            )?,
+        };
+        result.map_err(|err| self.to_query_error(Box::new(err)))
+    }
+
+    fn row_to_ruby_array(&self, row_values: Vec<duckdb::types::Value>) -> Result<Value, magnus::Error> {
+        if row_values.len() > 1 {
+            let row_result = RArray::with_capacity(row_values.len());
+            for current_column_value in row_values {
                 let ruby_value = conversions::duck_to_ruby(current_column_value);
                 row_result.push(ruby_value)?;
             }
@@ -29,127 +298,825 @@ impl MutDatabase {
         }
         // we are converting single column, do not create array
         else {
-            let column_name = column_names.first().ok_or(conversions::to_standard_error(
+            let current_column_value = row_values.into_iter().next().ok_or(conversions::to_standard_error(
                 "Could not get first column".into(),
             ))?;
-            let current_column_value = row
-                .get::<&str, duckdb::types::Value>(column_name)
-                .map_err(|err| conversions::to_standard_column_error(&err, column_name))?;
             let ruby_value = conversions::duck_to_ruby(current_column_value);
             Ok(ruby_value)
         }
     }
 
-    fn row_to_ruby_hash(&self, row: &Row<'_>, with_indifferent_access_available: bool) -> Result<RHash, magnus::Error> {
-        let mut ruby_hash = RHash::new();
-        let column_names = row.as_ref().column_names();
-        for column_name in column_names {
-            let current_column_value = row
-                .get::<&str, duckdb::types::Value>(&column_name)
-                .map_err(|err| conversions::to_standard_column_error(&err, &column_name))?;
-            ruby_hash.aset(
-                StaticSymbol::new(column_name),
-                conversions::duck_to_ruby(current_column_value),
-            )?
-        }
+    pub fn duck_pluck_to_hash(&self, query: String) -> Result<RArray, magnus::Error> {
+        let ruby = Ruby::get().expect("Ruby not initialized!");
+        let result_options = ResultOptions {
+            hash: HashFlavour::implicit(ruby)?,
+            ..ResultOptions::default()
+        };
+        self.pluck_to_hash_with(&query, &QueryOptions::default(), &result_options)
+    }
 
-        if with_indifferent_access_available {
-            ruby_hash = ruby_hash.funcall_public("with_indifferent_access", ())?;
-        }
+    // for comparing both conversions in benchmarks
+    pub fn duck_pluck_to_hash_by_rows(&self, query: String) -> Result<RArray, magnus::Error> {
+        let ruby = Ruby::get().expect("Ruby not initialized!");
+        let result_options = ResultOptions {
+            columnar: false,
+            hash: HashFlavour::implicit(ruby)?,
+            ..ResultOptions::default()
+        };
+        self.pluck_to_hash_with(&query, &QueryOptions::default(), &result_options)
+    }
 
-        Ok(ruby_hash)
+    // for comparing hash flavours in benchmarks, `hash` is a name `hash:` keyword takes ("plain", "indifferent"...)
+    pub fn duck_pluck_to_hash_as(&self, query: String, hash: &str, columnar: bool) -> Result<RArray, magnus::Error> {
+        let result_options = ResultOptions {
+            columnar,
+            hash: HashFlavour::from_name(hash)?,
+            ..ResultOptions::default()
+        };
+        self.pluck_to_hash_with(&query, &QueryOptions::default(), &result_options)
     }
 
-    pub fn duck_pluck_to_hash(&self, query: String) -> Result<RArray, magnus::Error> {
-        let conn = &self.0.borrow().database;
-        let mut stmt: duckdb::CachedStatement<'_> = conn
-            .prepare_cached(&query)
-            .map_err(|err| conversions::to_standard_error(Box::new(err)))?;
-        let mut rows = stmt
-            .query([])
-            .map_err(|err| conversions::to_standard_error(Box::new(err)))?;
+    fn pluck_to_hash_with(&self, query: &str, options: &QueryOptions, result_options: &ResultOptions) -> Result<RArray, magnus::Error> {
+        let ruby = Ruby::get().expect("Ruby not initialized!");
+        let hashes = RowHashes::new(ruby, result_options.hash)?;
+        self.pluck_rows_with(query, options, result_options, |_| Ok(hashes))
+    }
+
+    // Every row of the result built by one RowBuilder, created by `make_builder` once result columns are known.
+    // Builder is kept on the stack, where GC sees classes it references.
+    fn pluck_rows_with<B, F>(
+        &self,
+        query: &str,
+        options: &QueryOptions,
+        result_options: &ResultOptions,
+        make_builder: F,
+    ) -> Result<RArray, magnus::Error>
+    where
+        B: RowBuilder,
+        F: FnOnce(&ResultColumns) -> Result<B, magnus::Error>,
+    {
         let result = RArray::new();
+        let ruby = Ruby::get().expect("Ruby not initialized!");
+        let mut make_builder = Some(make_builder);
+        let mut builder: Option<B> = None;
 
-        let with_indifferent_access_available = RHash::new()
-            .respond_to("with_indifferent_access", false)
-            .map_err(|err| {
-                magnus::Error::new(magnus::exception::standard_error(), err.to_string())
+        if result_options.columnar {
+            self.query_each_batch(query, options, Some(result_options.hash_keys()), |columns_metadata, batch| {
+                if let Some(make_builder) = make_builder.take() {
+                    builder = Some(make_builder(columns_metadata)?);
+                }
+                let builder = builder.as_ref().expect("builder is created for the first batch");
+                // columns stay referenced from `columns` while rows are built, so GC does not collect them
+                let columns = columnar::batch_to_ruby_columns(ruby, batch)?;
+                let column_values = columns.to_vec::<RArray>()?;
+                for row in 0..batch.num_rows() {
+                    let ruby_row = builder.build(columns_metadata, &mut |column| column_values[column].entry(row as isize))?;
+                    result.push(ruby_row)?;
+                }
+                Ok(())
             })?;
-
-        while let Some(row) = rows
-            .next()
-            .map_err(|err| conversions::to_standard_error(Box::new(err)))?
-        {
-            let ruby_hash = self.row_to_ruby_hash(row, with_indifferent_access_available)?;
-            result.push(ruby_hash).unwrap();
+            return Ok(result);
         }
+
+        self.query_each_row(query, options, Some(result_options.hash_keys()), |columns, mut row_values| {
+            if let Some(make_builder) = make_builder.take() {
+                builder = Some(make_builder(columns)?);
+            }
+            let builder = builder.as_ref().expect("builder is created for the first row");
+            let ruby_row = builder.build(columns, &mut |column| {
+                let value = std::mem::replace(&mut row_values[column], duckdb::types::Value::Null);
+                Ok(conversions::duck_to_ruby(value))
+            })?;
+            result.push(ruby_row)
+        })?;
         Ok(result)
     }
 
+    // pluck_to_hash(sql, timeout: nil, columnar: true, keys: :symbol, hash: nil, duplicate_columns: :raise)
+    //
+    // Without `hash:` rows are HashWithIndifferentAccess when ActiveSupport is loaded, plain Hashes otherwise
+    fn ruby_pluck_to_hash(&self, args: &[Value]) -> Result<RArray, magnus::Error> {
+        let (query, keywords) = options::query_args(args)?;
+        let options = QueryOptions::from_keywords(&keywords)?;
+        let ruby = Ruby::get().expect("Ruby not initialized!");
+        let result_options = ResultOptions::from_hash_row_keywords(ruby, &keywords)?;
+        keywords.finish()?;
+        self.pluck_to_hash_with(&query, &options, &result_options)
+    }
+
+    // pluck_to(klass, sql, timeout: nil, columnar: true, duplicate_columns: :raise)
+    //
+    // Rows as instances of `klass`. Struct and Data classes get values of their members positionally, result
+    // columns have to match the members. Any other class (and keyword_init Struct) is created with
+    // `klass.new(**row)`.
+    fn ruby_pluck_to(&self, args: &[Value]) -> Result<RArray, magnus::Error> {
+        let args = scan_args::scan_args::<(RClass, String), (), (), (), RHash, ()>(args)?;
+        let (class, query) = args.required;
+        let keywords = Keywords::new(args.keywords)?;
+        let options = QueryOptions::from_keywords(&keywords)?;
+        let result_options = ResultOptions::from_row_object_keywords(&keywords)?;
+        keywords.finish()?;
+        self.pluck_rows_with(&query, &options, &result_options, |columns| RowObjects::for_class(class, columns))
+    }
+
+    // pluck_to_struct(sql, timeout: nil, columnar: true, duplicate_columns: :raise)
+    //
+    // Rows as instances of a Struct class generated for the result, its members are the column names
+    fn ruby_pluck_to_struct(&self, args: &[Value]) -> Result<RArray, magnus::Error> {
+        let (query, keywords) = options::query_args(args)?;
+        let options = QueryOptions::from_keywords(&keywords)?;
+        let result_options = ResultOptions::from_row_object_keywords(&keywords)?;
+        keywords.finish()?;
+        let ruby = Ruby::get().expect("Ruby not initialized!");
+        self.pluck_rows_with(&query, &options, &result_options, |columns| RowObjects::generated_struct(ruby, columns))
+    }
+
+    // Values of every selected column (all columns without `selected`), as ruby Arrays keyed by column name.
+    // Values are pushed column by column, no row is ever built.
+    fn pluck_columns_with(
+        &self,
+        query: &str,
+        options: &QueryOptions,
+        result_options: &ResultOptions,
+        selected: Option<&[String]>,
+    ) -> Result<RHash, magnus::Error> {
+        let ruby = Ruby::get().expect("Ruby not initialized!");
+        let hashes = RowHashes::new(ruby, result_options.hash)?;
+        // Array of every selected column, in `positions` order
+        let values = RArray::new();
+        let mut positions: Option<Vec<usize>> = None;
+
+        let columns = if result_options.columnar {
+            self.query_each_batch(query, options, Some(result_options.hash_keys()), |columns, batch| {
+                let column_positions = Self::selected_positions(&mut positions, values, columns, selected)?;
+                for (value_index, column) in column_positions.iter().enumerate() {
+                    columnar::append_column(ruby, batch, *column, values.entry(value_index as isize)?)?;
+                }
+                Ok(())
+            })?
+        } else {
+            self.query_each_row(query, options, Some(result_options.hash_keys()), |columns, mut row_values| {
+                let column_positions = Self::selected_positions(&mut positions, values, columns, selected)?;
+                for (value_index, column) in column_positions.iter().enumerate() {
+                    let value = std::mem::replace(&mut row_values[*column], duckdb::types::Value::Null);
+                    values.entry::<RArray>(value_index as isize)?.push(conversions::duck_to_ruby(value))?;
+                }
+                Ok(())
+            })?
+        };
+
+        // empty result still has its columns
+        let positions = Self::selected_positions(&mut positions, values, &columns, selected)?;
+        hashes.hash_with(|hash| {
+            for (value_index, column) in positions.iter().enumerate() {
+                hash.aset(columns.keys()[*column], values.entry::<Value>(value_index as isize)?)?;
+            }
+            Ok(())
+        })
+    }
+
+    // Positions of the selected columns, looked up once per statement (when `positions` are still unknown),
+    // together with an empty Array for values of each of them
+    fn selected_positions<'p>(
+        positions: &'p mut Option<Vec<usize>>,
+        values: RArray,
+        columns: &ResultColumns,
+        selected: Option<&[String]>,
+    ) -> Result<&'p [usize], magnus::Error> {
+        if positions.is_none() {
+            let column_positions = columns.positions(selected)?;
+            for _ in &column_positions {
+                values.push(RArray::new())?;
+            }
+            *positions = Some(column_positions);
+        }
+        Ok(positions.as_deref().expect("positions are set above"))
+    }
+
+    // pluck_columns(sql, columns: nil, timeout: nil, columnar: true, keys: :symbol, hash: :plain,
+    //               duplicate_columns: :raise)
+    //
+    // { column_name => [values...] } of the result, `columns: [:id, :species]` returns only these columns
+    fn ruby_pluck_columns(&self, args: &[Value]) -> Result<RHash, magnus::Error> {
+        let (query, keywords) = options::query_args(args)?;
+        let options = QueryOptions::from_keywords(&keywords)?;
+        let result_options = ResultOptions::from_keywords(&keywords)?;
+        let selected = options::selected_columns_from_keywords(&keywords)?;
+        keywords.finish()?;
+        self.pluck_columns_with(&query, &options, &result_options, selected.as_deref())
+    }
+
+    fn pluck_packed_with(
+        &self,
+        query: &str,
+        options: &QueryOptions,
+        result_options: &ResultOptions,
+        selected: Option<&[String]>,
+    ) -> Result<RHash, magnus::Error> {
+        let ruby = Ruby::get().expect("Ruby not initialized!");
+        // kept on the stack, where GC sees Strings values are packed into
+        let mut packed: Option<PackedColumns> = None;
+        let columns = self.query_each_batch(query, options, Some(result_options.hash_keys()), |columns, batch| {
+            if packed.is_none() {
+                packed = Some(PackedColumns::new(ruby, columns, selected)?);
+            }
+            packed.as_mut().expect("packed columns are created above").append(batch)
+        })?;
+        let packed = match packed {
+            Some(packed) => packed,
+            // empty result still has its columns
+            None => PackedColumns::new(ruby, &columns, selected)?,
+        };
+        packed.into_ruby(ruby, &columns)
+    }
+
+    // packed_columns(sql, columns: nil, timeout: nil, keys: :symbol, duplicate_columns: :raise)
+    //
+    // Fixed width numeric columns as { column_name => { type:, data:, validity:, length: } }, public
+    // DuckDatabase#pluck_packed wraps them into PackedColumn
+    fn ruby_packed_columns(&self, args: &[Value]) -> Result<RHash, magnus::Error> {
+        let (query, keywords) = options::query_args(args)?;
+        let options = QueryOptions::from_keywords(&keywords)?;
+        let result_options = ResultOptions::from_packed_keywords(&keywords)?;
+        let selected = options::selected_columns_from_keywords(&keywords)?;
+        keywords.finish()?;
+        self.pluck_packed_with(&query, &options, &result_options, selected.as_deref())
+    }
+
     pub fn duck_pluck(&self, query: String) -> Result<RArray, magnus::Error> {
-        let conn = &self.0.borrow().database;
-        let mut stmt = conn
-            .prepare_cached(&query)
-            .map_err(|err| conversions::to_standard_error(Box::new(err)))?;
-        let mut rows = stmt
-            .query([])
-            .map_err(|err| conversions::to_standard_error(Box::new(err)))?;
+        self.pluck_with(&query, &QueryOptions::default(), &ResultOptions::default())
+    }
+
+    // for comparing both conversions in benchmarks
+    pub fn duck_pluck_by_rows(&self, query: String) -> Result<RArray, magnus::Error> {
+        let result_options = ResultOptions {
+            columnar: false,
+            ..ResultOptions::default()
+        };
+        self.pluck_with(&query, &QueryOptions::default(), &result_options)
+    }
+
+    fn pluck_with(&self, query: &str, options: &QueryOptions, result_options: &ResultOptions) -> Result<RArray, magnus::Error> {
         let result = RArray::new();
-        while let Some(row) = rows
-            .next()
-            .map_err(|err| conversions::to_standard_error(Box::new(err)))?
-        {
-            let row_result = self.row_to_ruby_array(row)?;
-            result.push(row_result)?;
+        if result_options.columnar {
+            let ruby = Ruby::get().expect("Ruby not initialized!");
+            self.query_each_batch(query, options, None, |columns_metadata, batch| {
+                let columns = columnar::batch_to_ruby_columns(ruby, batch)?;
+                // single column is plucked without wrapping values into arrays
+                let rows: RArray = if columns_metadata.len() == 1 {
+                    columns.entry(0)?
+                } else {
+                    columns.funcall("transpose", ())?
+                };
+                result.concat(rows)
+            })?;
+            return Ok(result);
         }
+
+        self.query_each_row(query, options, None, |_, row_values| {
+            let row_result = self.row_to_ruby_array(row_values)?;
+            result.push(row_result)
+        })?;
         Ok(result)
     }
 
+    // pluck(sql, timeout: nil, columnar: true)
+    fn ruby_pluck(&self, args: &[Value]) -> Result<RArray, magnus::Error> {
+        let (query, keywords) = options::query_args(args)?;
+        let options = QueryOptions::from_keywords(&keywords)?;
+        let result_options = ResultOptions::from_keywords(&keywords)?;
+        keywords.finish()?;
+        self.pluck_with(&query, &options, &result_options)
+    }
+
+    // pluck_to_csv(sql, timeout: nil, col_sep: ',', row_sep: "\n", quote_char: '"', headers: true, force_quotes: false)
+    //
+    // Result as CSV String, written from record batches without creating ruby objects for the values
+    fn ruby_pluck_to_csv(&self, args: &[Value]) -> Result<RString, magnus::Error> {
+        let (query, keywords) = options::query_args(args)?;
+        let options = QueryOptions::from_keywords(&keywords)?;
+        let csv_options = CsvOptions::from_keywords(&keywords)?;
+        keywords.finish()?;
+
+        let mut writer = CsvWriter::new(&csv_options);
+        let mut header_written = !csv_options.headers();
+        let columns = self.query_each_batch(&query, &options, None, |columns, batch| {
+            if !header_written {
+                writer.write_header(columns.names());
+                header_written = true;
+            }
+            writer.write_batch(batch).map_err(conversions::to_standard_error)
+        })?;
+        if !header_written {
+            writer.write_header(columns.names());
+        }
+        Ok(RString::new(&writer.finish()))
+    }
+
+    // pluck_to_json(sql, timeout: nil, format: :array, duplicate_columns: :raise)
+    //
+    // Result as JSON String, array of row objects or (`format: :ndjson`) one row object per line
+    fn ruby_pluck_to_json(&self, args: &[Value]) -> Result<RString, magnus::Error> {
+        let (query, keywords) = options::query_args(args)?;
+        let options = QueryOptions::from_keywords(&keywords)?;
+        let json_options = JsonOptions::from_keywords(&keywords)?;
+        keywords.finish()?;
+
+        let hash_keys = HashKeys {
+            mode: KeyMode::String,
+            duplicates: json_options.duplicate_columns,
+        };
+        let mut writer: Option<JsonWriter> = None;
+        let columns = self.query_each_batch(&query, &options, Some(hash_keys), |columns, batch| {
+            writer
+                .get_or_insert_with(|| JsonWriter::new(json_options.format, columns.key_names().to_vec()))
+                .write_batch(batch)
+                .map_err(conversions::to_standard_error)
+        })?;
+        let writer = writer.unwrap_or_else(|| JsonWriter::new(json_options.format, columns.key_names().to_vec()));
+        Ok(RString::new(&writer.finish()))
+    }
+
+    // to_arrow_ipc(sql, io = nil, timeout: nil)
+    //
+    // Result as Arrow IPC stream, returned as binary String. With `io` every record batch is written to it
+    // as soon as it is read, and number of written bytes is returned instead.
+    fn ruby_to_arrow_ipc(&self, args: &[Value]) -> Result<Value, magnus::Error> {
+        let args = scan_args::scan_args::<(String,), (Option<Value>,), (), (), RHash, ()>(args)?;
+        let (query,) = args.required;
+        let io = args.optional.0.filter(|io| !io.is_nil());
+        let keywords = Keywords::new(args.keywords)?;
+        let options = QueryOptions::from_keywords(&keywords)?;
+        keywords.finish()?;
+
+        let ruby = Ruby::get().expect("Ruby not initialized!");
+        let to_error = |err: duckdb::arrow::error::ArrowError| conversions::to_standard_error(Box::new(err));
+        let write_to_io = |io: Value, bytes: &[u8]| -> Result<usize, magnus::Error> {
+            if !bytes.is_empty() {
+                io.funcall::<_, _, Value>("write", (ruby.str_from_slice(bytes),))?;
+            }
+            Ok(bytes.len())
+        };
+        let mut stream: Option<IpcStream> = None;
+        let mut written = 0;
+        let columns = self.query_each_batch(&query, &options, None, |columns, batch| {
+            if stream.is_none() {
+                let schema = columns.arrow_schema().expect("batches have a schema");
+                stream = Some(IpcStream::new(schema).map_err(to_error)?);
+            }
+            let stream = stream.as_mut().expect("stream is created above");
+            stream.write(batch).map_err(to_error)?;
+            if let Some(io) = io {
+                written += write_to_io(io, &stream.take_bytes())?;
+            }
+            Ok(())
+        })?;
+        let stream = match stream {
+            Some(stream) => stream,
+            // result without rows is still a stream with the schema
+            None => IpcStream::new(columns.arrow_schema().expect("batches have a schema")).map_err(to_error)?,
+        };
+        let bytes = stream.finish().map_err(to_error)?;
+        match io {
+            Some(io) => Ok((written + write_to_io(io, &bytes)?).into_value()),
+            None => Ok(ruby.str_from_slice(&bytes).as_value()),
+        }
+    }
+
+    // load_arrow_ipc(table_name, bytes_or_io, timeout: nil)
+    //
+    // Creates the table from Arrow IPC stream (or file) schema unless it already exists, then appends its rows
+    // by column name. Returns number of loaded rows.
+    fn ruby_load_arrow_ipc(&self, args: &[Value]) -> Result<usize, magnus::Error> {
+        let args = scan_args::scan_args::<(Value, Value), (), (), (), RHash, ()>(args)?;
+        let (table_name, source) = args.required;
+        let keywords = Keywords::new(args.keywords)?;
+        let options = QueryOptions::from_keywords(&keywords)?;
+        keywords.finish()?;
+
+        let table_name = conversions::identifier_from_ruby(table_name, "Table name")?;
+        let bytes = arrow_ipc::bytes_from_ruby(source)?;
+        let (database, _running) = self.start_query(&options)?;
+        self.without_gvl(|| arrow_ipc::load(&database.database, &table_name, &bytes))?
+            .map_err(|err| self.to_query_error(err))
+    }
+
+    // load_file(table_name, path_or_glob, format: nil, columns: nil, csv_options: nil, hive_partitioning: nil,
+    //           union_by_name: nil, timeout: nil)
+    //
+    // Creates the table from columns of the scanned file(s) unless it already exists, then appends their rows
+    // by column name. Returns number of loaded rows, raises SnowDuck::SchemaMismatchError when file columns
+    // don't match `columns:` or the existing table.
+    fn ruby_load_file(&self, args: &[Value]) -> Result<usize, magnus::Error> {
+        let args = scan_args::scan_args::<(Value, String), (), (), (), RHash, ()>(args)?;
+        let (table_name, path) = args.required;
+        let keywords = Keywords::new(args.keywords)?;
+        let options = QueryOptions::from_keywords(&keywords)?;
+        let load_options = LoadFileOptions::from_keywords(&keywords)?;
+        keywords.finish()?;
+
+        let table_name = conversions::identifier_from_ruby(table_name, "Table name")?;
+        let scan = load_options.scan(&path)?;
+        let (database, _running) = self.start_query(&options)?;
+        self.without_gvl(|| file_load::load(&database.database, &table_name, &scan, load_options.columns()))?
+            .map_err(|err| match err {
+                LoadError::SchemaMismatch(message) => errors::schema_mismatch_error(message),
+                LoadError::Query(err) => self.to_query_error(err),
+            })
+    }
+
     pub fn execute_batch(&self, batch_statement: String) -> Result<magnus::Value, magnus::Error> {
-        let database = &self.0.borrow().database;
-        database
-            .execute_batch(&batch_statement)
+        self.execute_batch_with(&batch_statement, &QueryOptions::default(), None)
+    }
+
+    fn execute_batch_with(
+        &self,
+        batch_statement: &str,
+        options: &QueryOptions,
+        progress: Option<&ProgressOptions>,
+    ) -> Result<magnus::Value, magnus::Error> {
+        self.run_statement_with_progress(options, progress, |database| database.execute_batch(batch_statement))
             .map(|_| magnus::value::qnil().as_value())
+    }
+
+    // execute_batch(sql, timeout: nil, on_progress: nil, progress_interval: 1)
+    fn ruby_execute_batch(&self, args: &[Value]) -> Result<magnus::Value, magnus::Error> {
+        let (batch_statement, keywords) = options::query_args(args)?;
+        let options = QueryOptions::from_keywords(&keywords)?;
+        let progress = ProgressOptions::from_keywords(&keywords)?;
+        keywords.finish()?;
+        self.execute_batch_with(&batch_statement, &options, progress.as_ref())
+    }
+
+    // DuckDatabase.new(s3_credentials, config: { threads: 2, memory_limit: '2GB' })
+    fn ruby_new(args: &[Value]) -> Result<Self, magnus::Error> {
+        let args = scan_args::scan_args::<(RHash,), (), (), (), RHash, ()>(args)?;
+        let keywords = scan_args::get_kwargs::<_, (), (Option<RHash>,), ()>(args.keywords, &[], &["config"])?;
+        let (options,) = args.required;
+        let (config,) = keywords.optional;
+        Self::initialize(options, config)
+    }
+
+    pub fn initialize(options: magnus::RHash, config: Option<magnus::RHash>) -> Result<Self, magnus::Error> {
+        let s3_credentials = S3Credentials::from_ruby_hash(options)?;
+        let config = config::database_config(config)?
+            .iter()
+            .try_fold(duckdb::Config::default(), |config, (name, value)| config.with(name, value))
+            .map_err(|err| conversions::to_standard_error(Box::new(err)))?;
+        let origin = Connection::open_in_memory_with_flags(config)
+            .map_err(|err| conversions::to_standard_error(Box::new(err)))?;
+        let connected = Self::connect_to(Arc::new(Mutex::new(origin)), Arc::default(), Arc::default())?;
+        {
+            let database = connected.lock()?;
+            connected.relations.install(&database.database)?;
+            arrow_ipc::install(&database.database)?;
+            database
+                .database
+                .execute_batch(
+                    "INSTALL aws;
+                     INSTALL httpfs;",
+                )
+                .map_err(|err| conversions::to_standard_error(Box::new(err)))?;
+            database
+                .database
+                .execute_batch(&s3_credentials.create_secret_statement())
+                .map_err(|err| conversions::to_standard_error(format!("Could not create secrets manager! {}", err).into()))?;
+        }
+        Ok(connected)
+    }
+
+    fn connect_to(
+        origin: Arc<Mutex<Connection>>,
+        functions: Arc<RubyFunctions>,
+        relations: Arc<Relations>,
+    ) -> Result<Self, magnus::Error> {
+        let database = Self::clone_connection(&origin)?;
+        let interrupt = Arc::new(QueryInterrupt::new(database.interrupt_handle()));
+        Ok(Self {
+            database: ConnectionLock::new(DuckDatabase {
+                database,
+                attached_relations: HashMap::new(),
+            }),
+            interrupt,
+            helper: HelperThread::default(),
+            functions,
+            relations,
+            origin,
+        })
+    }
+
+    fn clone_connection(origin: &Mutex<Connection>) -> Result<Connection, magnus::Error> {
+        origin
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .try_clone()
             .map_err(|err| conversions::to_standard_error(Box::new(err)))
     }
 
-    pub fn initialize(options: magnus::RHash) -> Self {
-        let database = Connection::open_in_memory().unwrap();
-        let s3_region = string_from_ruby_hash(options, "s3_region");
-        let s3_access_key_id = string_from_ruby_hash(options, "s3_access_key_id");
-        let s3_secret_access_key = string_from_ruby_hash(options, "s3_secret_access_key");
+    // Independent connection to the same database, it has its own lock so it can run queries in parallel
+    // with this one. Tables, views and secrets are shared, session settings and transactions are not.
+    // Cloned from the idle connection that opened the database, so it does not wait for query that might be
+    // running on this connection.
+    pub fn connect(&self) -> Result<Self, magnus::Error> {
+        Self::connect_to(self.origin.clone(), self.functions.clone(), self.relations.clone())
+    }
 
+    pub fn setting(&self, name: Value) -> Result<magnus::Value, magnus::Error> {
+        let name = config::setting_name_from_ruby(name)?;
+        let database = self.lock()?;
         database
-            .execute_batch(
-                "INSTALL aws;
-                 INSTALL httpfs;",
-            )
-            .unwrap();
-        database
-            .execute_batch(&format!(
-                "CREATE SECRET aws_bucket_secrets (TYPE S3, KEY_ID '{}', SECRET '{}', REGION '{}')",
-                s3_access_key_id, s3_secret_access_key, s3_region
-            ))
-            .expect("Could not create secrets manager!");
-        Self(std::cell::RefCell::from(DuckDatabase { database }))
+            .database
+            .query_row("SELECT current_setting(?)", params![name], |row| row.get::<usize, duckdb::types::Value>(0))
+            .map(conversions::duck_to_ruby)
+            .map_err(|err| conversions::to_standard_error(Box::new(err)))
+    }
+
+    // only settings known to config module can be changed, value type is validated before reaching DuckDB
+    pub fn set(&self, name: Value, value: Value) -> Result<magnus::Value, magnus::Error> {
+        let name = config::setting_name_from_ruby(name)?;
+        let setting_value = config::setting_value_from_ruby(&name, value)?;
+        self.run_statement(&QueryOptions::default(), |database| {
+            database.execute_batch(&config::set_statement(&name, &setting_value))
+        })
+        .map(|_| value)
+    }
+
+    // Replaces S3 secret used for reading remote files, tables that are already loaded are not affected
+    pub fn update_s3_credentials(&self, options: magnus::RHash) -> Result<magnus::Value, magnus::Error> {
+        let s3_credentials = S3Credentials::from_ruby_hash(options)?;
+        self.run_statement(&QueryOptions::default(), |database| {
+            database.execute_batch(&s3_credentials.create_secret_statement())
+        })
+        .map(|_| magnus::value::qnil().as_value())
     }
 
     pub fn execute(&self, statement: String) -> Result<magnus::Value, magnus::Error> {
-        let database = &self.0.borrow().database;
-        database
-            .execute(&statement, params![])
+        self.execute_with(&statement, &QueryOptions::default(), None)
+    }
+
+    fn execute_with(
+        &self,
+        statement: &str,
+        options: &QueryOptions,
+        progress: Option<&ProgressOptions>,
+    ) -> Result<magnus::Value, magnus::Error> {
+        self.run_statement_with_progress(options, progress, |database| database.execute(statement, params![]))
             .map(|rows_changed| rows_changed.into_value())
-            .map_err(|err| conversions::to_standard_error(Box::new(err)))
+    }
+
+    // execute(sql, timeout: nil, on_progress: nil, progress_interval: 1)
+    fn ruby_execute(&self, args: &[Value]) -> Result<magnus::Value, magnus::Error> {
+        let (statement, keywords) = options::query_args(args)?;
+        let options = QueryOptions::from_keywords(&keywords)?;
+        let progress = ProgressOptions::from_keywords(&keywords)?;
+        keywords.finish()?;
+        self.execute_with(&statement, &options, progress.as_ref())
+    }
+
+    // export(sql, path, format: :parquet, compression: nil, partition_by: nil, row_group_size: nil, timeout: nil,
+    //        on_progress: nil, progress_interval: 1)
+    //
+    // Writes result of the query to a file (or Hive partitioned directory with partition_by:) with COPY ... TO.
+    // Options are validated before anything runs. Returns number of written rows.
+    fn ruby_export(&self, args: &[Value]) -> Result<magnus::Value, magnus::Error> {
+        let args = scan_args::scan_args::<(String, String), (), (), (), RHash, ()>(args)?;
+        let (query, path) = args.required;
+        let keywords = Keywords::new(args.keywords)?;
+        let options = QueryOptions::from_keywords(&keywords)?;
+        let progress = ProgressOptions::from_keywords(&keywords)?;
+        let export = ExportOptions::from_keywords(&keywords)?;
+        keywords.finish()?;
+
+        let statement = export.copy_statement(&query, &path)?;
+        self.execute_with(&statement, &options, progress.as_ref())
+    }
+
+    // Transaction statements are the same ones duckdb-rs Transaction issues, for transactions that do not fit in
+    // a block. Block forms (DuckDatabase#transaction and #isolate) are built on `run_transaction`.
+    pub fn begin_transaction(&self) -> Result<magnus::Value, magnus::Error> {
+        self.run_statement(&QueryOptions::default(), |database| database.execute_batch("BEGIN TRANSACTION"))
+            .map(|_| magnus::value::qnil().as_value())
+    }
+
+    pub fn commit(&self) -> Result<magnus::Value, magnus::Error> {
+        self.run_statement(&QueryOptions::default(), |database| database.execute_batch("COMMIT"))
+            .map(|_| magnus::value::qnil().as_value())
+    }
+
+    pub fn rollback(&self) -> Result<magnus::Value, magnus::Error> {
+        self.run_statement(&QueryOptions::default(), |database| database.execute_batch("ROLLBACK"))
+            .map(|_| magnus::value::qnil().as_value())
+    }
+
+    // Yields in duckdb-rs Transaction, which is committed (with `commit` true) once the block returns and rolled
+    // back when it raises or jumps out of it (or always, with `commit` false). Block may commit or roll back on
+    // its own, then there is nothing left to end. DuckDB has no savepoints, so there is no nested transaction
+    // that could be rolled back without the outer one, and opening one is refused.
+    //
+    // Queries from the block need the lock, so it is released while the block runs. Transaction borrows the
+    // connection for its whole life regardless, it is only used while the lock is held though.
+    fn run_transaction(&self, commit: bool) -> Result<magnus::Value, magnus::Error> {
+        let ruby = Ruby::get().expect("Ruby not initialized!");
+        let transaction = {
+            let database = self.lock()?;
+            if !database.database.is_autocommit() {
+                return Err(conversions::to_argument_error(
+                    "Nested transactions are not supported, DuckDB has no savepoints to roll back the nested one"
+                        .to_owned(),
+                ));
+            }
+            // connection lives in `self.database` (which does not move) as long as `self` does
+            let connection = unsafe { &*(&database.database as *const Connection) };
+            self.without_gvl(|| connection.unchecked_transaction())?
+                .map_err(|err| self.to_query_error(Box::new(err)))?
+        };
+
+        let result = ruby.yield_values::<(), magnus::Value>(());
+
+        let database = match self.lock() {
+            Ok(database) => database,
+            Err(err) => {
+                // transaction stays open rather than being rolled back without the lock
+                let mut transaction = transaction;
+                transaction.set_drop_behavior(DropBehavior::Ignore);
+                return Err(err);
+            }
+        };
+        let ended = if database.database.is_autocommit() {
+            let mut transaction = transaction;
+            transaction.set_drop_behavior(DropBehavior::Ignore);
+            Ok(())
+        } else if commit && result.is_ok() {
+            self.without_gvl(|| transaction.commit())?
+        } else {
+            self.without_gvl(|| transaction.rollback())?
+        };
+        drop(database);
+        let value = result?;
+        ended.map_err(|err| self.to_query_error(Box::new(err)))?;
+        Ok(value)
+    }
+
+    pub fn in_transaction(&self) -> Result<bool, magnus::Error> {
+        Ok(!self.lock()?.database.is_autocommit())
+    }
+
+    // Functions are registered in the system catalog, every connection of the database sees them. Registration
+    // runs in its own connection, so that rolling back transaction open on this connection does not undo it.
+    // Lock is still held, so that it waits for the running query.
+    fn catalog_connection(&self) -> Result<Connection, magnus::Error> {
+        Self::clone_connection(&self.origin)
+    }
+
+    // create_function(name, [:integer, :varchar], :double, vectorized: false, volatile: false) { |*args| ... }
+    //
+    // Registers the block as DuckDB scalar function, visible to all connections of this database.
+    // Block is called with ruby values of the arguments and its result is converted to `return_type`,
+    // rows with NULL argument are NULL without calling it. With `vectorized: true` block is called once
+    // per chunk with an array per argument, and returns array of results. Exceptions raised by the block
+    // fail the query.
+    fn ruby_create_function(&self, args: &[Value]) -> Result<magnus::Value, magnus::Error> {
+        let args = scan_args::scan_args::<(Value, RArray, Value), (), (), (), RHash, Option<Proc>>(args)?;
+        let (name, argument_types, return_type) = args.required;
+        let block = args
+            .block
+            .ok_or_else(|| conversions::to_argument_error("create_function requires a block".to_owned()))?;
+        let keywords = Keywords::new(args.keywords)?;
+        let options = ScalarOptions::from_keywords(&keywords)?;
+        keywords.finish()?;
+
+        let name = conversions::identifier_from_ruby(name, "Function name")?;
+        let argument_types = column_type::column_types_from_ruby(argument_types)?;
+        let return_type = column_type::ColumnType::from_ruby(return_type)?;
+        let _database = self.lock()?;
+        self.functions.create_scalar_function(
+            &self.catalog_connection()?,
+            &name,
+            argument_types,
+            return_type,
+            &options,
+            block,
+        )?;
+        Ok(magnus::value::qnil().as_value())
+    }
+
+    // create_aggregate(name, [:double, :double], :double, init:, update:, combine:, finalize: nil)
+    //
+    // Registers ruby callables as DuckDB aggregate function. Every group starts with `init.call` state,
+    // `update.call(state, *arguments)` returns new state for each row (rows with NULL argument are skipped),
+    // `combine.call(state, other_state)` merges states aggregated in parallel, and `finalize.call(state)`
+    // (or the state itself) is the result. States are kept by us, callables run on this ruby thread.
+    fn ruby_create_aggregate(&self, args: &[Value]) -> Result<magnus::Value, magnus::Error> {
+        let args = scan_args::scan_args::<(Value, RArray, Value), (), (), (), RHash, ()>(args)?;
+        let (name, argument_types, return_type) = args.required;
+        let keywords = Keywords::new(args.keywords)?;
+        let callables = AggregateCallables::from_keywords(&keywords)?;
+        keywords.finish()?;
+
+        let name = conversions::identifier_from_ruby(name, "Aggregate name")?;
+        let argument_types = column_type::column_types_from_ruby(argument_types)?;
+        let return_type = column_type::ColumnType::from_ruby(return_type)?;
+        let _database = self.lock()?;
+        self.functions.create_aggregate_function(
+            &self.catalog_connection()?,
+            &name,
+            argument_types,
+            return_type,
+            callables,
+        )?;
+        Ok(magnus::value::qnil().as_value())
+    }
+
+    // define_table_function(name, { id: :integer, species: :varchar }, -> { Enumerator.new { ... } })
+    //
+    // Registers table function reading its rows from ruby. Every scan of the function calls the factory
+    // for a new Enumerator, rows are pulled from it (in chunks) on the ruby thread that runs the query.
+    fn define_table_function(&self, name: Value, schema: RHash, enumerator_factory: Value) -> Result<magnus::Value, magnus::Error> {
+        let name = conversions::identifier_from_ruby(name, "Table function name")?;
+        let columns = table_functions::table_schema_from_ruby(schema)?;
+        let _database = self.lock()?;
+        self.functions
+            .create_table_function(&self.catalog_connection()?, &name, columns, enumerator_factory)?;
+        Ok(magnus::value::qnil().as_value())
+    }
+
+    // attach_relation(name, { id: :integer, species: :varchar }, rows)
+    //
+    // Makes rows (hashes or arrays) queryable by name in this connection, until detached. Rows are converted
+    // right away, queries read them without calling ruby.
+    fn attach_relation(&self, name: Value, schema: RHash, rows: RArray) -> Result<magnus::Value, magnus::Error> {
+        let name = conversions::identifier_from_ruby(name, "Relation name")?;
+        let relation = Relation::from_ruby(table_functions::table_schema_from_ruby(schema)?, rows)?;
+        let mut database = self.lock()?;
+        if database.attached_relations.contains_key(&name.to_lowercase()) {
+            return Err(conversions::to_argument_error(format!("Relation {} is already attached", name)));
+        }
+        let key = self.relations.attach(&name, relation);
+        let view = relations::create_view_statement(&name, &key);
+        if let Err(err) = gvl::without_gvl(|| database.database.execute_batch(&view))? {
+            self.relations.detach(&key);
+            return Err(conversions::to_standard_error(Box::new(err)));
+        }
+        database.attached_relations.insert(name.to_lowercase(), key);
+        Ok(magnus::value::qnil().as_value())
+    }
+
+    fn detach_relation(&self, name: Value) -> Result<bool, magnus::Error> {
+        let name = conversions::identifier_from_ruby(name, "Relation name")?;
+        let mut database = self.lock()?;
+        let key = match database.attached_relations.remove(&name.to_lowercase()) {
+            Some(key) => key,
+            None => return Ok(false),
+        };
+        self.relations.detach(&key);
+        // view is gone already if transaction it was created in rolled back
+        let view = relations::drop_view_statement(&name);
+        gvl::without_gvl(|| database.database.execute_batch(&view))?
+            .map_err(|err| conversions::to_standard_error(Box::new(err)))?;
+        Ok(true)
+    }
+
+    // Can be called from any thread, interrupts query currently running on this database (if any)
+    // which then raises SnowDuck::InterruptError
+    pub fn interrupt(&self) -> magnus::Value {
+        self.interrupt.interrupt(InterruptReason::Request);
+        magnus::value::qnil().as_value()
     }
 }
 
 #[magnus::init]
 fn init() -> Result<(), Error> {
+    errors::define_errors()?;
     let class = define_class("DuckDatabase", class::object())?;
-    class.define_singleton_method("new", function!(MutDatabase::initialize, 1))?;
-    class.define_method("execute_batch", method!(MutDatabase::execute_batch, 1))?;
-    class.define_method("execute", method!(MutDatabase::execute, 1))?;
-    class.define_method("pluck", method!(MutDatabase::duck_pluck, 1))?;
-    class.define_method("pluck_to_hash", method!(MutDatabase::duck_pluck_to_hash, 1))?;
+    class.define_singleton_method("new", function!(MutDatabase::ruby_new, -1))?;
+    class.define_method("execute_batch", method!(MutDatabase::ruby_execute_batch, -1))?;
+    class.define_method("execute", method!(MutDatabase::ruby_execute, -1))?;
+    class.define_method("export", method!(MutDatabase::ruby_export, -1))?;
+    class.define_method("pluck", method!(MutDatabase::ruby_pluck, -1))?;
+    class.define_method("pluck_to_hash", method!(MutDatabase::ruby_pluck_to_hash, -1))?;
+    class.define_method("pluck_to", method!(MutDatabase::ruby_pluck_to, -1))?;
+    class.define_method("pluck_to_struct", method!(MutDatabase::ruby_pluck_to_struct, -1))?;
+    class.define_method("pluck_columns", method!(MutDatabase::ruby_pluck_columns, -1))?;
+    class.define_method("packed_columns", method!(MutDatabase::ruby_packed_columns, -1))?;
+    class.define_method("to_arrow_ipc", method!(MutDatabase::ruby_to_arrow_ipc, -1))?;
+    class.define_method("load_arrow_ipc", method!(MutDatabase::ruby_load_arrow_ipc, -1))?;
+    class.define_method("load_file", method!(MutDatabase::ruby_load_file, -1))?;
+    class.define_method("pluck_to_csv", method!(MutDatabase::ruby_pluck_to_csv, -1))?;
+    class.define_method("pluck_to_json", method!(MutDatabase::ruby_pluck_to_json, -1))?;
+    class.define_method("update_s3_credentials", method!(MutDatabase::update_s3_credentials, 1))?;
+    class.define_method("setting", method!(MutDatabase::setting, 1))?;
+    class.define_method("set", method!(MutDatabase::set, 2))?;
+    class.define_method("interrupt", method!(MutDatabase::interrupt, 0))?;
+    class.define_method("connect", method!(MutDatabase::connect, 0))?;
+    class.define_method("begin_transaction", method!(MutDatabase::begin_transaction, 0))?;
+    class.define_method("commit", method!(MutDatabase::commit, 0))?;
+    class.define_method("rollback", method!(MutDatabase::rollback, 0))?;
+    class.define_method("in_transaction?", method!(MutDatabase::in_transaction, 0))?;
+    class.define_method("run_transaction", method!(MutDatabase::run_transaction, 1))?;
+    class.define_method("create_function", method!(MutDatabase::ruby_create_function, -1))?;
+    class.define_method("create_aggregate", method!(MutDatabase::ruby_create_aggregate, -1))?;
+    class.define_method("define_table_function", method!(MutDatabase::define_table_function, 3))?;
+    class.define_method("attach_relation", method!(MutDatabase::attach_relation, 3))?;
+    class.define_method("detach_relation", method!(MutDatabase::detach_relation, 1))?;
     Ok(())
 }
@@ -0,0 +1,147 @@
+use std::{
+    mem::ManuallyDrop,
+    ops::{Deref, DerefMut},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Condvar, Mutex, MutexGuard, PoisonError, TryLockError,
+    },
+    thread::{self, ThreadId},
+    time::{Duration, Instant},
+};
+
+use crate::{errors, gvl};
+
+// Mutex that remembers which thread holds it. Ruby code called while the lock is held (row conversion hooks,
+// UDF callbacks...) can call back into the same database on the same thread; with plain Mutex that would
+// dead-lock the thread (and RefCell we used before panicked), so we raise SnowDuck::ConcurrencyError instead.
+// Other threads wait for the lock with GVL released, but still handle ruby interrupts while they wait.
+pub(crate) struct ConnectionLock<T> {
+    value: Mutex<T>,
+    owner: Mutex<Option<ThreadId>>,
+    // notified (with `owner` locked) once the lock is released, or when a waiting thread is interrupted
+    released: Condvar,
+}
+
+enum Wait<G> {
+    Locked(G),
+    // ruby wants to interrupt waiting thread
+    Unblocked,
+    TimedOut,
+}
+
+impl<T> ConnectionLock<T> {
+    pub(crate) fn new(value: T) -> Self {
+        Self {
+            value: Mutex::new(value),
+            owner: Mutex::new(None),
+            released: Condvar::new(),
+        }
+    }
+
+    pub(crate) fn lock(&self) -> Result<ConnectionGuard<'_, T>, magnus::Error> {
+        self.lock_within(None)
+    }
+
+    // Waits at most `timeout` for the lock, then raises SnowDuck::TimeoutError. Ruby interrupts (Thread#raise,
+    // Timeout.timeout, Ctrl-C) stop the wait, query of the thread holding the lock keeps running.
+    pub(crate) fn lock_within(&self, timeout: Option<Duration>) -> Result<ConnectionGuard<'_, T>, magnus::Error> {
+        let current_thread = thread::current().id();
+        if *self.owner() == Some(current_thread) {
+            return Err(errors::concurrency_error(
+                "Database is already in use by this thread (nested query from a callback or a block?). \
+                 Finish the running query first, or use separate connection from DuckDatabase#connect"
+                    .to_owned(),
+            ));
+        }
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            let unblocked = AtomicBool::new(false);
+            let (owner, released) = (&self.owner, &self.released);
+            let unblock = || {
+                unblocked.store(true, Ordering::SeqCst);
+                // with `owner` locked, waiting thread is either waiting already or has not checked the flag yet
+                let _owner = owner.lock().unwrap_or_else(PoisonError::into_inner);
+                released.notify_all();
+            };
+            // Lock is taken without GVL, as thread currently holding it may need GVL to finish what it is doing
+            match gvl::without_gvl_unblocking(|| self.wait(deadline, &unblocked), &unblock)? {
+                Wait::Locked(guard) => {
+                    *self.owner() = Some(current_thread);
+                    return Ok(ConnectionGuard {
+                        guard: ManuallyDrop::new(guard),
+                        lock: self,
+                    });
+                }
+                Wait::TimedOut => {
+                    return Err(errors::timeout_error(
+                        "Timed out waiting for the database, it is busy with another query".to_owned(),
+                    ))
+                }
+                // raises the interrupt, or keeps waiting if there was none after all
+                Wait::Unblocked => gvl::check_interrupts()?,
+            }
+        }
+    }
+
+    // Runs without GVL
+    fn wait(&self, deadline: Option<Instant>, unblocked: &AtomicBool) -> Wait<MutexGuard<'_, T>> {
+        let mut owner = self.owner();
+        loop {
+            match self.value.try_lock() {
+                Ok(guard) => return Wait::Locked(guard),
+                // connection itself is still usable if some other thread panicked while holding the lock
+                Err(TryLockError::Poisoned(poisoned)) => return Wait::Locked(poisoned.into_inner()),
+                Err(TryLockError::WouldBlock) => {}
+            }
+            if unblocked.load(Ordering::SeqCst) {
+                return Wait::Unblocked;
+            }
+            owner = match deadline {
+                None => self.released.wait(owner).unwrap_or_else(PoisonError::into_inner),
+                Some(deadline) => {
+                    let left = deadline.saturating_duration_since(Instant::now());
+                    if left.is_zero() {
+                        return Wait::TimedOut;
+                    }
+                    self.released
+                        .wait_timeout(owner, left)
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .0
+                }
+            };
+        }
+    }
+
+    fn owner(&self) -> MutexGuard<'_, Option<ThreadId>> {
+        self.owner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+pub(crate) struct ConnectionGuard<'a, T> {
+    guard: ManuallyDrop<MutexGuard<'a, T>>,
+    lock: &'a ConnectionLock<T>,
+}
+
+impl<T> Drop for ConnectionGuard<'_, T> {
+    // owner is cleared while we still hold the lock, waiting threads are woken up once it is released
+    fn drop(&mut self) {
+        let mut owner = self.lock.owner();
+        *owner = None;
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+        self.lock.released.notify_all();
+    }
+}
+
+impl<T> Deref for ConnectionGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T> DerefMut for ConnectionGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
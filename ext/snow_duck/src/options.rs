@@ -0,0 +1,292 @@
+use std::time::{Duration, Instant};
+
+use magnus::{prelude::*, scan_args, RArray, RHash, Ruby, Symbol, TryConvert, Value};
+
+use crate::conversions::{self, to_argument_error};
+
+// Keyword arguments passed to DuckDatabase methods, every option struct takes the keywords it knows about
+// and whatever is left over is reported as unknown keyword
+pub(crate) struct Keywords(RHash);
+
+impl Keywords {
+    pub(crate) fn new(keywords: RHash) -> Result<Self, magnus::Error> {
+        // we remove keywords while parsing, so make sure caller's hash is left alone
+        Ok(Self(keywords.funcall("dup", ())?))
+    }
+
+    pub(crate) fn take<T: TryConvert>(&self, name: &str) -> Result<Option<T>, magnus::Error> {
+        let value: Option<Value> = self.0.delete(Symbol::new(name))?;
+        match value {
+            Some(value) if !value.is_nil() => T::try_convert(value)
+                .map(Some)
+                .map_err(|err| to_argument_error(format!("Invalid value for {}: {}", name, err))),
+            _ => Ok(None),
+        }
+    }
+
+    pub(crate) fn finish(self) -> Result<(), magnus::Error> {
+        if self.0.is_empty() {
+            Ok(())
+        } else {
+            let unknown_keywords: Value = self.0.funcall("keys", ())?;
+            Err(to_argument_error(format!("unknown keyword(s): {}", unknown_keywords.inspect())))
+        }
+    }
+}
+
+// (sql, **keywords) arguments shared by all query methods
+pub(crate) fn query_args(args: &[Value]) -> Result<(String, Keywords), magnus::Error> {
+    let args = scan_args::scan_args::<(String,), (), (), (), RHash, ()>(args)?;
+    let (query,) = args.required;
+    Ok((query, Keywords::new(args.keywords)?))
+}
+
+#[derive(Default)]
+pub(crate) struct QueryOptions {
+    // query is interrupted with SnowDuck::TimeoutError once this passes
+    pub(crate) timeout: Option<Duration>,
+}
+
+impl QueryOptions {
+    pub(crate) fn from_keywords(keywords: &Keywords) -> Result<Self, magnus::Error> {
+        let timeout = keywords
+            .take::<f64>("timeout")?
+            .map(|seconds| {
+                if seconds.is_finite() && seconds > 0.0 {
+                    Ok(Duration::from_secs_f64(seconds))
+                } else {
+                    Err(to_argument_error(format!("timeout must be positive number of seconds, got {}", seconds)))
+                }
+            })
+            .transpose()?;
+        Ok(Self { timeout })
+    }
+
+    // options of what is left of the query after waiting since `started_at` (e.g. for the database lock)
+    pub(crate) fn after_waiting(&self, started_at: Instant) -> Self {
+        Self {
+            timeout: self.timeout.map(|timeout| timeout.saturating_sub(started_at.elapsed())),
+        }
+    }
+}
+
+// Hash keys of pluck_to_hash rows. Column names can be anything (aliases like sum_2024_11_practice_42),
+// so none of the modes creates static symbols, which are never garbage collected.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum KeyMode {
+    // dynamic symbols, collected once nothing uses them
+    #[default]
+    Symbol,
+    // frozen strings, created once per column of the result
+    String,
+    // deduplicated frozen strings (String#-@), shared by every result with the same column names
+    FrozenString,
+}
+
+impl KeyMode {
+    fn from_ruby(value: Symbol) -> Result<Self, magnus::Error> {
+        match value.name()?.as_ref() {
+            "symbol" => Ok(Self::Symbol),
+            "string" => Ok(Self::String),
+            "frozen_string" => Ok(Self::FrozenString),
+            name => Err(to_argument_error(format!(
+                "keys must be :symbol, :string or :frozen_string, got :{}",
+                name
+            ))),
+        }
+    }
+
+    pub(crate) fn key(self, ruby: &Ruby, column_name: &str) -> Result<Value, magnus::Error> {
+        match self {
+            Self::Symbol => Ok(Symbol::new(column_name).as_value()),
+            Self::String => {
+                // Hash#[]= copies string keys that are not frozen, for every row
+                let key = ruby.str_new(column_name);
+                key.freeze();
+                Ok(key.as_value())
+            }
+            Self::FrozenString => ruby.str_new(column_name).funcall("-@", ()),
+        }
+    }
+}
+
+// Class of pluck_to_hash rows (and pluck_columns result)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum HashFlavour {
+    #[default]
+    Plain,
+    // ActiveSupport::HashWithIndifferentAccess, which keeps string keys
+    Indifferent,
+    // frozen Hash
+    Frozen,
+}
+
+impl HashFlavour {
+    // pluck_to_hash rows without `hash:` keep the class they always had: HashWithIndifferentAccess when
+    // ActiveSupport is loaded, plain Hash otherwise. Checked once per call, not for every row.
+    pub(crate) fn implicit(ruby: &Ruby) -> Result<Self, magnus::Error> {
+        let indifferent = ruby.eval::<bool>("defined?(ActiveSupport::HashWithIndifferentAccess) ? true : false")?;
+        Ok(if indifferent { Self::Indifferent } else { Self::Plain })
+    }
+
+    fn from_ruby(value: Symbol) -> Result<Self, magnus::Error> {
+        Self::from_name(value.name()?.as_ref())
+    }
+
+    pub(crate) fn from_name(name: &str) -> Result<Self, magnus::Error> {
+        match name {
+            "plain" => Ok(Self::Plain),
+            "indifferent" => Ok(Self::Indifferent),
+            "frozen" => Ok(Self::Frozen),
+            name => Err(to_argument_error(format!(
+                "hash must be :plain, :indifferent or :frozen, got :{}",
+                name
+            ))),
+        }
+    }
+}
+
+// What pluck_to_hash does when several result columns have the same name, e.g. `SELECT a.id, b.id ...`
+// (hash can not have both of them under the same key)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum DuplicateColumns {
+    // raise SnowDuck::DuplicateColumnError
+    #[default]
+    Raise,
+    // later columns get `_1`, `_2`... suffix: id, id_1
+    Rename,
+}
+
+impl DuplicateColumns {
+    pub(crate) fn from_keywords(keywords: &Keywords) -> Result<Self, magnus::Error> {
+        Ok(keywords
+            .take::<Symbol>("duplicate_columns")?
+            .map(Self::from_ruby)
+            .transpose()?
+            .unwrap_or_default())
+    }
+
+    fn from_ruby(value: Symbol) -> Result<Self, magnus::Error> {
+        match value.name()?.as_ref() {
+            "raise" => Ok(Self::Raise),
+            "rename" => Ok(Self::Rename),
+            name => Err(to_argument_error(format!(
+                "duplicate_columns must be :raise or :rename, got :{}",
+                name
+            ))),
+        }
+    }
+}
+
+// `columns: [:id, :species]` of pluck_columns/pluck_packed, names of the (only) columns to return
+pub(crate) fn selected_columns_from_keywords(keywords: &Keywords) -> Result<Option<Vec<String>>, magnus::Error> {
+    keywords
+        .take::<RArray>("columns")?
+        .map(|columns| {
+            columns
+                .to_vec::<Value>()?
+                .into_iter()
+                .map(|column| conversions::identifier_from_ruby(column, "Column name"))
+                .collect()
+        })
+        .transpose()
+}
+
+// How result column names become hash keys
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct HashKeys {
+    pub(crate) mode: KeyMode,
+    pub(crate) duplicates: DuplicateColumns,
+}
+
+// Keywords deciding how rows of pluck/pluck_to_hash are converted to ruby
+pub(crate) struct ResultOptions {
+    // convert Arrow record batches column by column, `columnar: false` converts row by row (cell by cell)
+    pub(crate) columnar: bool,
+    pub(crate) keys: KeyMode,
+    pub(crate) hash: HashFlavour,
+    pub(crate) duplicate_columns: DuplicateColumns,
+}
+
+impl Default for ResultOptions {
+    fn default() -> Self {
+        Self {
+            columnar: true,
+            keys: KeyMode::default(),
+            hash: HashFlavour::default(),
+            duplicate_columns: DuplicateColumns::default(),
+        }
+    }
+}
+
+impl ResultOptions {
+    pub(crate) fn from_keywords(keywords: &Keywords) -> Result<Self, magnus::Error> {
+        Ok(Self {
+            columnar: Self::take_columnar(keywords)?,
+            keys: Self::take_keys(keywords)?,
+            hash: Self::take_hash(keywords)?.unwrap_or_default(),
+            duplicate_columns: DuplicateColumns::from_keywords(keywords)?,
+        })
+    }
+
+    // pluck_to_hash keywords, rows are HashFlavour::implicit without `hash:`
+    pub(crate) fn from_hash_row_keywords(ruby: &Ruby, keywords: &Keywords) -> Result<Self, magnus::Error> {
+        let hash = match Self::take_hash(keywords)? {
+            Some(hash) => hash,
+            None => HashFlavour::implicit(ruby)?,
+        };
+        Ok(Self {
+            columnar: Self::take_columnar(keywords)?,
+            keys: Self::take_keys(keywords)?,
+            hash,
+            duplicate_columns: DuplicateColumns::from_keywords(keywords)?,
+        })
+    }
+
+    // pluck_to/pluck_to_struct rows are not hashes, so only keywords that apply to them are accepted.
+    // Column names become symbols, same as Struct members are.
+    pub(crate) fn from_row_object_keywords(keywords: &Keywords) -> Result<Self, magnus::Error> {
+        Ok(Self {
+            columnar: Self::take_columnar(keywords)?,
+            duplicate_columns: DuplicateColumns::from_keywords(keywords)?,
+            ..Self::default()
+        })
+    }
+
+    // pluck_packed always reads record batches, and returns plain hash of packed columns
+    pub(crate) fn from_packed_keywords(keywords: &Keywords) -> Result<Self, magnus::Error> {
+        Ok(Self {
+            keys: Self::take_keys(keywords)?,
+            duplicate_columns: DuplicateColumns::from_keywords(keywords)?,
+            ..Self::default()
+        })
+    }
+
+    fn take_columnar(keywords: &Keywords) -> Result<bool, magnus::Error> {
+        Ok(keywords.take::<bool>("columnar")?.unwrap_or(true))
+    }
+
+    fn take_keys(keywords: &Keywords) -> Result<KeyMode, magnus::Error> {
+        Ok(keywords
+            .take::<Symbol>("keys")?
+            .map(KeyMode::from_ruby)
+            .transpose()?
+            .unwrap_or_default())
+    }
+
+    fn take_hash(keywords: &Keywords) -> Result<Option<HashFlavour>, magnus::Error> {
+        keywords.take::<Symbol>("hash")?.map(HashFlavour::from_ruby).transpose()
+    }
+
+    pub(crate) fn hash_keys(&self) -> HashKeys {
+        // indifferent access hashes keep their keys as strings, so symbols would only be converted again
+        let mode = match (self.hash, self.keys) {
+            (HashFlavour::Indifferent, KeyMode::Symbol) => KeyMode::FrozenString,
+            (_, keys) => keys,
+        };
+        HashKeys {
+            mode,
+            duplicates: self.duplicate_columns,
+        }
+    }
+}
@@ -0,0 +1,168 @@
+use duckdb::arrow::{
+    array::{Array, AsArray, BooleanBufferBuilder},
+    datatypes::{
+        ArrowPrimitiveType, DataType, Float32Type, Float64Type, Int16Type, Int32Type, Int64Type, Int8Type, UInt16Type,
+        UInt32Type, UInt64Type, UInt8Type,
+    },
+    record_batch::RecordBatch,
+};
+use magnus::{prelude::*, RArray, RHash, RString, Ruby, Symbol, Value};
+
+use crate::{conversions::to_argument_error, result_columns::ResultColumns};
+
+// Fixed width numeric columns packed into binary ruby Strings, values of each record batch are appended
+// with a single copy, without creating a ruby object per value. Values are in native byte order, the same
+// ones String#unpack (and Numo's from_binary) read.
+
+// Types that can be packed, named by DuckDB type they come from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum PackedType {
+    TinyInt,
+    SmallInt,
+    Integer,
+    BigInt,
+    UTinyInt,
+    USmallInt,
+    UInteger,
+    UBigInt,
+    Float,
+    Double,
+}
+
+impl PackedType {
+    fn from_arrow(data_type: &DataType) -> Option<Self> {
+        match data_type {
+            DataType::Int8 => Some(Self::TinyInt),
+            DataType::Int16 => Some(Self::SmallInt),
+            DataType::Int32 => Some(Self::Integer),
+            DataType::Int64 => Some(Self::BigInt),
+            DataType::UInt8 => Some(Self::UTinyInt),
+            DataType::UInt16 => Some(Self::USmallInt),
+            DataType::UInt32 => Some(Self::UInteger),
+            DataType::UInt64 => Some(Self::UBigInt),
+            DataType::Float32 => Some(Self::Float),
+            DataType::Float64 => Some(Self::Double),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::TinyInt => "tinyint",
+            Self::SmallInt => "smallint",
+            Self::Integer => "integer",
+            Self::BigInt => "bigint",
+            Self::UTinyInt => "utinyint",
+            Self::USmallInt => "usmallint",
+            Self::UInteger => "uinteger",
+            Self::UBigInt => "ubigint",
+            Self::Float => "float",
+            Self::Double => "double",
+        }
+    }
+
+    // values of the (already sliced) array, as bytes
+    fn bytes(self, array: &dyn Array) -> &[u8] {
+        match self {
+            Self::TinyInt => primitive_bytes::<Int8Type>(array),
+            Self::SmallInt => primitive_bytes::<Int16Type>(array),
+            Self::Integer => primitive_bytes::<Int32Type>(array),
+            Self::BigInt => primitive_bytes::<Int64Type>(array),
+            Self::UTinyInt => primitive_bytes::<UInt8Type>(array),
+            Self::USmallInt => primitive_bytes::<UInt16Type>(array),
+            Self::UInteger => primitive_bytes::<UInt32Type>(array),
+            Self::UBigInt => primitive_bytes::<UInt64Type>(array),
+            Self::Float => primitive_bytes::<Float32Type>(array),
+            Self::Double => primitive_bytes::<Float64Type>(array),
+        }
+    }
+}
+
+fn primitive_bytes<T: ArrowPrimitiveType>(array: &dyn Array) -> &[u8] {
+    // buffer of the values is sliced to the array's offset and length
+    array.as_primitive::<T>().values().inner().as_slice()
+}
+
+struct PackedColumn {
+    // index of the column in the result
+    position: usize,
+    packed_type: PackedType,
+    // Arrow style validity bitmap, bit set for values that are not NULL
+    validity: BooleanBufferBuilder,
+    null_count: usize,
+}
+
+// Selected columns of one result being packed
+pub(crate) struct PackedColumns {
+    columns: Vec<PackedColumn>,
+    // binary String of every column, in `columns` order. Kept in ruby Array so GC sees them.
+    data: RArray,
+}
+
+impl PackedColumns {
+    pub(crate) fn new(
+        ruby: &Ruby,
+        result_columns: &ResultColumns,
+        selected: Option<&[String]>,
+    ) -> Result<Self, magnus::Error> {
+        let schema = result_columns.arrow_schema().expect("packed columns are read as record batches");
+        let positions = result_columns.positions(selected)?;
+        let data = RArray::with_capacity(positions.len());
+        let mut columns = Vec::with_capacity(positions.len());
+        for position in positions {
+            let data_type = schema.field(position).data_type();
+            let packed_type = PackedType::from_arrow(data_type).ok_or_else(|| {
+                to_argument_error(format!(
+                    "Column {} ({}) can not be packed, only fixed width numeric columns can. \
+                     Cast it or leave it out with columns:",
+                    result_columns.key_names()[position],
+                    data_type
+                ))
+            })?;
+            data.push(ruby.str_buf_new(0))?;
+            columns.push(PackedColumn {
+                position,
+                packed_type,
+                validity: BooleanBufferBuilder::new(0),
+                null_count: 0,
+            });
+        }
+        Ok(Self { columns, data })
+    }
+
+    pub(crate) fn append(&mut self, batch: &RecordBatch) -> Result<(), magnus::Error> {
+        for (index, column) in self.columns.iter_mut().enumerate() {
+            let array = batch.column(column.position).as_ref();
+            self.data
+                .entry::<RString>(index as isize)?
+                .cat(column.packed_type.bytes(array));
+            match array.nulls() {
+                Some(nulls) => {
+                    column.validity.append_buffer(nulls.inner());
+                    column.null_count += nulls.null_count();
+                }
+                None => column.validity.append_n(array.len(), true),
+            }
+        }
+        Ok(())
+    }
+
+    // { column_name => { type: :double, data: "...", validity: "..." (nil without NULLs), length: 3 } }
+    pub(crate) fn into_ruby(self, ruby: &Ruby, result_columns: &ResultColumns) -> Result<RHash, magnus::Error> {
+        let result = RHash::new();
+        for (index, column) in self.columns.iter().enumerate() {
+            let packed = RHash::new();
+            packed.aset(Symbol::new("type"), Symbol::new(column.packed_type.name()))?;
+            packed.aset(Symbol::new("data"), self.data.entry::<Value>(index as isize)?)?;
+            let validity = if column.null_count > 0 {
+                ruby.str_from_slice(column.validity.as_slice()).as_value()
+            } else {
+                ruby.qnil().as_value()
+            };
+            packed.aset(Symbol::new("validity"), validity)?;
+            packed.aset(Symbol::new("length"), column.validity.len())?;
+            result.aset(result_columns.keys()[column.position], packed)?;
+        }
+        Ok(result)
+    }
+}
@@ -0,0 +1,73 @@
+use std::time::Duration;
+
+use magnus::{prelude::*, Value};
+
+use crate::{
+    callbacks::{self, CallbackQueue, HelperThread, Tick},
+    conversions::to_argument_error,
+    interrupt::QueryInterrupt,
+    options::Keywords,
+    raw::{ConnectionHandle, QueryProgress},
+};
+
+const DEFAULT_PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+// on_progress: ->(percentage, rows_processed, total_rows) { ... }, progress_interval: 1 (seconds)
+pub(crate) struct ProgressOptions {
+    callback: Value,
+    interval: Duration,
+}
+
+impl ProgressOptions {
+    pub(crate) fn from_keywords(keywords: &Keywords) -> Result<Option<Self>, magnus::Error> {
+        let callback = keywords.take::<Value>("on_progress")?;
+        let interval = keywords.take::<f64>("progress_interval")?;
+        let Some(callback) = callback else {
+            return match interval {
+                Some(_) => Err(to_argument_error("progress_interval requires on_progress callback".to_owned())),
+                None => Ok(None),
+            };
+        };
+        if !callback.respond_to("call", false)? {
+            return Err(to_argument_error(format!("on_progress must respond to call, got {}", callback.inspect())));
+        }
+        let interval = match interval {
+            None => DEFAULT_PROGRESS_INTERVAL,
+            Some(seconds) if seconds.is_finite() && seconds > 0.0 => Duration::from_secs_f64(seconds),
+            Some(seconds) => {
+                return Err(to_argument_error(format!("progress_interval must be positive number of seconds, got {}", seconds)))
+            }
+        };
+        Ok(Some(Self { callback, interval }))
+    }
+
+    fn report(&self, progress: QueryProgress) -> Result<(), magnus::Error> {
+        self.callback.funcall::<_, _, Value>(
+            "call",
+            (progress.percentage, progress.rows_processed, progress.total_rows_to_process),
+        )?;
+        Ok(())
+    }
+}
+
+// Runs statement(s) on the helper thread, while this ruby thread reports progress of the query running on
+// `connection` every `progress.interval` (and runs ruby functions the statement calls). Callback is called with GVL,
+// in between DuckDB keeps working and GVL is released.
+pub(crate) fn run_with_progress<F, R>(
+    connection: ConnectionHandle,
+    interrupt: &QueryInterrupt,
+    callbacks: &CallbackQueue,
+    helper: &HelperThread,
+    progress: &ProgressOptions,
+    statement: F,
+) -> Result<R, magnus::Error>
+where
+    F: FnOnce() -> R,
+{
+    let mut report = || progress.report(connection.progress());
+    let tick = Tick {
+        interval: progress.interval,
+        callback: &mut report,
+    };
+    callbacks::without_gvl_serving(callbacks, helper, interrupt, Some(tick), statement)
+}
@@ -0,0 +1,66 @@
+use std::ffi::CString;
+
+use duckdb::{ffi, Connection};
+
+pub(crate) struct QueryProgress {
+    // None when DuckDB can not estimate the progress (yet)
+    pub(crate) percentage: Option<f64>,
+    pub(crate) rows_processed: u64,
+    pub(crate) total_rows_to_process: u64,
+}
+
+// C API handle of a connection duckdb-rs opened, for what duckdb-rs has no API for: reading progress of the query
+// running on it and registering scalar and aggregate functions. duckdb-rs keeps owning the connection, handle must
+// not be used once it is closed.
+#[derive(Clone, Copy)]
+pub(crate) struct ConnectionHandle(ffi::duckdb_connection);
+
+// progress is read from ruby thread while the query runs on the helper thread, which DuckDB allows
+unsafe impl Send for ConnectionHandle {}
+unsafe impl Sync for ConnectionHandle {}
+
+impl ConnectionHandle {
+    pub(crate) fn of(connection: &Connection) -> Self {
+        Self(unsafe { connection.handle() })
+    }
+
+    pub(crate) fn raw(self) -> ffi::duckdb_connection {
+        self.0
+    }
+
+    pub(crate) fn progress(self) -> QueryProgress {
+        let progress = unsafe { ffi::duckdb_query_progress(self.0) };
+        QueryProgress {
+            percentage: Some(progress.percentage).filter(|percentage| *percentage >= 0.0),
+            rows_processed: progress.rows_processed,
+            total_rows_to_process: progress.total_rows_to_process,
+        }
+    }
+}
+
+// Logical type handle, DuckDB copies the type wherever it is used so it can be dropped right after
+pub(crate) struct RawLogicalType {
+    handle: ffi::duckdb_logical_type,
+}
+
+impl RawLogicalType {
+    pub(crate) fn new(duckdb_type: ffi::duckdb_type) -> Self {
+        Self {
+            handle: unsafe { ffi::duckdb_create_logical_type(duckdb_type) },
+        }
+    }
+
+    pub(crate) fn handle(&self) -> ffi::duckdb_logical_type {
+        self.handle
+    }
+}
+
+impl Drop for RawLogicalType {
+    fn drop(&mut self) {
+        unsafe { ffi::duckdb_destroy_logical_type(&mut self.handle) };
+    }
+}
+
+pub(crate) fn to_c_string(value: &str) -> Result<CString, String> {
+    CString::new(value).map_err(|_| format!("{:?} contains NUL byte", value))
+}
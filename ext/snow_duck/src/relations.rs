@@ -0,0 +1,145 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError,
+    },
+};
+
+use duckdb::{
+    core::{DataChunkHandle, LogicalTypeHandle, LogicalTypeId},
+    vtab::{BindInfo, InitInfo, TableFunctionInfo, VTab},
+    Connection,
+};
+use magnus::{RArray, Value};
+
+use crate::{
+    column_type::ColumnType,
+    conversions::{to_sql_identifier, to_sql_string_literal, to_standard_error},
+    table_functions, vector,
+};
+
+// Table function reading attached relation by its key, `FROM visits` reads temporary view
+// `visits AS SELECT * FROM snow_duck_relation('visits_1')` of the connection relation was attached to
+const RELATION_SCAN_FUNCTION: &str = "snow_duck_relation";
+
+// same as DuckDB's STANDARD_VECTOR_SIZE
+const RELATION_CHUNK_SIZE: usize = 2048;
+
+// Ruby rows converted to DuckDB values when attached, so they can be read from any DuckDB thread
+pub(crate) struct Relation {
+    columns: Vec<(String, ColumnType)>,
+    rows: Vec<Vec<duckdb::types::Value>>,
+}
+
+impl Relation {
+    pub(crate) fn from_ruby(columns: Vec<(String, ColumnType)>, rows: RArray) -> Result<Self, magnus::Error> {
+        let rows = (0..rows.len())
+            .map(|index| table_functions::row_from_ruby(&columns, rows.entry::<Value>(index as isize)?))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { columns, rows })
+    }
+}
+
+// Rows of relations attached by any connection of one database (the scan function is database-wide), by keys
+// unique to every attachment. Which name a relation is queried by is up to temporary views of the connection
+// it was attached to, so connections (e.g. threads of a Pool) can attach relations with the same name.
+#[derive(Default)]
+pub(crate) struct Relations {
+    relations: Mutex<HashMap<String, Arc<Relation>>>,
+    next_id: AtomicU64,
+}
+
+impl Relations {
+    fn relations(&self) -> MutexGuard<'_, HashMap<String, Arc<Relation>>> {
+        self.relations.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn get(&self, key: &str) -> Option<Arc<Relation>> {
+        self.relations().get(key).cloned()
+    }
+
+    // returns key the relation is scanned by
+    pub(crate) fn attach(&self, name: &str, relation: Relation) -> String {
+        let key = format!("{}_{}", name.to_lowercase(), self.next_id.fetch_add(1, Ordering::Relaxed));
+        self.relations().insert(key.clone(), Arc::new(relation));
+        key
+    }
+
+    // queries already reading the relation keep their own reference to its rows
+    pub(crate) fn detach(&self, key: &str) -> bool {
+        self.relations().remove(key).is_some()
+    }
+
+    // Registers the scan function, once per database
+    pub(crate) fn install(self: &Arc<Self>, connection: &Connection) -> Result<(), magnus::Error> {
+        connection
+            .register_table_function_with_extra_info::<RelationScanFunction, _>(RELATION_SCAN_FUNCTION, self)
+            .map_err(|err| to_standard_error(Box::new(err)))
+    }
+}
+
+// Temporary views are only visible to the connection that created them, and shadow tables of the same name
+pub(crate) fn create_view_statement(name: &str, key: &str) -> String {
+    format!(
+        "CREATE TEMPORARY VIEW {} AS SELECT * FROM {}({})",
+        to_sql_identifier(name),
+        RELATION_SCAN_FUNCTION,
+        to_sql_string_literal(key)
+    )
+}
+
+pub(crate) fn drop_view_statement(name: &str) -> String {
+    format!("DROP VIEW IF EXISTS temp.main.{}", to_sql_identifier(name))
+}
+
+#[derive(Default)]
+pub(crate) struct RelationScan {
+    next_row: AtomicUsize,
+}
+
+pub(crate) struct RelationScanFunction;
+
+impl VTab for RelationScanFunction {
+    type InitData = RelationScan;
+    type BindData = Arc<Relation>;
+
+    fn bind(bind: &BindInfo) -> Result<Self::BindData, Box<dyn Error>> {
+        let relations = unsafe { &*bind.get_extra_info::<Arc<Relations>>() };
+        let key = bind.get_parameter(0).to_string();
+        let relation = relations
+            .get(&key)
+            .ok_or_else(|| format!("Relation {} is not attached (anymore)", key))?;
+        for (column, column_type) in &relation.columns {
+            bind.add_result_column(column, LogicalTypeHandle::from(column_type.logical_type_id()));
+        }
+        Ok(relation)
+    }
+
+    fn init(_init: &InitInfo) -> Result<Self::InitData, Box<dyn Error>> {
+        Ok(RelationScan::default())
+    }
+
+    fn func(func: &TableFunctionInfo<Self>, output: &mut DataChunkHandle) -> Result<(), Box<dyn Error>> {
+        let relation = func.get_bind_data();
+        let start = func
+            .get_init_data()
+            .next_row
+            .fetch_add(RELATION_CHUNK_SIZE, Ordering::Relaxed)
+            .min(relation.rows.len());
+        let rows = &relation.rows[start..(start + RELATION_CHUNK_SIZE).min(relation.rows.len())];
+        for (column, (_, column_type)) in relation.columns.iter().enumerate() {
+            let mut vector = output.flat_vector(column);
+            for (row, values) in rows.iter().enumerate() {
+                vector::write_flat_value(&mut vector, *column_type, row, &values[column])?;
+            }
+        }
+        output.set_len(rows.len());
+        Ok(())
+    }
+
+    fn parameters() -> Option<Vec<LogicalTypeHandle>> {
+        Some(vec![LogicalTypeHandle::from(LogicalTypeId::Varchar)])
+    }
+}
@@ -0,0 +1,122 @@
+use std::collections::HashSet;
+
+use duckdb::arrow::datatypes::SchemaRef;
+use magnus::{RArray, Ruby, Value};
+
+use crate::{
+    conversions::to_argument_error,
+    errors,
+    options::{DuplicateColumns, HashKeys},
+};
+
+// Column metadata of a statement's result, computed once per statement instead of for every row.
+// `names` are the names DuckDB gives result columns, which do not have to be unique (`SELECT a.id, b.id`).
+// Hash keys are unique: duplicate names either raise SnowDuck::DuplicateColumnError, or (with
+// `duplicate_columns: :rename`) later columns get the first free `_1`, `_2`... suffix, so `id, id` becomes
+// keys `id, id_1`. Keys follow column order either way.
+pub(crate) struct ResultColumns {
+    names: Vec<String>,
+    // unique names keys are made of
+    key_names: Vec<String>,
+    // pluck_to_hash keys, in column order. Kept in ruby Array, so GC does not collect (dynamic) keys
+    // while rows are built; empty when rows are not hashes.
+    keys: RArray,
+    // Arrow schema of the result, when it is read as record batches
+    arrow_schema: Option<SchemaRef>,
+}
+
+impl ResultColumns {
+    // needs GVL, keys are ruby objects
+    pub(crate) fn new(names: Vec<String>, hash_keys: Option<HashKeys>) -> Result<Self, magnus::Error> {
+        let ruby = Ruby::get().expect("Ruby not initialized!");
+        let (key_names, keys) = match hash_keys {
+            Some(hash_keys) => {
+                let key_names = unique_key_names(&names, hash_keys.duplicates)?;
+                let keys = RArray::with_capacity(key_names.len());
+                for key_name in &key_names {
+                    keys.push(hash_keys.mode.key(ruby, key_name)?)?;
+                }
+                (key_names, keys)
+            }
+            None => (Vec::new(), RArray::new()),
+        };
+        Ok(Self {
+            names,
+            key_names,
+            keys,
+            arrow_schema: None,
+        })
+    }
+
+    pub(crate) fn with_arrow_schema(self, arrow_schema: SchemaRef) -> Self {
+        Self {
+            arrow_schema: Some(arrow_schema),
+            ..self
+        }
+    }
+
+    pub(crate) fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    pub(crate) fn key_names(&self) -> &[String] {
+        &self.key_names
+    }
+
+    pub(crate) fn keys(&self) -> &[Value] {
+        // keys Array is private and never changed after it is filled in, so its buffer stays where it is
+        unsafe { self.keys.as_slice() }
+    }
+
+    pub(crate) fn arrow_schema(&self) -> Option<&SchemaRef> {
+        self.arrow_schema.as_ref()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    // Indexes of columns with the given (key) names, in the order they are given; every column without names
+    pub(crate) fn positions(&self, selected: Option<&[String]>) -> Result<Vec<usize>, magnus::Error> {
+        let Some(selected) = selected else {
+            return Ok((0..self.len()).collect());
+        };
+        selected
+            .iter()
+            .map(|name| {
+                self.key_names.iter().position(|key_name| key_name == name).ok_or_else(|| {
+                    to_argument_error(format!("Result has no column {:?}, columns are {:?}", name, self.key_names))
+                })
+            })
+            .collect()
+    }
+}
+
+fn unique_key_names(names: &[String], duplicates: DuplicateColumns) -> Result<Vec<String>, magnus::Error> {
+    let mut taken: HashSet<String> = HashSet::with_capacity(names.len());
+    let mut key_names = Vec::with_capacity(names.len());
+    for (index, name) in names.iter().enumerate() {
+        if taken.insert(name.clone()) {
+            key_names.push(name.clone());
+            continue;
+        }
+        let renamed = match duplicates {
+            DuplicateColumns::Raise => {
+                return Err(errors::duplicate_column_error(format!(
+                    "Column {:?} appears more than once in the result (again as column {}), its values would \
+                     overwrite each other. Alias the columns or pass duplicate_columns: :rename",
+                    name,
+                    index + 1
+                )));
+            }
+            // suffixed name can not be one of the columns that come later either
+            DuplicateColumns::Rename => (1..)
+                .map(|suffix| format!("{}_{}", name, suffix))
+                .find(|candidate| !taken.contains(candidate) && !names.contains(candidate))
+                .expect("there is always a free suffix"),
+        };
+        taken.insert(renamed.clone());
+        key_names.push(renamed);
+    }
+    Ok(key_names)
+}
@@ -0,0 +1,171 @@
+use magnus::{prelude::*, KwArgs, RArray, RClass, RHash, Ruby, Symbol, Value};
+
+use crate::{
+    conversions::{to_argument_error, to_standard_error},
+    options::HashFlavour,
+    result_columns::ResultColumns,
+};
+
+// Builds one ruby object for every result row (pluck_to_hash, pluck_to, pluck_to_struct).
+// `value(index)` converts value of column `index` in the current row, builders pass it on to ruby right away
+// so converted values never wait in Rust memory, where GC would not see them.
+pub(crate) trait RowBuilder {
+    fn build(
+        &self,
+        columns: &ResultColumns,
+        value: &mut dyn FnMut(usize) -> Result<Value, magnus::Error>,
+    ) -> Result<Value, magnus::Error>;
+}
+
+// pluck_to_hash rows (and pluck_columns result) of the chosen flavour, filled in one pass (without converting
+// finished hashes)
+pub(crate) struct RowHashes {
+    flavour: HashFlavour,
+    // ActiveSupport::HashWithIndifferentAccess for indifferent rows
+    class: Option<RClass>,
+}
+
+impl RowHashes {
+    pub(crate) fn new(ruby: &Ruby, flavour: HashFlavour) -> Result<Self, magnus::Error> {
+        let class = match flavour {
+            HashFlavour::Indifferent => Some(
+                ruby.eval::<Option<RClass>>(
+                    "ActiveSupport::HashWithIndifferentAccess if defined?(ActiveSupport::HashWithIndifferentAccess)",
+                )?
+                .ok_or_else(|| to_standard_error("hash: :indifferent requires ActiveSupport".into()))?,
+            ),
+            HashFlavour::Plain | HashFlavour::Frozen => None,
+        };
+        Ok(Self { flavour, class })
+    }
+
+    // New hash of the flavour, with entries `fill` sets
+    pub(crate) fn hash_with(
+        &self,
+        fill: impl FnOnce(RHash) -> Result<(), magnus::Error>,
+    ) -> Result<RHash, magnus::Error> {
+        let hash = match self.class {
+            // RHash#aset skips HashWithIndifferentAccess#[]=, keys already are strings it would convert them to
+            Some(class) => RHash::from_value(class.new_instance(())?).expect("HashWithIndifferentAccess is a Hash"),
+            None => RHash::new(),
+        };
+        fill(hash)?;
+        if self.flavour == HashFlavour::Frozen {
+            hash.freeze();
+        }
+        Ok(hash)
+    }
+}
+
+impl RowBuilder for RowHashes {
+    fn build(
+        &self,
+        columns: &ResultColumns,
+        value: &mut dyn FnMut(usize) -> Result<Value, magnus::Error>,
+    ) -> Result<Value, magnus::Error> {
+        let hash = self.hash_with(|hash| {
+            for (index, key) in columns.keys().iter().enumerate() {
+                hash.aset(*key, value(index)?)?;
+            }
+            Ok(())
+        })?;
+        Ok(hash.as_value())
+    }
+}
+
+// pluck_to/pluck_to_struct rows
+pub(crate) enum RowObjects {
+    // Struct (and Data) classes take values of their members positionally, `columns[i]` is column of i-th member
+    Positional { class: RClass, columns: Vec<usize> },
+    // any other class (and keyword_init Structs) gets `klass.new(**row)`
+    Keywords(RClass),
+}
+
+impl RowObjects {
+    // Struct class generated for the result, members are the (unique) column names
+    pub(crate) fn generated_struct(ruby: &Ruby, columns: &ResultColumns) -> Result<Self, magnus::Error> {
+        let struct_class: RClass = ruby.class_object().const_get("Struct")?;
+        let row_class: RClass = struct_class.funcall("new", columns.keys())?;
+        Ok(Self::Positional {
+            class: row_class,
+            columns: (0..columns.key_names().len()).collect(),
+        })
+    }
+
+    pub(crate) fn for_class(class: RClass, columns: &ResultColumns) -> Result<Self, magnus::Error> {
+        if keyword_init_struct(class)? || !class.respond_to("members", false)? {
+            return Ok(Self::Keywords(class));
+        }
+        let members: Vec<Symbol> = class.funcall("members", ())?;
+        let member_names = members
+            .iter()
+            .map(|member| member.name().map(|name| name.into_owned()))
+            .collect::<Result<Vec<String>, _>>()?;
+        if let Some(column) = columns.key_names().iter().find(|column| !member_names.contains(column)) {
+            return Err(to_argument_error(format!(
+                "Result column {} is not a member of {}, members are {:?}",
+                column,
+                class.inspect(),
+                member_names
+            )));
+        }
+        let member_columns = member_names
+            .iter()
+            .map(|member| {
+                columns.key_names().iter().position(|column| column == member).ok_or_else(|| {
+                    to_argument_error(format!("Result has no column for {} member {}", class.inspect(), member))
+                })
+            })
+            .collect::<Result<Vec<usize>, _>>()?;
+        Ok(Self::Positional {
+            class,
+            columns: member_columns,
+        })
+    }
+}
+
+// Struct.keyword_init? only exists since ruby 3.1. Older ones keep keyword_init: option of Struct.new in hidden
+// `__keyword_init__` instance variable of the class Struct.new generated, subclasses look it up there.
+fn keyword_init_struct(class: RClass) -> Result<bool, magnus::Error> {
+    if class.respond_to("keyword_init?", false)? {
+        return Ok(class.funcall::<_, _, Value>("keyword_init?", ())?.to_bool());
+    }
+    let struct_class: RClass = Ruby::get().expect("Ruby not initialized!").class_object().const_get("Struct")?;
+    if !class.funcall::<_, _, Value>("<", (struct_class,))?.to_bool() {
+        return Ok(false);
+    }
+    let mut generated = class;
+    loop {
+        let superclass = generated.superclass()?;
+        if superclass.equal(struct_class)? {
+            return Ok(generated.ivar_get::<_, Value>("__keyword_init__")?.to_bool());
+        }
+        generated = superclass;
+    }
+}
+
+impl RowBuilder for RowObjects {
+    fn build(
+        &self,
+        columns: &ResultColumns,
+        value: &mut dyn FnMut(usize) -> Result<Value, magnus::Error>,
+    ) -> Result<Value, magnus::Error> {
+        match self {
+            Self::Positional { class, columns } => {
+                let arguments = RArray::with_capacity(columns.len());
+                for column in columns {
+                    arguments.push(value(*column)?)?;
+                }
+                // `arguments` is not changed (or exposed to ruby) while the slice is used
+                class.new_instance(unsafe { arguments.as_slice() })
+            }
+            Self::Keywords(class) => {
+                let keywords = RHash::new();
+                for (index, key) in columns.keys().iter().enumerate() {
+                    keywords.aset(*key, value(index)?)?;
+                }
+                class.new_instance((KwArgs(keywords),))
+            }
+        }
+    }
+}
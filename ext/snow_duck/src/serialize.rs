@@ -0,0 +1,486 @@
+use std::{error::Error, fmt::Write};
+
+use chrono::{DateTime, Local};
+use duckdb::{
+    arrow::{array::Array, record_batch::RecordBatch},
+    types::TimeUnit,
+};
+use magnus::Symbol;
+
+use crate::{
+    columnar::{self, ColumnReader, Values},
+    conversions::{self, to_argument_error},
+    options::{DuplicateColumns, Keywords},
+};
+
+// Query results written straight to CSV or JSON text, from Arrow record batches, without creating ruby objects
+// for the values. Values are formatted the way ruby formats values pluck_to_hash returns (with ActiveSupport):
+// CSV fields are their `to_s`, JSON values their `to_json`. Dates are `2024-03-01`, timestamps (which ruby reads
+// as local Time) are written in the local time zone with its offset, decimals are plain decimal strings and
+// intervals are whole seconds.
+// CSV fields of nested values (lists, structs, maps) and BLOBs are their JSON.
+
+type WriteResult = Result<(), Box<dyn Error>>;
+
+// pluck_to_csv(sql, col_sep: ',', row_sep: "\n", quote_char: '"', headers: true, force_quotes: false)
+pub(crate) struct CsvOptions {
+    col_sep: String,
+    row_sep: String,
+    quote_char: char,
+    // header line with column names
+    headers: bool,
+    force_quotes: bool,
+}
+
+impl CsvOptions {
+    pub(crate) fn from_keywords(keywords: &Keywords) -> Result<Self, magnus::Error> {
+        let col_sep = keywords.take::<String>("col_sep")?.unwrap_or_else(|| ",".to_owned());
+        let row_sep = keywords.take::<String>("row_sep")?.unwrap_or_else(|| "\n".to_owned());
+        let quote_char = keywords.take::<String>("quote_char")?.unwrap_or_else(|| "\"".to_owned());
+        let mut quote_chars = quote_char.chars();
+        let quote_char = match (quote_chars.next(), quote_chars.next()) {
+            (Some(quote_char), None) => quote_char,
+            _ => return Err(to_argument_error(format!("quote_char must be a single character, got {:?}", quote_char))),
+        };
+        if col_sep.is_empty() || row_sep.is_empty() {
+            return Err(to_argument_error("col_sep and row_sep can not be empty".to_owned()));
+        }
+        Ok(Self {
+            col_sep,
+            row_sep,
+            quote_char,
+            headers: keywords.take::<bool>("headers")?.unwrap_or(true),
+            force_quotes: keywords.take::<bool>("force_quotes")?.unwrap_or(false),
+        })
+    }
+
+    pub(crate) fn headers(&self) -> bool {
+        self.headers
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum JsonFormat {
+    // one JSON array of row objects
+    #[default]
+    Array,
+    // row object per line
+    NdJson,
+}
+
+// pluck_to_json(sql, format: :array, duplicate_columns: :raise)
+pub(crate) struct JsonOptions {
+    pub(crate) format: JsonFormat,
+    // rows are objects, so duplicate column names are handled the same way pluck_to_hash handles them
+    pub(crate) duplicate_columns: DuplicateColumns,
+}
+
+impl JsonOptions {
+    pub(crate) fn from_keywords(keywords: &Keywords) -> Result<Self, magnus::Error> {
+        let format = keywords
+            .take::<Symbol>("format")?
+            .map(|format| match format.name()?.as_ref() {
+                "array" => Ok(JsonFormat::Array),
+                "ndjson" => Ok(JsonFormat::NdJson),
+                name => Err(to_argument_error(format!("format must be :array or :ndjson, got :{}", name))),
+            })
+            .transpose()?
+            .unwrap_or_default();
+        Ok(Self {
+            format,
+            duplicate_columns: DuplicateColumns::from_keywords(keywords)?,
+        })
+    }
+}
+
+// CSV text of a result, header first (if enabled) and then record batches one after another
+pub(crate) struct CsvWriter<'o> {
+    options: &'o CsvOptions,
+    out: String,
+    // field being written, quoted (if needed) once it is complete
+    field: String,
+}
+
+impl<'o> CsvWriter<'o> {
+    pub(crate) fn new(options: &'o CsvOptions) -> Self {
+        Self {
+            options,
+            out: String::new(),
+            field: String::new(),
+        }
+    }
+
+    pub(crate) fn write_header(&mut self, names: &[String]) {
+        for (index, name) in names.iter().enumerate() {
+            if index > 0 {
+                self.out.push_str(&self.options.col_sep);
+            }
+            self.field.clear();
+            self.field.push_str(name);
+            self.push_field(false);
+        }
+        self.out.push_str(&self.options.row_sep);
+    }
+
+    pub(crate) fn write_batch(&mut self, batch: &RecordBatch) -> WriteResult {
+        let readers = columnar::batch_readers(batch);
+        for row in 0..batch.num_rows() {
+            for (index, reader) in readers.iter().enumerate() {
+                if index > 0 {
+                    self.out.push_str(&self.options.col_sep);
+                }
+                // NULL is an empty field, empty string is a quoted one
+                if reader.is_null(row) {
+                    continue;
+                }
+                self.field.clear();
+                write_text(&mut self.field, reader, row)?;
+                self.push_field(true);
+            }
+            self.out.push_str(&self.options.row_sep);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn finish(self) -> String {
+        self.out
+    }
+
+    // same quoting ruby's CSV does, fields are quoted when they would not read back otherwise
+    fn push_field(&mut self, quote_empty: bool) {
+        let quote_char = self.options.quote_char;
+        let quote = self.options.force_quotes
+            || (quote_empty && self.field.is_empty())
+            || self.field.contains(quote_char)
+            || self.field.contains(self.options.col_sep.as_str())
+            || self.field.contains(self.options.row_sep.as_str())
+            || self.field.contains(['\r', '\n']);
+        if !quote {
+            self.out.push_str(&self.field);
+            return;
+        }
+        self.out.push(quote_char);
+        for character in self.field.chars() {
+            if character == quote_char {
+                self.out.push(quote_char);
+            }
+            self.out.push(character);
+        }
+        self.out.push(quote_char);
+    }
+}
+
+// JSON text of a result, rows are objects keyed by (unique) column names
+pub(crate) struct JsonWriter {
+    format: JsonFormat,
+    keys: Vec<String>,
+    out: String,
+    rows: usize,
+}
+
+impl JsonWriter {
+    pub(crate) fn new(format: JsonFormat, keys: Vec<String>) -> Self {
+        let mut out = String::new();
+        if format == JsonFormat::Array {
+            out.push('[');
+        }
+        Self {
+            format,
+            keys,
+            out,
+            rows: 0,
+        }
+    }
+
+    pub(crate) fn write_batch(&mut self, batch: &RecordBatch) -> WriteResult {
+        let readers = columnar::batch_readers(batch);
+        for row in 0..batch.num_rows() {
+            if self.format == JsonFormat::Array && self.rows > 0 {
+                self.out.push(',');
+            }
+            self.out.push('{');
+            for (index, (key, reader)) in self.keys.iter().zip(&readers).enumerate() {
+                if index > 0 {
+                    self.out.push(',');
+                }
+                write_json_string(&mut self.out, key);
+                self.out.push(':');
+                write_json(&mut self.out, reader, row)?;
+            }
+            self.out.push('}');
+            if self.format == JsonFormat::NdJson {
+                self.out.push('\n');
+            }
+            self.rows += 1;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn finish(mut self) -> String {
+        if self.format == JsonFormat::Array {
+            self.out.push(']');
+        }
+        self.out
+    }
+}
+
+// Value as JSON, the way ActiveSupport encodes value ruby conversion returns
+fn write_json(out: &mut String, reader: &ColumnReader, row: usize) -> WriteResult {
+    if reader.is_null(row) {
+        out.push_str("null");
+        return Ok(());
+    }
+    match reader.values() {
+        Values::Float32(values) => write_json_float(out, f64::from(values[row])),
+        Values::Float64(values) => write_json_float(out, values[row]),
+        Values::Time(values, unit) => {
+            let mut text = String::new();
+            write_time(&mut text, values[row], *unit, JSON_TIME_FORMAT)?;
+            write_json_string(out, &text);
+        }
+        // BigDecimal#as_json is a string
+        Values::Decimal(..) | Values::Utf8(_) | Values::LargeUtf8(_) | Values::Date32(_) | Values::Enum(..) => {
+            let mut text = String::new();
+            write_text(&mut text, reader, row)?;
+            write_json_string(out, &text);
+        }
+        Values::Binary(array) => write_json_bytes(out, array.value(row)),
+        Values::LargeBinary(array) => write_json_bytes(out, array.value(row)),
+        Values::List(array) => write_json_array(out, reader, array.value(row).as_ref())?,
+        Values::LargeList(array) => write_json_array(out, reader, array.value(row).as_ref())?,
+        Values::FixedSizeList(array) => write_json_array(out, reader, array.value(row).as_ref())?,
+        Values::Struct(array) => {
+            out.push('{');
+            for (index, (field, column)) in array.fields().iter().zip(array.columns()).enumerate() {
+                if index > 0 {
+                    out.push(',');
+                }
+                write_json_string(out, field.name());
+                out.push(':');
+                write_json(out, &reader.child(index, column.as_ref()), row)?;
+            }
+            out.push('}');
+        }
+        Values::Map(array) => {
+            let entries = array.value(row);
+            let (keys, values) = (reader.child(0, entries.column(0).as_ref()), reader.child(1, entries.column(1).as_ref()));
+            out.push('{');
+            let mut key = String::new();
+            for entry in 0..entries.len() {
+                if entry > 0 {
+                    out.push(',');
+                }
+                // JSON keys are strings, ActiveSupport uses key's to_s
+                key.clear();
+                write_text(&mut key, &keys, entry)?;
+                write_json_string(out, &key);
+                out.push(':');
+                write_json(out, &values, entry)?;
+            }
+            out.push('}');
+        }
+        Values::Union(array) => {
+            let type_id = array.type_id(row);
+            let child = array.child(type_id);
+            write_json(out, &reader.child(type_id as usize, child.as_ref()), array.value_offset(row))?;
+        }
+        // booleans, integers and intervals are the same in JSON as in text
+        _ => write_text(out, reader, row)?,
+    }
+    Ok(())
+}
+
+// Value as text, the way ruby's `to_s` formats value ruby conversion returns. Nested values are their JSON.
+fn write_text(out: &mut String, reader: &ColumnReader, row: usize) -> WriteResult {
+    if reader.is_null(row) {
+        return Ok(());
+    }
+    match reader.values() {
+        Values::Boolean(array) => out.push_str(if array.value(row) { "true" } else { "false" }),
+        Values::Int8(values) => write!(out, "{}", values[row])?,
+        Values::Int16(values) => write!(out, "{}", values[row])?,
+        Values::Int32(values) => write!(out, "{}", values[row])?,
+        Values::Int64(values) => write!(out, "{}", values[row])?,
+        Values::UInt8(values) => write!(out, "{}", values[row])?,
+        Values::UInt16(values) => write!(out, "{}", values[row])?,
+        Values::UInt32(values) => write!(out, "{}", values[row])?,
+        Values::UInt64(values) => write!(out, "{}", values[row])?,
+        Values::Float32(values) => write_ruby_float(out, f64::from(values[row])),
+        Values::Float64(values) => write_ruby_float(out, values[row]),
+        Values::HugeInt(values) => write!(out, "{}", values[row])?,
+        Values::Decimal(values, scale) => write_decimal(out, values[row], *scale),
+        Values::Utf8(array) => out.push_str(array.value(row)),
+        Values::LargeUtf8(array) => out.push_str(array.value(row)),
+        Values::Date32(days) => write!(out, "{}", conversions::duck_date(days[row]).format("%Y-%m-%d"))?,
+        Values::Time(values, unit) => write_time(out, values[row], *unit, TEXT_TIME_FORMAT)?,
+        Values::Interval(values) => {
+            let interval = values[row];
+            let seconds = conversions::duck_interval_seconds(interval.months, interval.days, interval.nanoseconds);
+            write!(out, "{}", seconds)?
+        }
+        Values::Enum(keys, names) => out.push_str(names.value(keys[row])),
+        Values::Union(array) => {
+            let type_id = array.type_id(row);
+            let child = array.child(type_id);
+            write_text(out, &reader.child(type_id as usize, child.as_ref()), array.value_offset(row))?;
+        }
+        Values::Null => {}
+        Values::Unsupported(data_type) => return Err(format!("Unsupported result column type {}", data_type).into()),
+        Values::Binary(_)
+        | Values::LargeBinary(_)
+        | Values::List(_)
+        | Values::LargeList(_)
+        | Values::FixedSizeList(_)
+        | Values::Struct(_)
+        | Values::Map(_) => write_json(out, reader, row)?,
+    }
+    Ok(())
+}
+
+// items of one list value of `list` column
+fn write_json_array(out: &mut String, list: &ColumnReader, items: &dyn Array) -> WriteResult {
+    let reader = list.child(0, items);
+    out.push('[');
+    for row in 0..items.len() {
+        if row > 0 {
+            out.push(',');
+        }
+        write_json(out, &reader, row)?;
+    }
+    out.push(']');
+    Ok(())
+}
+
+// BLOBs are arrays of bytes in ruby
+fn write_json_bytes(out: &mut String, bytes: &[u8]) {
+    out.push('[');
+    for (index, byte) in bytes.iter().enumerate() {
+        if index > 0 {
+            out.push(',');
+        }
+        let _ = write!(out, "{}", byte);
+    }
+    out.push(']');
+}
+
+fn write_json_string(out: &mut String, value: &str) {
+    out.push('"');
+    for character in value.chars() {
+        match character {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\u{08}' => out.push_str("\\b"),
+            '\u{0c}' => out.push_str("\\f"),
+            character if character < ' ' => {
+                let _ = write!(out, "\\u{:04x}", character as u32);
+            }
+            character => out.push(character),
+        }
+    }
+    out.push('"');
+}
+
+// ActiveSupport encodes NaN and infinities as null
+fn write_json_float(out: &mut String, value: f64) {
+    if value.is_finite() {
+        write_ruby_float(out, value);
+    } else {
+        out.push_str("null");
+    }
+}
+
+// Float#to_s: shortest digits that read back the same value, in decimal notation for exponents -4..16,
+// otherwise as `1.0e+16`
+fn write_ruby_float(out: &mut String, value: f64) {
+    if value.is_nan() {
+        out.push_str("NaN");
+        return;
+    }
+    if value.is_infinite() {
+        out.push_str(if value > 0.0 { "Infinity" } else { "-Infinity" });
+        return;
+    }
+    if value.is_sign_negative() {
+        out.push('-');
+    }
+    if value == 0.0 {
+        out.push_str("0.0");
+        return;
+    }
+    // `{:e}` is the shortest representation, e.g. 1.25e-5
+    let formatted = format!("{:e}", value.abs());
+    let (mantissa, exponent) = formatted.split_once('e').expect("exponent format has an exponent");
+    let exponent: i32 = exponent.parse().expect("exponent is a number");
+    let digits = mantissa.replace('.', "");
+    if !(-4..16).contains(&exponent) {
+        let fraction = if digits.len() > 1 { &digits[1..] } else { "0" };
+        let _ = write!(
+            out,
+            "{}.{}e{}{:02}",
+            &digits[..1],
+            fraction,
+            if exponent < 0 { '-' } else { '+' },
+            exponent.abs()
+        );
+    } else if exponent < 0 {
+        out.push_str("0.");
+        out.push_str(&"0".repeat((-exponent - 1) as usize));
+        out.push_str(&digits);
+    } else {
+        let integer_digits = exponent as usize + 1;
+        if digits.len() <= integer_digits {
+            out.push_str(&digits);
+            out.push_str(&"0".repeat(integer_digits - digits.len()));
+            out.push_str(".0");
+        } else {
+            out.push_str(&digits[..integer_digits]);
+            out.push('.');
+            out.push_str(&digits[integer_digits..]);
+        }
+    }
+}
+
+// BigDecimal#to_s of ActiveSupport, plain notation without trailing zeros: 1.5, 100.0, 0.05
+fn write_decimal(out: &mut String, value: i128, scale: i8) {
+    if value < 0 {
+        out.push('-');
+    }
+    let digits = value.unsigned_abs().to_string();
+    if scale <= 0 {
+        out.push_str(&digits);
+        if value != 0 {
+            out.push_str(&"0".repeat(scale.unsigned_abs() as usize));
+        }
+        out.push_str(".0");
+        return;
+    }
+    let scale = scale as usize;
+    let digits = format!("{:0>width$}", digits, width = scale + 1);
+    let (integer, fraction) = digits.split_at(digits.len() - scale);
+    let fraction = fraction.trim_end_matches('0');
+    out.push_str(integer);
+    out.push('.');
+    out.push_str(if fraction.is_empty() { "0" } else { fraction });
+}
+
+// Time#to_s of the local time pluck returns (Time.at), with offset of the process time zone
+const TEXT_TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S %z";
+// Time#as_json of the same time, xmlschema(3): ISO 8601 with milliseconds and the offset
+const JSON_TIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.3f%:z";
+
+fn write_time(out: &mut String, value: i64, unit: TimeUnit, format: &str) -> WriteResult {
+    let (per_second, nanos_per_unit) = match unit {
+        TimeUnit::Second => (1, 1_000_000_000),
+        TimeUnit::Millisecond => (1_000, 1_000_000),
+        TimeUnit::Microsecond => (1_000_000, 1_000),
+        TimeUnit::Nanosecond => (1_000_000_000, 1),
+    };
+    let seconds = value.div_euclid(per_second);
+    let nanos = (value.rem_euclid(per_second) * nanos_per_unit) as u32;
+    let time = DateTime::from_timestamp(seconds, nanos).ok_or_else(|| format!("Time {} is out of range", value))?;
+    write!(out, "{}", time.with_timezone(&Local).format(format))?;
+    Ok(())
+}
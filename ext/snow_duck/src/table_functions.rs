@@ -0,0 +1,237 @@
+use std::{
+    error::Error,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    thread::{self, ThreadId},
+};
+
+use duckdb::{
+    core::{DataChunkHandle, LogicalTypeHandle},
+    vtab::{BindInfo, InitInfo, TableFunctionInfo, VTab},
+    Connection,
+};
+use magnus::{prelude::*, value::Opaque, RArray, RHash, Ruby, Symbol, Value};
+
+use crate::{
+    callbacks::CallbackQueue,
+    column_type::ColumnType,
+    conversions::{self, to_argument_error, to_standard_error},
+    functions::{PinnedValues, RubyFunctions},
+    vector,
+};
+
+// DuckDB's STANDARD_VECTOR_SIZE, table function outputs at most this many rows at once
+const TABLE_FUNCTION_CHUNK_SIZE: usize = 2048;
+
+// Table function schema, like { id: :integer, species: :varchar }
+pub(crate) fn table_schema_from_ruby(schema: RHash) -> Result<Vec<(String, ColumnType)>, magnus::Error> {
+    let columns = schema
+        .to_vec::<Value, Value>()?
+        .into_iter()
+        .map(|(name, column_type)| -> Result<(String, ColumnType), magnus::Error> {
+            Ok((conversions::identifier_from_ruby(name, "Column name")?, ColumnType::from_ruby(column_type)?))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if columns.is_empty() {
+        return Err(to_argument_error("Schema needs at least one column".to_owned()));
+    }
+    Ok(columns)
+}
+
+// rows are arrays in schema order, or hashes with column names as (symbol or string) keys
+pub(crate) fn row_from_ruby(columns: &[(String, ColumnType)], row: Value) -> Result<Vec<duckdb::types::Value>, magnus::Error> {
+    if let Some(hash) = RHash::from_value(row) {
+        return columns
+            .iter()
+            .map(|(name, column_type)| {
+                let value = hash
+                    .get(Symbol::new(name))
+                    .or_else(|| hash.get(name.as_str()))
+                    .unwrap_or_else(|| magnus::value::qnil().as_value());
+                conversions::ruby_to_duck(value, *column_type)
+            })
+            .collect();
+    }
+    let values = RArray::from_value(row).ok_or_else(|| {
+        magnus::Error::new(
+            magnus::exception::type_error(),
+            format!("Rows have to be Arrays or Hashes, got {}", row.inspect()),
+        )
+    })?;
+    if values.len() != columns.len() {
+        return Err(to_argument_error(format!(
+            "Row has to have {} values, got {}",
+            columns.len(),
+            row.inspect()
+        )));
+    }
+    columns
+        .iter()
+        .enumerate()
+        .map(|(index, (_, column_type))| conversions::ruby_to_duck(values.entry(index as isize)?, *column_type))
+        .collect()
+}
+
+pub(crate) struct TableFunctionSource {
+    // called for every scan of the function, returns Enumerator of rows
+    enumerator_factory: Opaque<Value>,
+    columns: Vec<(String, ColumnType)>,
+    callbacks: Arc<CallbackQueue>,
+    pinned: Arc<PinnedValues>,
+}
+
+impl TableFunctionSource {
+    // Runs on ruby thread. Enumerator is created on the first pull of the scan, and pinned until scan is done.
+    fn pull(
+        &self,
+        ruby: &Ruby,
+        enumerator_id: Option<u64>,
+    ) -> Result<(Option<u64>, Vec<Vec<duckdb::types::Value>>), magnus::Error> {
+        let (enumerator, created_id) = match enumerator_id {
+            Some(id) => {
+                let enumerator = self
+                    .pinned
+                    .get(ruby, id)
+                    .ok_or_else(|| to_standard_error("Enumerator of the table function is gone".into()))?;
+                (enumerator, None)
+            }
+            None => {
+                let enumerator: Value = ruby.get_inner(self.enumerator_factory).funcall("call", ())?;
+                (enumerator, Some(self.pinned.pin(enumerator)))
+            }
+        };
+        match self.next_rows(enumerator) {
+            Ok(rows) => Ok((created_id, rows)),
+            Err(error) => {
+                // scan never learns about enumerator it could not read from
+                if let Some(id) = created_id {
+                    self.pinned.unpin(id);
+                }
+                Err(error)
+            }
+        }
+    }
+
+    // next chunk of rows, empty once enumerator is done
+    fn next_rows(&self, enumerator: Value) -> Result<Vec<Vec<duckdb::types::Value>>, magnus::Error> {
+        let mut rows = Vec::with_capacity(TABLE_FUNCTION_CHUNK_SIZE);
+        while rows.len() < TABLE_FUNCTION_CHUNK_SIZE {
+            let row: Value = match enumerator.funcall("next", ()) {
+                Ok(row) => row,
+                Err(error) if error.is_kind_of(magnus::exception::stop_iteration()) => break,
+                Err(error) => return Err(error),
+            };
+            rows.push(row_from_ruby(&self.columns, row)?);
+        }
+        Ok(rows)
+    }
+}
+
+// Enumerator of a scan. Fibers of enumerators can not be resumed from other threads,
+// so it is only ever read from the ruby thread that created it.
+struct ScanEnumerator {
+    id: u64,
+    thread: ThreadId,
+    pinned: Arc<PinnedValues>,
+}
+
+impl Drop for ScanEnumerator {
+    fn drop(&mut self) {
+        self.pinned.unpin(self.id);
+    }
+}
+
+#[derive(Default)]
+struct ScanState {
+    enumerator: Option<ScanEnumerator>,
+    finished: bool,
+}
+
+#[derive(Default)]
+pub(crate) struct TableScan {
+    state: Mutex<ScanState>,
+}
+
+impl TableScan {
+    fn state(&self) -> MutexGuard<'_, ScanState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+pub(crate) struct RubyTableFunction;
+
+impl VTab for RubyTableFunction {
+    type InitData = TableScan;
+    type BindData = Arc<TableFunctionSource>;
+
+    fn bind(bind: &BindInfo) -> Result<Self::BindData, Box<dyn Error>> {
+        let source = unsafe { &*bind.get_extra_info::<Arc<TableFunctionSource>>() }.clone();
+        for (name, column_type) in &source.columns {
+            bind.add_result_column(name, LogicalTypeHandle::from(column_type.logical_type_id()));
+        }
+        Ok(source)
+    }
+
+    fn init(_init: &InitInfo) -> Result<Self::InitData, Box<dyn Error>> {
+        Ok(TableScan::default())
+    }
+
+    // Runs on DuckDB thread, pulls next chunk of rows from ruby
+    fn func(func: &TableFunctionInfo<Self>, output: &mut DataChunkHandle) -> Result<(), Box<dyn Error>> {
+        let source = func.get_bind_data().clone();
+        let mut state = func.get_init_data().state();
+        if state.finished {
+            output.set_len(0);
+            return Ok(());
+        }
+        let enumerator = state.enumerator.as_ref().map(|enumerator| (enumerator.id, enumerator.thread));
+        let puller = source.clone();
+        let (created_id, rows) = source.callbacks.call_ruby_on(enumerator.map(|(_, thread)| thread), move |ruby| {
+            puller
+                .pull(ruby, enumerator.map(|(id, _)| id))
+                .map(|(created_id, rows)| (created_id.map(|id| (id, thread::current().id())), rows))
+                .map_err(|error| format!("Ruby table function failed: {}", error))
+        })?;
+        if let Some((id, thread)) = created_id {
+            state.enumerator = Some(ScanEnumerator {
+                id,
+                thread,
+                pinned: source.pinned.clone(),
+            });
+        }
+        if rows.is_empty() {
+            // enumerator is not needed anymore, GC can have it before scan itself is dropped
+            state.finished = true;
+            state.enumerator = None;
+        }
+        for (column, (_, column_type)) in source.columns.iter().enumerate() {
+            let mut vector = output.flat_vector(column);
+            for (row, values) in rows.iter().enumerate() {
+                vector::write_flat_value(&mut vector, *column_type, row, &values[column])?;
+            }
+        }
+        output.set_len(rows.len());
+        Ok(())
+    }
+}
+
+impl RubyFunctions {
+    pub(crate) fn create_table_function(
+        &self,
+        connection: &Connection,
+        name: &str,
+        columns: Vec<(String, ColumnType)>,
+        enumerator_factory: Value,
+    ) -> Result<(), magnus::Error> {
+        let source = Arc::new(TableFunctionSource {
+            enumerator_factory: Opaque::from(enumerator_factory),
+            columns,
+            callbacks: self.callback_queue(),
+            pinned: self.pinned_values(),
+        });
+        connection
+            .register_table_function_with_extra_info::<RubyTableFunction, _>(name, &source)
+            .map_err(|err| to_standard_error(Box::new(err)))?;
+        self.remember(enumerator_factory);
+        Ok(())
+    }
+}
@@ -0,0 +1,139 @@
+use std::{ffi::c_char, slice};
+
+use duckdb::{
+    core::{FlatVector, Inserter},
+    ffi,
+    types::{TimeUnit, Value},
+};
+
+use crate::column_type::ColumnType;
+
+// Reading and writing cells of DuckDB vectors handed to ruby functions through C API.
+// C API only deals with flat vectors, callers have to make sure `row` is within the chunk
+// and that the vector really holds `column_type` values.
+
+pub(crate) unsafe fn read_value(vector: ffi::duckdb_vector, column_type: ColumnType, row: usize) -> Value {
+    let validity = ffi::duckdb_vector_get_validity(vector);
+    // NULL validity mask means all rows are valid
+    if !validity.is_null() && !ffi::duckdb_validity_row_is_valid(validity, row as ffi::idx_t) {
+        return Value::Null;
+    }
+    let data = ffi::duckdb_vector_get_data(vector);
+    match column_type {
+        ColumnType::Boolean => Value::Boolean(*(data as *const bool).add(row)),
+        ColumnType::TinyInt => Value::TinyInt(*(data as *const i8).add(row)),
+        ColumnType::SmallInt => Value::SmallInt(*(data as *const i16).add(row)),
+        ColumnType::Integer => Value::Int(*(data as *const i32).add(row)),
+        ColumnType::BigInt => Value::BigInt(*(data as *const i64).add(row)),
+        ColumnType::UTinyInt => Value::UTinyInt(*(data as *const u8).add(row)),
+        ColumnType::USmallInt => Value::USmallInt(*(data as *const u16).add(row)),
+        ColumnType::UInteger => Value::UInt(*(data as *const u32).add(row)),
+        ColumnType::UBigInt => Value::UBigInt(*(data as *const u64).add(row)),
+        ColumnType::Float => Value::Float(*(data as *const f32).add(row)),
+        ColumnType::Double => Value::Double(*(data as *const f64).add(row)),
+        ColumnType::Varchar => Value::Text(String::from_utf8_lossy(string_bytes(data, row)).into_owned()),
+        ColumnType::Blob => Value::Blob(string_bytes(data, row).to_vec()),
+        // days since epoch
+        ColumnType::Date => Value::Date32(*(data as *const i32).add(row)),
+        // microseconds since epoch
+        ColumnType::Timestamp => Value::Timestamp(TimeUnit::Microsecond, *(data as *const i64).add(row)),
+    }
+}
+
+// Rows of function arguments in `chunk`, None for rows where any argument is NULL
+// (functions skip those, same as built in DuckDB functions do)
+pub(crate) unsafe fn read_arguments(chunk: ffi::duckdb_data_chunk, argument_types: &[ColumnType]) -> Vec<Option<Vec<Value>>> {
+    let row_count = ffi::duckdb_data_chunk_get_size(chunk) as usize;
+    let vectors: Vec<ffi::duckdb_vector> = (0..argument_types.len())
+        .map(|column| ffi::duckdb_data_chunk_get_vector(chunk, column as ffi::idx_t))
+        .collect();
+    (0..row_count)
+        .map(|row| {
+            let arguments: Vec<Value> = vectors
+                .iter()
+                .zip(argument_types)
+                .map(|(vector, argument_type)| read_value(*vector, *argument_type, row))
+                .collect();
+            Some(arguments).filter(|arguments| !arguments.iter().any(|argument| matches!(argument, Value::Null)))
+        })
+        .collect()
+}
+
+unsafe fn string_bytes<'a>(data: *mut std::ffi::c_void, row: usize) -> &'a [u8] {
+    let string = (data as *mut ffi::duckdb_string_t).add(row);
+    let length = ffi::duckdb_string_t_length(*string) as usize;
+    slice::from_raw_parts(ffi::duckdb_string_t_data(string) as *const u8, length)
+}
+
+// `value` is expected to be what conversions::ruby_to_duck produced for `column_type`
+pub(crate) unsafe fn write_value(
+    vector: ffi::duckdb_vector,
+    column_type: ColumnType,
+    row: usize,
+    value: &Value,
+) -> Result<(), String> {
+    let data = ffi::duckdb_vector_get_data(vector);
+    match (column_type, value) {
+        (_, Value::Null) => set_null(vector, row),
+        (ColumnType::Boolean, Value::Boolean(value)) => *(data as *mut bool).add(row) = *value,
+        (ColumnType::TinyInt, Value::TinyInt(value)) => *(data as *mut i8).add(row) = *value,
+        (ColumnType::SmallInt, Value::SmallInt(value)) => *(data as *mut i16).add(row) = *value,
+        (ColumnType::Integer, Value::Int(value)) => *(data as *mut i32).add(row) = *value,
+        (ColumnType::BigInt, Value::BigInt(value)) => *(data as *mut i64).add(row) = *value,
+        (ColumnType::UTinyInt, Value::UTinyInt(value)) => *(data as *mut u8).add(row) = *value,
+        (ColumnType::USmallInt, Value::USmallInt(value)) => *(data as *mut u16).add(row) = *value,
+        (ColumnType::UInteger, Value::UInt(value)) => *(data as *mut u32).add(row) = *value,
+        (ColumnType::UBigInt, Value::UBigInt(value)) => *(data as *mut u64).add(row) = *value,
+        (ColumnType::Float, Value::Float(value)) => *(data as *mut f32).add(row) = *value,
+        (ColumnType::Double, Value::Double(value)) => *(data as *mut f64).add(row) = *value,
+        (ColumnType::Varchar, Value::Text(text)) => assign_string(vector, row, text.as_bytes()),
+        (ColumnType::Blob, Value::Blob(bytes)) => assign_string(vector, row, bytes),
+        (ColumnType::Date, Value::Date32(days)) => *(data as *mut i32).add(row) = *days,
+        (ColumnType::Timestamp, Value::Timestamp(TimeUnit::Microsecond, micros)) => *(data as *mut i64).add(row) = *micros,
+        (column_type, value) => {
+            return Err(format!("Can not write {:?} as {} value", value, column_type.sql_name()));
+        }
+    }
+    Ok(())
+}
+
+// Same as `write_value`, for output vectors of table functions (duckdb-rs vtab)
+pub(crate) fn write_flat_value(vector: &mut FlatVector, column_type: ColumnType, row: usize, value: &Value) -> Result<(), String> {
+    match (column_type, value) {
+        (_, Value::Null) => vector.set_null(row),
+        (ColumnType::Boolean, Value::Boolean(value)) => vector.as_mut_slice::<bool>()[row] = *value,
+        (ColumnType::TinyInt, Value::TinyInt(value)) => vector.as_mut_slice::<i8>()[row] = *value,
+        (ColumnType::SmallInt, Value::SmallInt(value)) => vector.as_mut_slice::<i16>()[row] = *value,
+        (ColumnType::Integer, Value::Int(value)) => vector.as_mut_slice::<i32>()[row] = *value,
+        (ColumnType::BigInt, Value::BigInt(value)) => vector.as_mut_slice::<i64>()[row] = *value,
+        (ColumnType::UTinyInt, Value::UTinyInt(value)) => vector.as_mut_slice::<u8>()[row] = *value,
+        (ColumnType::USmallInt, Value::USmallInt(value)) => vector.as_mut_slice::<u16>()[row] = *value,
+        (ColumnType::UInteger, Value::UInt(value)) => vector.as_mut_slice::<u32>()[row] = *value,
+        (ColumnType::UBigInt, Value::UBigInt(value)) => vector.as_mut_slice::<u64>()[row] = *value,
+        (ColumnType::Float, Value::Float(value)) => vector.as_mut_slice::<f32>()[row] = *value,
+        (ColumnType::Double, Value::Double(value)) => vector.as_mut_slice::<f64>()[row] = *value,
+        (ColumnType::Varchar, Value::Text(text)) => vector.insert(row, text.as_str()),
+        (ColumnType::Blob, Value::Blob(bytes)) => vector.insert(row, bytes.as_slice()),
+        (ColumnType::Date, Value::Date32(days)) => vector.as_mut_slice::<i32>()[row] = *days,
+        (ColumnType::Timestamp, Value::Timestamp(TimeUnit::Microsecond, micros)) => vector.as_mut_slice::<i64>()[row] = *micros,
+        (column_type, value) => {
+            return Err(format!("Can not write {:?} as {} value", value, column_type.sql_name()));
+        }
+    }
+    Ok(())
+}
+
+pub(crate) unsafe fn set_null(vector: ffi::duckdb_vector, row: usize) {
+    ffi::duckdb_vector_ensure_validity_writable(vector);
+    ffi::duckdb_validity_set_row_invalid(ffi::duckdb_vector_get_validity(vector), row as ffi::idx_t);
+}
+
+// DuckDB copies string data into the vector
+unsafe fn assign_string(vector: ffi::duckdb_vector, row: usize, bytes: &[u8]) {
+    ffi::duckdb_vector_assign_string_element_len(
+        vector,
+        row as ffi::idx_t,
+        bytes.as_ptr() as *const c_char,
+        bytes.len() as ffi::idx_t,
+    );
+}
@@ -7,6 +7,11 @@ require_relative 'snow_duck/ddl/postgres_table'
 require_relative 'snow_duck/ddl/snowflake_table'
 require_relative 'snow_duck/ddl/view'
 require_relative 'snow_duck/snow_duck'
+require_relative 'snow_duck/duck_database/pool'
+require_relative 'snow_duck/duck_database/transactions'
+require_relative 'snow_duck/duck_database/table_functions'
+require_relative 'snow_duck/duck_database/relations'
+require_relative 'snow_duck/duck_database/packed_columns'
 require_relative 'snow_duck/utils/data_initialisation'
 
 module SnowDuck
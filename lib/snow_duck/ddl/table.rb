@@ -8,6 +8,9 @@ module SnowDuck
       include SnowDuck::DDL::EmbeddableData
       include SnowDuck::Utils::Logger
 
+      # seconds between two progress log lines while ingesting remote data
+      INGESTION_PROGRESS_INTERVAL = 10
+
       attr_reader :ddl_query, :options, :connection_provider, :s3_bucket
 
       def initialize(options)
@@ -31,9 +34,9 @@ module SnowDuck
               # Lets override whatever gets autodetected with explicit types -> this is especially important when file is empty, then it is assumed that every column is VARCHAR
               # https://duckdb.org/docs/stable/data/csv/tips#override-the-types-of-specific-columns
               json_types = column_definitions.to_json
-              database.execute_batch("LOAD aws; LOAD httpfs; CREATE TABLE #{table_name} AS SELECT * FROM read_csv('#{remote_file_location}', types = #{json_types});")
+              database.execute_batch("LOAD aws; LOAD httpfs; CREATE TABLE #{table_name} AS SELECT * FROM read_csv('#{remote_file_location}', types = #{json_types});", **ingestion_progress_options)
             elsif remote_file_type == 'parquet'
-              database.execute_batch("LOAD aws; LOAD httpfs; CREATE TABLE #{table_name} AS SELECT * FROM read_parquet('#{remote_file_location}');")
+              database.execute_batch("LOAD aws; LOAD httpfs; CREATE TABLE #{table_name} AS SELECT * FROM read_parquet('#{remote_file_location}');", **ingestion_progress_options)
             else
               raise "Unknown format #{remote_file_type}, not sure how to export and ingest it"
             end
@@ -48,11 +51,18 @@ module SnowDuck
         database.execute_batch("CREATE TABLE #{table_name} (#{columns_def});")
       end
 
+      # Bucket is built again once credentials are rotated (see Data::Database#refresh_s3_credentials!),
+      # client built with the previous session token would keep using it after it expires
       def setup_s3_bucket(database_options)
-        @s3_bucket ||= begin
-          s3 = Aws::S3::Resource.new(access_key_id: database_options['s3_access_key_id'], secret_access_key: database_options['s3_secret_access_key'])
-          s3.bucket(remote_s3_bucket_name)
-        end
+        credentials = {
+          access_key_id: database_options['s3_access_key_id'],
+          secret_access_key: database_options['s3_secret_access_key'],
+          session_token: database_options['s3_session_token']
+        }
+        return @s3_bucket if @s3_bucket && @s3_bucket_credentials == credentials
+
+        @s3_bucket_credentials = credentials
+        @s3_bucket = Aws::S3::Resource.new(**credentials).bucket(remote_s3_bucket_name)
       end
 
       def remote_filename_suffix
@@ -152,6 +162,13 @@ module SnowDuck
 
       private
 
+      def ingestion_progress_options
+        {
+          on_progress: snow_duck_progress_logger("Ingesting #{table_name} from #{remote_file_location}"),
+          progress_interval: INGESTION_PROGRESS_INTERVAL
+        }
+      end
+
       # Snowflake can't export empty parquet file, with just a header and column definitions
       # We detect it by detecting that we are dealing with 404 error and parquet files
       def empty_parquet_file_detected?(e)
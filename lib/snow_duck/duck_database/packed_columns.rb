@@ -0,0 +1,55 @@
+class DuckDatabase
+  ##
+  # Numeric columns as packed binary strings, for results too big to become a ruby object per value.
+  #
+  # @example
+  #   columns = duck_db.pluck_packed('SELECT visit_id, amount::DOUBLE AS amount FROM invoices')
+  #   columns[:amount].data.unpack('d*')
+  #   Numo::DFloat.from_binary(columns[:amount].data)
+  #   columns[:amount].to_a # => [12.5, nil, 7.0]
+  #
+  #   duck_db.pluck_packed('SELECT * FROM invoices', columns: %i[amount])
+  #
+  # Only fixed width numeric columns (TINYINT..UBIGINT, FLOAT, DOUBLE) can be packed, other columns raise
+  # ArgumentError unless they are cast or left out with `columns:`. Values are in native byte order.
+  # `validity` is nil for columns without NULLs, otherwise an Arrow style bitmap: bit `i` (least significant bit
+  # first, same as String#unpack('b*') reads them) is set when value `i` is not NULL. Bytes of NULL values in
+  # `data` are unspecified.
+  #
+  module PackedColumns
+
+    # String#unpack directives of the packed types, in native byte order
+    PACK_FORMATS = {
+      tinyint: 'c', smallint: 's', integer: 'l', bigint: 'q',
+      utinyint: 'C', usmallint: 'S', uinteger: 'L', ubigint: 'Q',
+      float: 'f', double: 'd'
+    }.freeze
+
+    PackedColumn = Struct.new(:type, :data, :validity, :length, keyword_init: true) do
+      def pack_format
+        "#{PACK_FORMATS.fetch(type)}*"
+      end
+
+      def null?(index)
+        return false if validity.nil?
+
+        validity.getbyte(index / 8)[index % 8].zero?
+      end
+
+      def to_a
+        values = data.unpack(pack_format)
+        return values if validity.nil?
+
+        values.each_index.map { |index| null?(index) ? nil : values[index] }
+      end
+    end
+
+    def pluck_packed(sql, **options)
+      packed_columns(sql, **options).transform_values { |column| PackedColumn.new(**column) }
+    end
+
+  end
+
+  include PackedColumns
+  private :packed_columns
+end
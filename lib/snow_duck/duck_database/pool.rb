@@ -0,0 +1,102 @@
+class DuckDatabase
+  ##
+  # Thread-safe pool of connections to the same DuckDB database. Every connection has its own lock,
+  # so threads (e.g. Puma threads) can query already loaded tables in parallel.
+  #
+  # @example
+  #   pool = DuckDatabase::Pool.new(duck_db, size: 5)
+  #   pool.with { |connection| connection.pluck('SELECT count(*) FROM visits') }
+  #   pool.pluck_to_hash('SELECT * FROM visits LIMIT 10')
+  #
+  # Connections are opened lazily with DuckDatabase#connect, so they share tables, views and secrets with
+  # the database pool was created from, but not session settings or open transactions.
+  #
+  class Pool
+
+    class TimeoutError < SnowDuck::Error; end
+
+    DEFAULT_CHECKOUT_TIMEOUT = 5
+
+    attr_reader :database, :size, :checkout_timeout
+
+    def initialize(database, size:, checkout_timeout: DEFAULT_CHECKOUT_TIMEOUT)
+      raise ArgumentError, "Pool size must be positive, got #{size}" unless size.to_i.positive?
+
+      @database = database
+      @size = size.to_i
+      @checkout_timeout = checkout_timeout
+      @available_connections = []
+      @opened_connections = 0
+      @mutex = Mutex.new
+      @connection_returned = ConditionVariable.new
+    end
+
+    def with(timeout: checkout_timeout)
+      connection = checkout(timeout)
+      yield connection
+    ensure
+      checkin(connection) if connection
+    end
+
+    %i[
+      pluck pluck_to_hash pluck_to pluck_to_struct pluck_columns pluck_packed pluck_to_csv pluck_to_json
+      to_arrow_ipc load_arrow_ipc load_file export execute execute_batch
+    ].each do |method_name|
+      define_method(method_name) do |*args, **kwargs|
+        with { |connection| connection.public_send(method_name, *args, **kwargs) }
+      end
+    end
+
+    private
+
+    def checkout(timeout)
+      deadline = Process.clock_gettime(Process::CLOCK_MONOTONIC) + timeout
+      connection = @mutex.synchronize do
+        loop do
+          break @available_connections.pop unless @available_connections.empty?
+
+          # nil makes caller open a new connection
+          if @opened_connections < size
+            @opened_connections += 1
+            break nil
+          end
+
+          remaining = deadline - Process.clock_gettime(Process::CLOCK_MONOTONIC)
+          raise TimeoutError, "Could not get connection from pool (size #{size}) within #{timeout}s" if remaining <= 0
+
+          @connection_returned.wait(@mutex, remaining)
+        end
+      end
+      connection || open_connection
+    end
+
+    # opened outside of the pool mutex, so other threads can check connections in/out meanwhile
+    def open_connection
+      database.connect
+    rescue StandardError
+      forget_connection
+      raise
+    end
+
+    # Transaction left open (e.g. begin_transaction without commit) is rolled back, so next user does not
+    # inherit it. Connection that can not even roll back is dropped from the pool.
+    def checkin(connection)
+      connection.rollback if connection.in_transaction?
+    rescue StandardError
+      forget_connection
+    else
+      @mutex.synchronize do
+        @available_connections.push(connection)
+        @connection_returned.signal
+      end
+    end
+
+    def forget_connection
+      @mutex.synchronize do
+        @opened_connections -= 1
+        @connection_returned.signal
+      end
+    end
+
+  end
+end
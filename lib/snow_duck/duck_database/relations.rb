@@ -0,0 +1,87 @@
+class DuckDatabase
+  ##
+  # Ruby arrays of hashes queried by name, the way DuckDB's python client queries DataFrames, without creating
+  # a table for them.
+  #
+  # @example
+  #   duck_db.with_relation(visits: [{ id: 1, species: 'canine' }, { id: 2, species: 'feline' }]) do
+  #     duck_db.pluck('SELECT count(*) FROM visits')
+  #   end
+  #
+  #   duck_db.with_relation(visits: visits, schema: { visits: { id: :integer, species: :varchar } }) { ... }
+  #
+  # Schema is inferred from the values (nil values are skipped, columns with nil values only are VARCHAR),
+  # unless it is given for the relation with `schema:`. Rows are copied into DuckDB values when the block starts,
+  # so changing the array in the block does not change the relation.
+  #
+  # Relation is a temporary view of this connection while the block runs, so it shadows table with the same name
+  # and other connections (from #connect and Pool) do not see it. They can attach relations of the same name
+  # at the same time, each one queries its own rows.
+  #
+  # BigDecimal values have no inferred type, DuckDB DECIMAL is not one of the relation types. Pass schema: with
+  # :double for such columns, or turn the values into Strings for :varchar to keep every digit.
+  #
+  module Relations
+
+    RELATION_TYPES = [
+      [TrueClass, :boolean],
+      [FalseClass, :boolean],
+      [Integer, :bigint],
+      [Float, :double],
+      # DateTime is a Date as well
+      [Time, :timestamp],
+      [DateTime, :timestamp],
+      [Date, :date],
+      [String, :varchar]
+    ].freeze
+
+    def with_relation(schema: {}, **relations)
+      raise ArgumentError, 'with_relation requires at least one relation' if relations.empty?
+
+      attached = []
+      relations.each do |name, rows|
+        attach_relation(name, schema.fetch(name) { infer_relation_schema(name, rows) }, rows)
+        attached << name
+      end
+      yield(self)
+    ensure
+      attached&.each { |name| detach_relation(name) }
+    end
+
+    private
+
+    def infer_relation_schema(name, rows)
+      raise TypeError, "Rows of #{name} have to be an Array, got #{rows.class}" unless rows.is_a?(Array)
+
+      types = {}
+      rows.each do |row|
+        raise TypeError, "Rows of #{name} have to be Hashes, got #{row.inspect}" unless row.is_a?(Hash)
+
+        row.each { |column, value| types[column.to_s] = merge_relation_type(name, column, types[column.to_s], value) }
+      end
+      raise ArgumentError, "Can not infer schema of #{name} without any rows, pass it with schema:" if types.empty?
+
+      types.transform_values { |type| type || :varchar }
+    end
+
+    def merge_relation_type(name, column, type, value)
+      return type if value.nil?
+      if value.is_a?(BigDecimal)
+        raise ArgumentError, "#{name}.#{column} has BigDecimal values, pass schema: with :double for it"
+      end
+
+      value_type = RELATION_TYPES.find { |klass, _| value.is_a?(klass) }&.last
+      if value_type.nil?
+        raise ArgumentError, "Can not infer type of #{name}.#{column} from #{value.inspect}, pass schema: for #{name}"
+      end
+      return value_type if type.nil? || type == value_type
+      return :double if [type, value_type].sort == %i[bigint double]
+
+      raise ArgumentError, "#{name}.#{column} mixes #{type} and #{value_type} values, pass schema: for #{name}"
+    end
+
+  end
+
+  include Relations
+  private :attach_relation, :detach_relation
+end
@@ -0,0 +1,40 @@
+class DuckDatabase
+  ##
+  # Table functions reading their rows from ruby, so data only ruby has (API responses, ActiveRecord relations)
+  # can be joined with loaded tables without copying it into a DuckDB table first.
+  #
+  # @example
+  #   duck_db.register_table_function(:api_visits, id: :integer, species: :varchar) do |emit|
+  #     api.each_visit { |visit| emit << [visit.id, visit.species] }
+  #   end
+  #   duck_db.pluck('SELECT v.* FROM api_visits() v JOIN visits USING (id)')
+  #
+  #   duck_db.register_relation(:practices, Practice.where(active: true), schema: { id: :integer, name: :varchar })
+  #   duck_db.pluck('SELECT name FROM practices()')
+  #
+  # Rows are arrays (values in schema order) or hashes (keyed by column names, as symbols or strings).
+  # ActiveRecord records (anything responding to `attributes`) are read by column names as well.
+  # Rows are pulled from ruby in chunks while DuckDB reads the function, on the thread that runs the query,
+  # and the block runs again for every query reading the function.
+  #
+  module TableFunctions
+
+    def register_table_function(name, schema, &block)
+      raise ArgumentError, 'register_table_function requires a block' if block.nil?
+
+      define_table_function(name, schema, -> { Enumerator.new(&block) })
+    end
+
+    def register_relation(name, enumerable, schema:)
+      raise ArgumentError, "#{enumerable.inspect} does not respond to each" unless enumerable.respond_to?(:each)
+
+      register_table_function(name, schema) do |emit|
+        enumerable.each { |row| emit << (row.respond_to?(:attributes) ? row.attributes : row) }
+      end
+    end
+
+  end
+
+  include TableFunctions
+  private :define_table_function
+end
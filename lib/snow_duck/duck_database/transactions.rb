@@ -0,0 +1,39 @@
+class DuckDatabase
+  ##
+  # Block form of DuckDB transactions, built on duckdb-rs Transaction. begin_transaction/commit/rollback and
+  # in_transaction? primitives are there for transactions that do not fit in a block.
+  #
+  # @example
+  #   duck_db.transaction do |tx|
+  #     tx.execute_batch('CREATE TABLE visits_summary AS ...')
+  #     tx.execute_batch('CREATE TABLE practices_summary AS ...')
+  #   end
+  #
+  # Transaction is committed when the block returns and rolled back when it raises (the exception is re-raised).
+  # The block may also call `commit` or `rollback` itself. DuckDB does not support savepoints, so transaction
+  # blocks can not be nested: calling `transaction` inside of one raises ArgumentError.
+  #
+  # `isolate` is the test-suite flavour: everything done in the block is always rolled back, so expensive tables
+  # loaded before it stay untouched and nothing leaks from one isolated block into the next one. Code run in the
+  # block can not open transactions of its own.
+  # Only this connection sees changes made in the block, connections from #connect (and Pool) do not.
+  #
+  module Transactions
+
+    def transaction
+      run_transaction(true) { yield(self) }
+    end
+
+    def isolate
+      if in_transaction?
+        raise SnowDuck::Error, 'Can not isolate inside of a transaction, DuckDB does not support nested transactions'
+      end
+
+      run_transaction(false) { yield(self) }
+    end
+
+  end
+
+  include Transactions
+  private :run_transaction
+end
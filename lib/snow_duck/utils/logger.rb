@@ -12,6 +12,14 @@ module SnowDuck
         result
       end
 
+      # Callback for DuckDatabase#execute_batch(on_progress:), logs progress of long running statements
+      def snow_duck_progress_logger(message)
+        lambda do |percentage, rows_processed, total_rows|
+          progress = percentage.nil? ? 'progress unknown' : "#{percentage.round(1)}%"
+          snow_duck_log("#{message}: #{progress} (#{rows_processed}/#{total_rows} rows)")
+        end
+      end
+
       def snow_duck_log(message)
         snow_duck_logger_object.info(snow_duck_standard_log_message_format(message))
       end
@@ -0,0 +1,36 @@
+module SnowDuck
+  module Utils
+    ##
+    # RSpec helper, wrapping every example of a group in Data::Database#isolate (or DuckDatabase#isolate).
+    # It is not loaded with the gem, require it from spec helper:
+    #
+    #   require 'snow_duck/utils/rspec_isolation'
+    #
+    #   RSpec.describe VisitsReport do
+    #     extend SnowDuck::Utils::RSpecIsolation
+    #
+    #     before(:all) do
+    #       @database = SnowDuck::Data::Database.new(definition, options)
+    #       @database.with_tables(:visits, :practices) {}
+    #     end
+    #
+    #     isolate_snow_duck_database { @database }
+    #
+    #     it 'can mutate loaded tables' do ... end
+    #   end
+    #
+    # Block is evaluated in the example, so it can use instance variables, `let`s etc.
+    #
+    module RSpecIsolation
+
+      def isolate_snow_duck_database(&database)
+        raise ArgumentError, 'isolate_snow_duck_database needs a block returning the database' if database.nil?
+
+        around do |example|
+          instance_exec(&database).isolate { example.run }
+        end
+      end
+
+    end
+  end
+end
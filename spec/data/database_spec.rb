@@ -0,0 +1,84 @@
+# frozen_string_literal: true
+
+RSpec.describe SnowDuck::Data::Database do
+  subject(:database) do
+    described_class.new(
+      instance_double(SnowDuck::DDL::Database),
+      s3_region: 'us-east-1',
+      s3_access_key_id: 'first-key',
+      s3_secret_access_key: 'first-secret',
+      s3_credentials_provider: credentials_provider
+    )
+  end
+
+  let(:credentials_provider) do
+    lambda do
+      {
+        s3_region: 'eu-west-1',
+        s3_access_key_id: 'rotated-key',
+        s3_secret_access_key: 'rotated-secret',
+        s3_session_token: 'rotated-token'
+      }
+    end
+  end
+
+  def s3_secret
+    database.pluck!("SELECT secret_string FROM duckdb_secrets() WHERE type = 's3'").first
+  end
+
+  describe '#refresh_s3_credentials!' do
+    it 'swaps credentials from the provider into DuckDB' do
+      expect(s3_secret).to include('key_id=first-key')
+
+      database.refresh_s3_credentials!
+
+      expect(s3_secret).to include('key_id=rotated-key').and include('region=eu-west-1')
+      expect(database.options).to include(s3_access_key_id: 'rotated-key', s3_session_token: 'rotated-token')
+    end
+
+    context 'without credentials provider' do
+      let(:credentials_provider) { nil }
+
+      it 'keeps the credentials' do
+        database.refresh_s3_credentials!
+
+        expect(s3_secret).to include('key_id=first-key')
+      end
+    end
+
+    context 'when provider returns incomplete credentials' do
+      let(:credentials_provider) { -> { { s3_access_key_id: '' } } }
+
+      it 'raises and keeps the previous credentials' do
+        expect { database.refresh_s3_credentials! }.to raise_error(ArgumentError, /s3_access_key_id/)
+
+        expect(database.options).to include(s3_region: 'us-east-1', s3_access_key_id: 'first-key')
+        expect(s3_secret).to include('key_id=first-key')
+      end
+    end
+
+    context 'when provider returns credentials without session token' do
+      subject(:database) do
+        described_class.new(
+          instance_double(SnowDuck::DDL::Database),
+          s3_region: 'us-east-1',
+          s3_access_key_id: 'first-key',
+          s3_secret_access_key: 'first-secret',
+          s3_session_token: 'first-token',
+          s3_credentials_provider: credentials_provider
+        )
+      end
+
+      let(:credentials_provider) do
+        -> { { s3_region: 'eu-west-1', s3_access_key_id: 'rotated-key', s3_secret_access_key: 'rotated-secret' } }
+      end
+
+      it 'drops the previous session token' do
+        database.refresh_s3_credentials!
+
+        expect(database.options).to include(s3_access_key_id: 'rotated-key')
+        expect(database.options).not_to have_key(:s3_session_token)
+      end
+    end
+  end
+end
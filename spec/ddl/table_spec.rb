@@ -0,0 +1,53 @@
+# frozen_string_literal: true
+
+RSpec.describe SnowDuck::DDL::Table do
+  subject(:table) { table_class.new(connection_provider: nil, remote_s3_bucket_name: 'exports') }
+
+  let(:table_class) do
+    Class.new(described_class) do
+      def self.table_name
+        'visits'
+      end
+
+      def generate_ddl
+        'SELECT 1 AS id'
+      end
+    end
+  end
+
+  let(:first_credentials) do
+    { 's3_access_key_id' => 'first-key', 's3_secret_access_key' => 'first-secret', 's3_session_token' => 'first-token' }
+  end
+
+  before do
+    stub_const('Aws::S3::Resource', Class.new do
+      attr_reader :credentials
+
+      def initialize(**credentials)
+        @credentials = credentials
+      end
+
+      def bucket(name)
+        Struct.new(:name, :resource).new(name, self)
+      end
+    end)
+  end
+
+  describe '#setup_s3_bucket' do
+    it 'reuses the bucket while credentials stay the same' do
+      bucket = table.setup_s3_bucket(first_credentials)
+
+      expect(table.setup_s3_bucket(first_credentials.dup)).to be(bucket)
+      expect(bucket.name).to eq('exports')
+    end
+
+    it 'builds the bucket again with rotated credentials' do
+      table.setup_s3_bucket(first_credentials)
+
+      bucket = table.setup_s3_bucket(first_credentials.merge('s3_session_token' => 'rotated-token'))
+
+      expect(bucket.resource.credentials).to include(access_key_id: 'first-key', session_token: 'rotated-token')
+      expect(table.s3_bucket).to be(bucket)
+    end
+  end
+end
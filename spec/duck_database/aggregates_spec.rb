@@ -0,0 +1,63 @@
+# frozen_string_literal: true
+
+RSpec.describe DuckDatabase do
+  subject(:database) { described_class.new(FAKE_S3_CREDENTIALS) }
+
+  # weighted median, state is list of [value, weight] pairs
+  let(:weighted_median) do
+    {
+      init: -> { [] },
+      update: ->(pairs, value, weight) { pairs << [value, weight] },
+      combine: ->(pairs, other_pairs) { pairs + other_pairs },
+      finalize: lambda do |pairs|
+        half = pairs.sum(&:last) / 2.0
+        running = 0
+        pairs.sort_by(&:first).find { |_value, weight| (running += weight) >= half }&.first
+      end
+    }
+  end
+
+  describe '#create_aggregate' do
+    it 'aggregates groups' do
+      database.create_aggregate(:weighted_median, %i[double double], :double, **weighted_median)
+
+      result = database.pluck(<<~SQL)
+        SELECT species, weighted_median(fee, visits)
+        FROM (VALUES ('cat', 10.0, 1), ('cat', 20.0, 5), ('dog', 30.0, 1), ('dog', 40.0, 1), ('dog', 50.0, 1)) t(species, fee, visits)
+        GROUP BY species ORDER BY species
+      SQL
+      expect(result).to eq([['cat', 20.0], ['dog', 40.0]])
+    end
+
+    it 'uses the state as result without finalize and skips NULL rows' do
+      database.create_aggregate(:ruby_sum, [:bigint], :bigint, init: -> { 0 }, update: ->(sum, value) { sum + value },
+                                                               combine: ->(sum, other) { sum + other })
+
+      expect(database.pluck('SELECT ruby_sum(i) FROM (VALUES (1), (NULL), (2)) t(i)')).to eq([3])
+      expect(database.pluck('SELECT ruby_sum(i) FROM range(0) t(i)')).to eq([0])
+    end
+
+    it 'combines states aggregated in parallel' do
+      database.set(:threads, 4)
+      database.create_aggregate(:ruby_sum, [:bigint], :bigint, init: -> { 0 }, update: ->(sum, value) { sum + value },
+                                                               combine: ->(sum, other) { sum + other })
+
+      expect(database.pluck('SELECT ruby_sum(i) FROM range(1000000) t(i)')).to eq([499_999_500_000])
+      expect(database.pluck('SELECT count(*) FROM (SELECT i % 100 AS g, ruby_sum(i) FROM range(100000) t(i) GROUP BY g)')).to eq([100])
+    end
+
+    it 'turns exceptions into query errors' do
+      database.create_aggregate(:broken, [:integer], :integer, init: -> { 0 }, update: ->(_sum, _value) { raise 'no rollup' },
+                                                               combine: ->(sum, _other) { sum })
+
+      expect { database.pluck('SELECT broken(1)') }.to raise_error(StandardError, /no rollup/)
+    end
+
+    it 'requires callables' do
+      expect { database.create_aggregate(:broken, [:integer], :integer, init: -> { 0 }, update: 1, combine: ->(a, _b) { a }) }
+        .to raise_error(ArgumentError, /update must respond to call/)
+      expect { database.create_aggregate(:broken, [:integer], :integer, init: -> { 0 }) }
+        .to raise_error(ArgumentError, /missing keyword: :update/)
+    end
+  end
+end
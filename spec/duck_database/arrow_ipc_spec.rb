@@ -0,0 +1,64 @@
+# frozen_string_literal: true
+
+require 'stringio'
+
+RSpec.describe DuckDatabase do
+  subject(:database) { described_class.new(FAKE_S3_CREDENTIALS) }
+
+  let(:query) do
+    <<~SQL
+      SELECT i AS id, 'species ' || i AS species, i * 0.5::DOUBLE AS amount, DATE '2024-03-01' + i::INTEGER AS visited_on
+      FROM range(3) t(i)
+    SQL
+  end
+
+  describe '#to_arrow_ipc' do
+    it 'returns the result as binary Arrow IPC stream' do
+      bytes = database.to_arrow_ipc(query)
+
+      expect(bytes.encoding).to eq(Encoding::BINARY)
+      # stream starts with continuation marker of the schema message
+      expect(bytes.byteslice(0, 4)).to eq("\xFF\xFF\xFF\xFF".b)
+    end
+
+    it 'writes the stream to IO' do
+      io = StringIO.new(''.b)
+
+      written = database.to_arrow_ipc(query, io)
+
+      expect(written).to eq(io.string.bytesize)
+      expect(io.string).to eq(database.to_arrow_ipc(query))
+    end
+  end
+
+  describe '#load_arrow_ipc' do
+    it 'creates table from the stream' do
+      expect(database.load_arrow_ipc(:visits, database.to_arrow_ipc(query))).to eq(3)
+
+      expect(database.pluck('SELECT * FROM visits ORDER BY id')).to eq(database.pluck("#{query} ORDER BY id"))
+      types = database.pluck('SELECT column_name, column_type FROM (DESCRIBE visits)').to_h
+      expect(types).to eq('id' => 'BIGINT', 'species' => 'VARCHAR', 'amount' => 'DOUBLE', 'visited_on' => 'DATE')
+    end
+
+    it 'appends to existing table by column name' do
+      database.execute_batch('CREATE TABLE visits (species VARCHAR, id BIGINT, amount DOUBLE, visited_on DATE, note VARCHAR)')
+
+      database.load_arrow_ipc('visits', StringIO.new(database.to_arrow_ipc(query)))
+      database.load_arrow_ipc('visits', database.to_arrow_ipc(query))
+
+      expect(database.pluck('SELECT count(*), count(note), max(species) FROM visits')).to eq([[6, 0, 'species 2']])
+    end
+
+    it 'creates empty table from a result without rows' do
+      expect(database.load_arrow_ipc(:visits, database.to_arrow_ipc("#{query} LIMIT 0"))).to eq(0)
+
+      expect(database.pluck("SELECT count(*) FROM information_schema.columns WHERE table_name = 'visits'")).to eq([4])
+    end
+
+    it 'leaves nothing behind when the data is not Arrow IPC' do
+      expect { database.load_arrow_ipc(:visits, 'not arrow') }.to raise_error(StandardError)
+      expect { database.load_arrow_ipc(:visits, 42) }.to raise_error(TypeError)
+      expect(database.pluck("SELECT count(*) FROM duckdb_tables() WHERE table_name = 'visits'")).to eq([0])
+    end
+  end
+end
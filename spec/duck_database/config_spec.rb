@@ -0,0 +1,80 @@
+# frozen_string_literal: true
+
+RSpec.describe DuckDatabase do
+  subject(:database) { described_class.new(FAKE_S3_CREDENTIALS) }
+
+  describe '.new with config:' do
+    it 'applies the settings' do
+      database = described_class.new(
+        FAKE_S3_CREDENTIALS, config: { threads: 2, preserve_insertion_order: false, 'default_order' => :desc }
+      )
+
+      expect(database.setting(:threads)).to eq(2)
+      expect(database.setting(:preserve_insertion_order)).to be(false)
+      expect(database.setting('default_order').downcase).to eq('desc')
+    end
+
+    it 'rejects unknown settings' do
+      expect { described_class.new(FAKE_S3_CREDENTIALS, config: { thread_count: 2 }) }
+        .to raise_error(ArgumentError, /Unknown DuckDB setting "thread_count", supported settings are/)
+    end
+
+    it 'validates setting values' do
+      expect { described_class.new(FAKE_S3_CREDENTIALS, config: { threads: 0 }) }
+        .to raise_error(ArgumentError, 'Setting threads expects a positive Integer, got 0')
+      expect { described_class.new(FAKE_S3_CREDENTIALS, config: { memory_limit: :lots }) }
+        .to raise_error(ArgumentError, /Setting memory_limit expects a String like '4GB' or a number of bytes/)
+    end
+  end
+
+  describe '#setting' do
+    it 'reads any DuckDB setting' do
+      expect(database.setting('enable_progress_bar')).to be(true)
+      expect(database.setting(:threads)).to be_a(Integer)
+    end
+
+    it 'raises on names DuckDB does not know' do
+      expect { database.setting(:no_such_setting) }.to raise_error(StandardError, /no_such_setting/)
+    end
+
+    it 'requires String or Symbol name' do
+      expect { database.setting(42) }.to raise_error(ArgumentError, /Setting name must be a String or Symbol/)
+    end
+  end
+
+  describe '#set' do
+    it 'changes the setting and returns the value' do
+      expect(database.set(:threads, 3)).to eq(3)
+      expect(database.set('memory_limit', 1024**3)).to eq(1024**3)
+      database.set(:temp_directory, '/tmp/snow_duck')
+
+      expect(database.setting(:threads)).to eq(3)
+      expect(database.setting(:memory_limit)).to include('GiB')
+      expect(database.setting(:temp_directory)).to eq('/tmp/snow_duck')
+    end
+
+    it 'quotes text values' do
+      database.set(:temp_directory, "/tmp/owner's duck")
+
+      expect(database.setting(:temp_directory)).to eq("/tmp/owner's duck")
+    end
+
+    it 'rejects unknown settings' do
+      expect { database.set(:enable_external_access, false) }
+        .to raise_error(ArgumentError, /Unknown DuckDB setting "enable_external_access"/)
+    end
+
+    it 'validates values before they reach DuckDB' do
+      expect { database.set(:preserve_insertion_order, 'yes') }
+        .to raise_error(ArgumentError, 'Setting preserve_insertion_order expects true or false, got "yes"')
+      expect { database.set(:temp_directory, 42) }
+        .to raise_error(ArgumentError, 'Setting temp_directory expects a String, got 42')
+      expect { database.set(:default_order, :sideways) }
+        .to raise_error(ArgumentError, 'Setting default_order expects :asc or :desc, got :sideways')
+      expect { database.set(:threads, 2.5) }
+        .to raise_error(ArgumentError, 'Setting threads expects a positive Integer, got 2.5')
+
+      expect(database.setting(:preserve_insertion_order)).to be(true)
+    end
+  end
+end
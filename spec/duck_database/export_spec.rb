@@ -0,0 +1,88 @@
+# frozen_string_literal: true
+
+require 'tmpdir'
+
+RSpec.describe DuckDatabase do
+  subject(:database) { described_class.new(FAKE_S3_CREDENTIALS) }
+
+  let(:query) do
+    <<~SQL
+      SELECT i AS id, CASE WHEN i % 2 = 0 THEN 'canine' ELSE 'feline' END AS species, i * 0.5::DOUBLE AS amount
+      FROM range(4) t(i)
+    SQL
+  end
+
+  around do |example|
+    Dir.mktmpdir('snow_duck_export') do |directory|
+      @directory = directory
+      example.run
+    end
+  end
+
+  def path(name)
+    File.join(@directory, name)
+  end
+
+  describe '#export' do
+    it 'writes Parquet file and returns number of written rows' do
+      expect(database.export(query, path('visits.parquet'))).to eq(4)
+
+      expect(database.pluck("SELECT * FROM read_parquet('#{path('visits.parquet')}') ORDER BY id"))
+        .to eq(database.pluck("#{query} ORDER BY id"))
+    end
+
+    it 'writes CSV and JSON files' do
+      database.export(query, path('visits.csv'), format: :csv)
+      database.export(query, path('visits.json'), format: :json)
+
+      expect(File.readlines(path('visits.csv')).first.chomp).to eq('id,species,amount')
+      expect(database.pluck("SELECT count(*) FROM read_json('#{path('visits.json')}')")).to eq([4])
+    end
+
+    it 'compresses files' do
+      database.export(query, path('visits.parquet'), compression: :zstd, row_group_size: 2)
+      database.export(query, path('visits.csv.gz'), format: :csv, compression: :gzip)
+
+      codecs = database.pluck("SELECT DISTINCT compression FROM parquet_metadata('#{path('visits.parquet')}')")
+      expect(codecs).to eq(['ZSTD'])
+      expect(File.binread(path('visits.csv.gz'), 2)).to eq("\x1F\x8B".b)
+    end
+
+    it 'writes Hive partitioned directory' do
+      database.export(query, path('visits'), partition_by: %i[species])
+
+      expect(Dir.children(path('visits')).sort).to eq(%w[species=canine species=feline])
+      expect(database.pluck("SELECT count(*) FROM read_parquet('#{path('visits')}/*/*.parquet')")).to eq([4])
+    end
+
+    it 'exports query ending with a comment' do
+      expect(database.export("#{query} -- every visit", path('visits.parquet'))).to eq(4)
+    end
+
+    it 'quotes the path' do
+      database.export(query, path("owner's visits.parquet"))
+
+      expect(File).to exist(path("owner's visits.parquet"))
+    end
+
+    it 'accepts query with trailing semicolon' do
+      expect(database.export("#{query};", path('visits.parquet'))).to eq(4)
+    end
+
+    it 'validates options before the query runs' do
+      expect { database.export(query, path('visits.parquet'), format: :xlsx) }
+        .to raise_error(ArgumentError, /format must be :parquet, :csv or :json/)
+      expect { database.export(query, path('visits.csv'), format: :csv, compression: :snappy) }
+        .to raise_error(ArgumentError, /CSV files can not be compressed with Snappy/)
+      expect { database.export(query, path('visits.csv'), format: :csv, row_group_size: 10) }
+        .to raise_error(ArgumentError, /row_group_size is only supported by :parquet/)
+      expect { database.export(query, path('visits.parquet'), row_group_size: 0) }
+        .to raise_error(ArgumentError, /row_group_size must be positive/)
+      expect { database.export(query, path('visits'), partition_by: []) }
+        .to raise_error(ArgumentError, /partition_by needs at least one column/)
+      expect { database.export(query, path('visits.parquet'), codec: :zstd) }.to raise_error(ArgumentError, /codec/)
+
+      expect(Dir.children(@directory)).to be_empty
+    end
+  end
+end
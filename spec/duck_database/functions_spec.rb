@@ -0,0 +1,90 @@
+# frozen_string_literal: true
+
+RSpec.describe DuckDatabase do
+  subject(:database) { described_class.new(FAKE_S3_CREDENTIALS) }
+
+  describe '#create_function' do
+    it 'calls the block for every row' do
+      database.create_function(:fee, %i[double integer], :double) { |amount, visits| amount * visits }
+
+      expect(database.pluck('SELECT fee(2.5, 4)')).to eq([10.0])
+    end
+
+    it 'converts strings and dates both ways' do
+      database.create_function(:species_code, %i[varchar date], :varchar) { |species, date| "#{species.upcase[0, 3]}-#{date.year}" }
+
+      expect(database.pluck("SELECT species_code('canine', DATE '2024-03-01')")).to eq(['CAN-2024'])
+    end
+
+    it 'returns NULL for NULL arguments without calling the block' do
+      calls = 0
+      database.create_function(:twice, [:integer], :integer) { |value| calls += 1; value * 2 }
+
+      expect(database.pluck('SELECT twice(i) FROM (VALUES (1), (NULL), (3)) t(i) ORDER BY i NULLS LAST')).to eq([2, 6, nil])
+      expect(calls).to eq(2)
+    end
+
+    it 'calls vectorized block once per chunk with argument columns' do
+      chunk_sizes = []
+      database.create_function(:twice, [:bigint], :bigint, vectorized: true) do |values|
+        chunk_sizes << values.size
+        values.map { |value| value * 2 }
+      end
+
+      expect(database.pluck('SELECT sum(twice(i)) FROM range(10000) t(i)')).to eq([99_990_000])
+      expect(chunk_sizes.sum).to eq(10_000)
+      expect(chunk_sizes.size).to be < 10_000
+    end
+
+    it 'works with parallel execution on DuckDB threads' do
+      database.set(:threads, 4)
+      database.create_function(:twice, [:bigint], :bigint) { |value| value * 2 }
+
+      expect(database.pluck('SELECT sum(twice(i)) FROM range(1000000) t(i)')).to eq([999_999_000_000])
+    end
+
+    it 'is visible from other connections' do
+      database.create_function(:twice, [:integer], :integer) { |value| value * 2 }
+
+      expect(database.connect.pluck('SELECT twice(21)')).to eq([42])
+    end
+
+    it 'stays registered when transaction it was created in rolls back' do
+      database.transaction do
+        database.create_function(:twice, [:integer], :integer) { |value| value * 2 }
+        database.rollback
+      end
+
+      expect(database.pluck('SELECT twice(21)')).to eq([42])
+    end
+
+    it 'runs queries on one helper thread instead of a thread per query', if: File.directory?('/proc/self/task') do
+      os_threads = -> { Dir.children('/proc/self/task').size }
+      database.create_function(:twice, [:integer], :integer) { |value| value * 2 }
+      database.pluck('SELECT twice(1)')
+      threads = os_threads.call
+
+      100.times { |i| expect(database.pluck("SELECT twice(#{i})")).to eq([i * 2]) }
+
+      expect(os_threads.call).to eq(threads)
+    end
+
+    it 'turns exceptions raised by the block into query errors' do
+      database.create_function(:broken, [:integer], :integer) { |_value| raise ArgumentError, 'unknown species' }
+
+      expect { database.pluck('SELECT broken(1)') }.to raise_error(StandardError, /unknown species/)
+      expect(database.pluck('SELECT 1')).to eq([1])
+    end
+
+    it 'rejects results that do not match return type' do
+      database.create_function(:not_a_number, [:integer], :integer) { |_value| 'one' }
+
+      expect { database.pluck('SELECT not_a_number(1)') }.to raise_error(StandardError, /Expected Integer for INTEGER/)
+    end
+
+    it 'validates types and requires a block' do
+      expect { database.create_function(:fee, [:money], :double) { 1 } }.to raise_error(ArgumentError, /Unsupported type/)
+      expect { database.create_function(:fee, [:double], :double) }.to raise_error(ArgumentError, /requires a block/)
+    end
+  end
+end
@@ -0,0 +1,35 @@
+# frozen_string_literal: true
+
+RSpec.describe DuckDatabase do
+  subject(:database) { described_class.new(FAKE_S3_CREDENTIALS) }
+
+  # runs far longer than the examples wait, it is always ended by DuckDatabase#interrupt or the timeout
+  let(:endless_query) { 'SELECT sum(i) FROM range(100_000_000_000) t(i)' }
+
+  describe 'running queries' do
+    it 'let other ruby threads run' do
+      query = Thread.new do
+        database.pluck(endless_query, timeout: 10)
+      rescue SnowDuck::InterruptError => e
+        e
+      end
+      sleep 0.2
+
+      started_at = Process.clock_gettime(Process::CLOCK_MONOTONIC)
+      ticks = 0
+      20.times do
+        ticks += 1
+        sleep 0.01
+      end
+      elapsed = Process.clock_gettime(Process::CLOCK_MONOTONIC) - started_at
+
+      expect(ticks).to eq(20)
+      # holding the GVL, the query would keep this thread waiting until the timeout
+      expect(elapsed).to be < 2
+      expect(query).to be_alive
+
+      database.interrupt
+      expect(query.value).to be_a(SnowDuck::InterruptError)
+    end
+  end
+end
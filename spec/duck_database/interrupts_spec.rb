@@ -0,0 +1,112 @@
+# frozen_string_literal: true
+
+require 'timeout'
+
+RSpec.describe DuckDatabase do
+  subject(:database) { described_class.new(FAKE_S3_CREDENTIALS) }
+
+  # runs far longer than any example waits, it is always ended by an interrupt
+  let(:endless_query) { 'SELECT sum(i) FROM range(100_000_000_000) t(i)' }
+
+  def elapsed
+    started_at = Process.clock_gettime(Process::CLOCK_MONOTONIC)
+    yield
+    Process.clock_gettime(Process::CLOCK_MONOTONIC) - started_at
+  end
+
+  # runs the endless query in a thread, returning its error once it is interrupted
+  def run_endless_query
+    Thread.new do
+      database.pluck(endless_query)
+    rescue SnowDuck::InterruptError => e
+      e
+    end.tap { sleep 0.2 }
+  end
+
+  describe 'timeout:' do
+    it 'raises SnowDuck::TimeoutError once the query runs longer' do
+      expect(elapsed do
+        expect { database.pluck(endless_query, timeout: 0.2) }.to raise_error(SnowDuck::TimeoutError, /timed out/)
+      end).to be < 5
+    end
+
+    it 'covers waiting for the query of another thread' do
+      running = run_endless_query
+
+      expect(elapsed do
+        expect { database.pluck('SELECT 1', timeout: 0.2) }
+          .to raise_error(SnowDuck::TimeoutError, /busy with another query/)
+      end).to be < 5
+      expect(running).to be_alive
+    ensure
+      database.interrupt
+      running&.join
+    end
+
+    it 'leaves database usable' do
+      expect { database.execute(endless_query, timeout: 0.1) }.to raise_error(SnowDuck::TimeoutError)
+
+      expect(database.pluck('SELECT 42')).to eq([42])
+    end
+  end
+
+  describe '#interrupt' do
+    it 'raises SnowDuck::InterruptError in the thread running the query' do
+      running = run_endless_query
+
+      database.interrupt
+
+      error = running.value
+      expect(error).to be_a(SnowDuck::InterruptError)
+      expect(error).not_to be_a(SnowDuck::TimeoutError)
+      expect(database.pluck('SELECT 42')).to eq([42])
+    end
+
+    it 'does nothing while no query runs' do
+      database.interrupt
+
+      expect(database.pluck('SELECT 42')).to eq([42])
+    end
+  end
+
+  describe 'ruby interrupts' do
+    it 'interrupt the running query' do
+      expect(elapsed do
+        expect { Timeout.timeout(0.2) { database.pluck(endless_query) } }.to raise_error(Timeout::Error)
+      end).to be < 5
+      expect(database.pluck('SELECT 42')).to eq([42])
+    end
+
+    it 'raise the exception ruby interrupted the query with' do
+      running = Thread.new do
+        database.pluck(endless_query)
+      rescue RuntimeError => e
+        e
+      end
+      sleep 0.2
+
+      running.raise(RuntimeError, 'stop the query')
+
+      expect(running.join(5)&.value).to have_attributes(message: 'stop the query')
+      expect(database.pluck('SELECT 42')).to eq([42])
+    end
+
+    it 'stop waiting for the query of another thread' do
+      running = run_endless_query
+      waiting = Thread.new do
+        database.pluck('SELECT 1')
+      rescue RuntimeError => e
+        e
+      end
+      sleep 0.2
+
+      waiting.raise(RuntimeError, 'stop waiting')
+
+      expect(waiting.join(5)&.value).to have_attributes(message: 'stop waiting')
+      expect(running).to be_alive
+    ensure
+      database.interrupt
+      running&.join
+    end
+  end
+end
@@ -0,0 +1,160 @@
+# frozen_string_literal: true
+
+require 'tmpdir'
+
+RSpec.describe DuckDatabase do
+  subject(:database) { described_class.new(FAKE_S3_CREDENTIALS) }
+
+  let(:query) do
+    <<~SQL
+      SELECT i AS id, CASE WHEN i % 2 = 0 THEN 'canine' ELSE 'feline' END AS species, i * 0.5::DOUBLE AS amount
+      FROM range(4) t(i)
+    SQL
+  end
+
+  around do |example|
+    Dir.mktmpdir('snow_duck_load_file') do |directory|
+      @directory = directory
+      example.run
+    end
+  end
+
+  def path(name)
+    File.join(@directory, name)
+  end
+
+  def column_types(table_name)
+    database.pluck("SELECT column_name, column_type FROM (DESCRIBE #{table_name})").to_h
+  end
+
+  describe '#load_file' do
+    it 'creates table from Parquet file and returns number of loaded rows' do
+      database.export(query, path('visits.parquet'))
+
+      expect(database.load_file(:visits, path('visits.parquet'))).to eq(4)
+
+      expect(database.pluck('SELECT * FROM visits ORDER BY id')).to eq(database.pluck("#{query} ORDER BY id"))
+      expect(column_types(:visits)).to eq('id' => 'BIGINT', 'species' => 'VARCHAR', 'amount' => 'DOUBLE')
+    end
+
+    it 'appends to existing table by column name' do
+      database.export(query, path('visits.parquet'))
+      database.execute('CREATE TABLE visits (amount DOUBLE, id BIGINT, species VARCHAR, note VARCHAR)')
+
+      expect(database.load_file('visits', path('visits.parquet'))).to eq(4)
+
+      expect(database.pluck('SELECT id, note FROM visits ORDER BY id')).to eq([[0, nil], [1, nil], [2, nil], [3, nil]])
+    end
+
+    it 'loads listed columns with given types' do
+      File.write(path('visits.csv'), "id,species,amount\n1,canine,2.5\n2,feline,\n")
+
+      database.load_file(:visits, path('visits.csv'), columns: { id: :integer, amount: :double })
+
+      expect(column_types(:visits)).to eq('id' => 'INTEGER', 'amount' => 'DOUBLE')
+      expect(database.pluck('SELECT * FROM visits ORDER BY id')).to eq([[1, 2.5], [2, nil]])
+    end
+
+    it 'keeps column types of a CSV file without rows' do
+      File.write(path('visits.csv'), "id,visited_on\n")
+
+      loaded = database.load_file(
+        :visits, path('visits.csv'), columns: { id: :bigint, visited_on: :date }, csv_options: { headers: true }
+      )
+
+      expect(loaded).to eq(0)
+
+      expect(column_types(:visits)).to eq('id' => 'BIGINT', 'visited_on' => 'DATE')
+    end
+
+    it 'passes CSV options' do
+      File.write(path('visits.txt'), "skipped line\nid;species\n1;'canine; feline'\n2;NULL\n")
+
+      database.load_file(
+        :visits, path('visits.txt'),
+        format: :csv, csv_options: { col_sep: ';', quote_char: "'", null_string: 'NULL', skip: 1, headers: true }
+      )
+
+      expect(database.pluck('SELECT * FROM visits ORDER BY id')).to eq([[1, 'canine; feline'], [2, nil]])
+    end
+
+    it 'loads JSON files' do
+      database.export(query, path('visits.json'), format: :json)
+
+      expect(database.load_file(:visits, path('visits.json'))).to eq(4)
+    end
+
+    it 'loads globs of Hive partitioned files' do
+      database.export(query, path('visits'), partition_by: %i[species])
+
+      loaded = database.load_file(:visits, path('visits/*/*.parquet'), hive_partitioning: true, union_by_name: true)
+
+      expect(loaded).to eq(4)
+      expect(database.pluck('SELECT species, count(*) FROM visits GROUP BY ALL ORDER BY species'))
+        .to eq([['canine', 2], ['feline', 2]])
+    end
+
+    it 'quotes path and table name' do
+      database.export(query, path("owner's visits.parquet"))
+
+      database.load_file('visits"; DROP TABLE x; --', path("owner's visits.parquet"))
+
+      expect(database.pluck('SELECT count(*) FROM "visits""; DROP TABLE x; --"')).to eq([4])
+    end
+
+    context 'with schema mismatch' do
+      before { File.write(path('visits.csv'), "id,species\n1,canine\nabc,feline\n") }
+
+      it 'raises on column missing in the file' do
+        expect { database.load_file(:visits, path('visits.csv'), columns: { id: :varchar, amount: :double }) }
+          .to raise_error(SnowDuck::SchemaMismatchError, /File has no column\(s\) \["amount"\]/)
+      end
+
+      it 'raises on value of a different type' do
+        expect { database.load_file(:visits, path('visits.csv'), columns: { id: :integer }) }
+          .to raise_error(SnowDuck::SchemaMismatchError, /\AConversion Error: .*abc/)
+      end
+
+      # conversion errors are told from other failures by this prefix of DuckDB's message
+      it 'relies on DuckDB conversion errors starting with their type' do
+        expect { database.execute("SELECT 'abc'::INTEGER") }.to raise_error(StandardError, /\AConversion Error: /)
+      end
+
+      it 'raises other failures of the load as they are' do
+        File.write(path('more_visits.csv'), "id,species\n1,feline\n")
+        database.execute('CREATE TABLE visits (id INTEGER PRIMARY KEY, species VARCHAR)')
+        database.execute("INSERT INTO visits VALUES (1, 'canine')")
+
+        expect { database.load_file(:visits, path('more_visits.csv')) }
+          .to raise_error(StandardError) { |error| expect(error).not_to be_a(SnowDuck::SchemaMismatchError) }
+      end
+
+      it 'raises on column missing in the existing table' do
+        database.execute('CREATE TABLE visits (id VARCHAR)')
+
+        expect { database.load_file(:visits, path('visits.csv')) }
+          .to raise_error(SnowDuck::SchemaMismatchError, /Table visits has no column\(s\) \["species"\]/)
+      end
+
+      it 'leaves nothing behind' do
+        expect { database.load_file(:visits, path('visits.csv'), columns: { id: :integer }) }
+          .to raise_error(SnowDuck::SchemaMismatchError)
+
+        expect(database.pluck("SELECT count(*) FROM duckdb_tables() WHERE table_name = 'visits'")).to eq([0])
+      end
+    end
+
+    it 'validates options' do
+      expect { database.load_file(:visits, path('visits.xlsx')) }
+        .to raise_error(ArgumentError, /Can not tell format of .* pass format:/)
+      expect { database.load_file(:visits, path('visits.parquet'), csv_options: { col_sep: ';' }) }
+        .to raise_error(ArgumentError, /csv_options are only supported by :csv format/)
+      expect { database.load_file(:visits, path('visits.csv'), csv_options: { separator: ';' }) }
+        .to raise_error(ArgumentError, /separator/)
+      expect { database.load_file(:visits, path('visits.csv'), columns: { id: :money }) }
+        .to raise_error(ArgumentError, /Unsupported type "money"/)
+      expect { database.load_file(:visits, path('visits.csv'), columns: {}) }
+        .to raise_error(ArgumentError, /columns needs at least one column/)
+    end
+  end
+end
@@ -0,0 +1,65 @@
+# frozen_string_literal: true
+
+RSpec.describe DuckDatabase do
+  subject(:database) { described_class.new(FAKE_S3_CREDENTIALS) }
+
+  describe '#pluck_packed' do
+    let(:query) do
+      <<~SQL
+        SELECT i AS id, i::INTEGER AS visits, i * 0.5::DOUBLE AS amount, i::UTINYINT AS tiny,
+               CASE WHEN i % 3 = 0 THEN NULL ELSE i::DOUBLE END AS sometimes
+        FROM range(10) t(i)
+        ORDER BY i
+      SQL
+    end
+
+    it 'packs numeric columns into binary strings' do
+      columns = database.pluck_packed(query)
+
+      expect(columns.keys).to eq(%i[id visits amount tiny sometimes])
+      expect(columns[:id]).to have_attributes(type: :bigint, length: 10, validity: nil)
+      expect(columns[:id].data.encoding).to eq(Encoding::BINARY)
+      expect(columns[:id].data.unpack('q*')).to eq((0...10).to_a)
+      expect(columns[:visits].data.unpack('l*')).to eq((0...10).to_a)
+      expect(columns[:amount].data.unpack('d*')).to eq((0...10).map { |i| i * 0.5 })
+      expect(columns[:tiny].data.bytesize).to eq(10)
+    end
+
+    it 'marks NULLs in validity bitmap' do
+      sometimes = database.pluck_packed(query)[:sometimes]
+
+      expect(sometimes.validity.unpack1('b*')[0, 10]).to eq('0110110110')
+      expect(sometimes.null?(3)).to be(true)
+      expect(sometimes.null?(4)).to be(false)
+      expect(sometimes.to_a).to eq(database.pluck('SELECT CASE WHEN i % 3 = 0 THEN NULL ELSE i::DOUBLE END FROM range(10) t(i) ORDER BY i'))
+    end
+
+    it 'packs values of several record batches' do
+      ids = database.pluck_packed('SELECT i AS id FROM range(100003) t(i) ORDER BY i')[:id]
+
+      expect(ids.length).to eq(100_003)
+      expect(ids.to_a).to eq((0...100_003).to_a)
+    end
+
+    it 'keeps validity bitmap aligned across record batches' do
+      query = 'SELECT CASE WHEN i % 7 = 0 THEN NULL ELSE i END AS id FROM range(5000) t(i) ORDER BY i'
+
+      expect(database.pluck_packed(query)[:id].to_a).to eq(database.pluck(query))
+    end
+
+    it 'packs only selected columns' do
+      expect(database.pluck_packed(query, columns: %i[amount id]).keys).to eq(%i[amount id])
+    end
+
+    it 'raises for columns that can not be packed' do
+      expect { database.pluck_packed("SELECT 1 AS id, 'canine' AS species") }.to raise_error(ArgumentError, /species/)
+      expect(database.pluck_packed("SELECT 1 AS id, 'canine' AS species", columns: %i[id]).keys).to eq(%i[id])
+    end
+
+    it 'returns empty columns for empty results' do
+      id = database.pluck_packed('SELECT 1 AS id WHERE false')[:id]
+
+      expect(id).to have_attributes(type: :integer, data: '', length: 0, validity: nil)
+    end
+  end
+end
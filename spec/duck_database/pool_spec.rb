@@ -0,0 +1,90 @@
+# frozen_string_literal: true
+
+RSpec.describe DuckDatabase::Pool do
+  subject(:pool) { described_class.new(database, size: 2) }
+
+  let(:database) { DuckDatabase.new(FAKE_S3_CREDENTIALS) }
+
+  before { database.execute('CREATE TABLE visits AS SELECT i AS id FROM range(3) t(i)') }
+
+  it 'validates size' do
+    expect { described_class.new(database, size: 0) }.to raise_error(ArgumentError, /Pool size must be positive/)
+  end
+
+  it 'queries tables of the database' do
+    expect(pool.pluck('SELECT count(*) FROM visits')).to eq([3])
+    expect(pool.pluck_to_hash('SELECT id FROM visits ORDER BY id LIMIT 1', hash: :plain)).to eq([{ id: 0 }])
+  end
+
+  it 'reuses checked in connections' do
+    first = pool.with { |connection| connection }
+
+    expect(pool.with { |connection| connection }).to be(first)
+  end
+
+  it 'runs queries of concurrent threads on at most size connections' do
+    connections = Queue.new
+    mutex = Mutex.new
+    running = 0
+    most_running = 0
+
+    threads = Array.new(6) do
+      Thread.new do
+        pool.with do |connection|
+          mutex.synchronize { most_running = [most_running, running += 1].max }
+          connections << connection
+          connection.pluck('SELECT count(*) FROM visits, range(100_000) t(i)')
+          mutex.synchronize { running -= 1 }
+        end
+      end
+    end
+    threads.each(&:join)
+
+    expect(most_running).to eq(2)
+    expect(Array.new(connections.size) { connections.pop }.uniq.size).to eq(2)
+  end
+
+  it 'raises Pool::TimeoutError when no connection is returned in time' do
+    pool = described_class.new(database, size: 1)
+    checked_out = Queue.new
+    release = Queue.new
+    holder = Thread.new do
+      pool.with do
+        checked_out << true
+        release.pop
+      end
+    end
+    checked_out.pop
+
+    expect { pool.with(timeout: 0.1) { nil } }
+      .to raise_error(described_class::TimeoutError, /Could not get connection from pool \(size 1\) within 0.1s/)
+  ensure
+    release << true
+    holder.join
+  end
+
+  it 'frees the slot of a connection that failed to open' do
+    pool = described_class.new(database, size: 1, checkout_timeout: 0.1)
+    attempts = 0
+    allow(database).to receive(:connect).and_wrap_original do |connect|
+      attempts += 1
+      raise SnowDuck::Error, 'could not connect' if attempts == 1
+
+      connect.call
+    end
+
+    expect { pool.pluck('SELECT 1') }.to raise_error(SnowDuck::Error, 'could not connect')
+    expect(pool.pluck('SELECT count(*) FROM visits')).to eq([3])
+  end
+
+  it 'rolls back transaction left open by the block' do
+    pool = described_class.new(database, size: 1)
+    pool.with do |connection|
+      connection.begin_transaction
+      connection.execute('INSERT INTO visits VALUES (3)')
+    end
+
+    pool.with { |connection| expect(connection.in_transaction?).to be(false) }
+    expect(database.pluck('SELECT count(*) FROM visits')).to eq([3])
+  end
+end
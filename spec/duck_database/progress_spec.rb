@@ -0,0 +1,78 @@
+# frozen_string_literal: true
+
+RSpec.describe DuckDatabase do
+  subject(:database) { described_class.new(FAKE_S3_CREDENTIALS) }
+
+  # long enough for a few progress reports
+  let(:long_statement) { 'CREATE TABLE sums AS SELECT sum(i) AS total FROM range(1_000_000_000) t(i)' }
+  let(:reports) { [] }
+  let(:on_progress) { ->(*report) { reports << report } }
+
+  describe 'on_progress:' do
+    it 'reports progress of execute' do
+      database.execute(long_statement, on_progress: on_progress, progress_interval: 0.01)
+
+      expect(reports).not_to be_empty
+      percentage, rows_processed, total_rows = reports.last
+      expect(percentage).to be_nil.or(be_between(0, 100))
+      expect(rows_processed).to be_a(Integer)
+      expect(total_rows).to be_a(Integer)
+      expect(database.pluck('SELECT total FROM sums')).to eq([499_999_999_500_000_000])
+    end
+
+    it 'reports progress of execute_batch' do
+      database.execute_batch("#{long_statement}; DROP TABLE sums", on_progress: on_progress, progress_interval: 0.01)
+
+      expect(reports).not_to be_empty
+    end
+
+    it 'sees session settings of the connection' do
+      database.execute('CREATE SCHEMA clinic')
+      database.execute('CREATE TABLE clinic.visits AS SELECT 1 AS id')
+      database.execute("SET search_path = 'clinic'")
+
+      database.execute('CREATE TABLE main.copies AS SELECT * FROM visits', on_progress: on_progress)
+
+      expect(database.pluck('SELECT count(*) FROM main.copies')).to eq([1])
+    end
+
+    it 'reports progress of statement using temporary objects' do
+      database.execute('CREATE TEMP TABLE scratch AS SELECT 1 AS id')
+
+      database.execute(
+        "#{long_statement.sub('FROM range', 'FROM scratch, range')} WHERE scratch.id = 1",
+        on_progress: on_progress, progress_interval: 0.01
+      )
+
+      expect(reports).not_to be_empty
+      expect(database.pluck('SELECT total FROM sums')).to eq([499_999_999_500_000_000])
+    end
+
+    it 'keeps temporary objects the statement creates' do
+      database.execute('CREATE TEMP TABLE scratch AS SELECT 1 AS id', on_progress: on_progress)
+
+      expect(database.pluck('SELECT count(*) FROM scratch')).to eq([1])
+    end
+
+    it 'reports progress inside of a transaction' do
+      database.transaction do
+        database.execute(long_statement, on_progress: on_progress, progress_interval: 0.01)
+
+        expect(database.pluck('SELECT count(*) FROM sums')).to eq([1])
+        database.rollback
+      end
+
+      expect(reports).not_to be_empty
+      expect(database.pluck("SELECT count(*) FROM duckdb_tables() WHERE table_name = 'sums'")).to eq([0])
+    end
+
+    it 'validates the options' do
+      expect { database.execute('SELECT 1', on_progress: :report) }
+        .to raise_error(ArgumentError, /on_progress must respond to call/)
+      expect { database.execute('SELECT 1', progress_interval: 1) }
+        .to raise_error(ArgumentError, /progress_interval requires on_progress callback/)
+      expect { database.execute('SELECT 1', on_progress: on_progress, progress_interval: 0) }
+        .to raise_error(ArgumentError, /progress_interval must be positive/)
+    end
+  end
+end
@@ -0,0 +1,63 @@
+# frozen_string_literal: true
+
+require 'securerandom'
+
+RSpec.describe DuckDatabase do
+  subject(:database) { described_class.new(FAKE_S3_CREDENTIALS) }
+
+  # Ruby function is called by DuckDB while the query using it still holds the database, on the thread that
+  # runs the query. Returns whatever the nested call returned, or the error it raised.
+  def call_from_function(&nested_call)
+    outcome = nil
+    name = "nested_call_#{SecureRandom.hex(4)}"
+    database.create_function(name, [:integer], :integer) do |value|
+      outcome = nested_call.call
+      value
+    rescue StandardError => e
+      outcome = e
+      value
+    end
+    expect(database.pluck("SELECT #{name}(1)")).to eq([1])
+    outcome
+  end
+
+  describe 'nested calls from the same thread' do
+    it 'raises SnowDuck::ConcurrencyError instead of dead-locking' do
+      expect(call_from_function { database.pluck('SELECT 1') }).to be_a(SnowDuck::ConcurrencyError)
+    end
+
+    it 'raises for every query method' do
+      nested_calls = [
+        -> { database.execute('SELECT 1') },
+        -> { database.execute_batch('SELECT 1') },
+        -> { database.pluck_to_hash('SELECT 1') },
+        -> { database.setting(:threads) }
+      ]
+      nested_calls.each do |nested_call|
+        expect(call_from_function(&nested_call)).to be_a(SnowDuck::ConcurrencyError)
+      end
+    end
+
+    it 'leaves database usable after the error' do
+      expect(call_from_function { database.pluck('SELECT 1') }).to be_a(SnowDuck::ConcurrencyError)
+
+      expect(database.pluck('SELECT 42')).to eq([42])
+    end
+
+    it 'allows nested queries on a separate connection' do
+      connection = database.connect
+
+      expect(call_from_function { connection.pluck('SELECT 2') }).to eq([2])
+    end
+  end
+
+  describe 'calls from multiple threads' do
+    it 'waits for the running query instead of failing' do
+      database.execute('CREATE TABLE numbers AS SELECT range AS number FROM range(100000)')
+      results = Array.new(4) do
+        Thread.new { database.pluck('SELECT sum(number) FROM numbers') }
+      end.map(&:value)
+      expect(results).to all(eq([4_999_950_000]))
+    end
+  end
+end
@@ -0,0 +1,130 @@
+# frozen_string_literal: true
+
+RSpec.describe DuckDatabase do
+  subject(:database) { described_class.new(FAKE_S3_CREDENTIALS) }
+
+  describe '#with_relation' do
+    let(:visits) do
+      [
+        { id: 1, species: 'canine', amount: 10, visited_on: Date.new(2024, 3, 1), paid: true },
+        { id: 2, species: 'feline', amount: 12.5, visited_on: nil, paid: false },
+        { id: 3, species: nil, amount: nil, visited_on: Date.new(2024, 3, 2), paid: nil, note: 'late' }
+      ]
+    end
+
+    it 'queries arrays of hashes by name' do
+      result = database.with_relation(visits: visits) do
+        database.pluck('SELECT count(*), sum(amount), max(visited_on) FROM visits')
+      end
+
+      expect(result).to eq([[3, 22.5, Date.new(2024, 3, 2)]])
+    end
+
+    it 'infers the schema from values' do
+      database.with_relation(visits: visits) do
+        types = database.pluck("SELECT column_name, column_type FROM (DESCRIBE SELECT * FROM visits)").to_h
+
+        expect(types).to eq(
+          'id' => 'BIGINT', 'species' => 'VARCHAR', 'amount' => 'DOUBLE', 'visited_on' => 'DATE',
+          'paid' => 'BOOLEAN', 'note' => 'VARCHAR'
+        )
+      end
+    end
+
+    it 'takes explicit schema' do
+      database.with_relation(visits: visits, schema: { visits: { id: :integer, species: :varchar } }) do
+        expect(database.pluck('SELECT id, species FROM visits ORDER BY id'))
+          .to eq([[1, 'canine'], [2, 'feline'], [3, nil]])
+      end
+    end
+
+    it 'joins several relations with tables' do
+      database.execute_batch("CREATE TABLE prices AS SELECT 'canine' AS species, 2 AS factor")
+      practices = [{ 'id' => 1, 'name' => 'Downtown' }]
+
+      database.with_relation(visits: visits, practices: practices) do
+        expect(database.pluck(<<~SQL)).to eq([['Downtown', 20.0]])
+          SELECT p.name, v.amount * f.factor FROM visits v JOIN prices f USING (species) JOIN practices p ON p.id = v.id
+        SQL
+      end
+    end
+
+    it 'does not leave anything behind' do
+      database.with_relation(visits: visits) { nil }
+
+      expect { database.pluck('SELECT * FROM visits') }.to raise_error(StandardError, /visits/)
+      expect(database.pluck("SELECT count(*) FROM duckdb_tables() WHERE table_name = 'visits'")).to eq([0])
+      expect(database.pluck("SELECT count(*) FROM duckdb_views() WHERE view_name = 'visits'")).to eq([0])
+    end
+
+    it 'detaches relations when the block raises' do
+      expect { database.with_relation(visits: visits) { raise 'boom' } }.to raise_error('boom')
+
+      expect { database.pluck('SELECT * FROM visits') }.to raise_error(StandardError, /visits/)
+    end
+
+    it 'raises for values it can not infer types of' do
+      expect { database.with_relation(visits: [{ id: 1 }, { id: 'two' }]) { nil } }
+        .to raise_error(ArgumentError, /mixes bigint and varchar/)
+      expect { database.with_relation(visits: [{ at: Object.new }]) { nil } }.to raise_error(ArgumentError, /schema:/)
+      expect { database.with_relation(visits: []) { nil } }.to raise_error(ArgumentError, /without any rows/)
+    end
+
+    it 'does not infer type of BigDecimal values' do
+      amounts = [{ amount: BigDecimal('12.35') }]
+
+      expect { database.with_relation(amounts: amounts) { nil } }
+        .to raise_error(ArgumentError, /amounts.amount has BigDecimal values, pass schema: with :double/)
+      database.with_relation(amounts: amounts, schema: { amounts: { amount: :double } }) do
+        expect(database.pluck('SELECT amount FROM amounts')).to eq([12.35])
+      end
+    end
+
+    it 'shadows table with the same name in the block' do
+      database.execute_batch('CREATE TABLE visits AS SELECT 1 AS id')
+
+      database.with_relation(visits: visits) do
+        expect(database.pluck('SELECT count(*) FROM visits')).to eq([3])
+      end
+      expect(database.pluck('SELECT count(*) FROM visits')).to eq([1])
+    end
+
+    it 'is only visible to the connection it was attached to' do
+      connection = database.connect
+
+      database.with_relation(visits: visits) do
+        expect { connection.pluck('SELECT * FROM visits') }.to raise_error(StandardError, /visits/)
+      end
+    end
+
+    it 'lets connections attach relations with the same name at the same time' do
+      pool = DuckDatabase::Pool.new(database, size: 4)
+      attached = Queue.new
+      proceed = Queue.new
+
+      counts = Array.new(4) do |i|
+        Thread.new do
+          pool.with do |connection|
+            connection.with_relation(visits: visits.first(i + 1)) do
+              attached << true
+              proceed.pop
+              connection.pluck('SELECT count(*) FROM visits').first
+            end
+          end
+        end
+      end
+      # every relation is attached before any of them is queried or detached
+      4.times { attached.pop }
+      4.times { proceed << true }
+
+      expect(counts.map(&:value)).to eq([1, 2, 3, 3])
+    end
+
+    it 'raises when relation with the same name is attached already' do
+      database.with_relation(visits: visits) do
+        expect { database.with_relation(visits: visits) { nil } }.to raise_error(ArgumentError, /already attached/)
+        expect(database.pluck('SELECT count(*) FROM visits')).to eq([3])
+      end
+    end
+  end
+end
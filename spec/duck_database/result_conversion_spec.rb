@@ -0,0 +1,311 @@
+# frozen_string_literal: true
+
+RSpec.describe DuckDatabase do
+  subject(:database) { described_class.new(FAKE_S3_CREDENTIALS) }
+
+  describe 'columnar result conversion' do
+    let(:query) do
+      <<~SQL
+        SELECT i AS id,
+               i::TINYINT AS tiny, i::UBIGINT AS unsigned, sum(i) OVER () AS total,
+               i / 4 AS ratio, (i * 1.25)::DECIMAL(10, 2) AS amount,
+               'visit ' || i AS label, DATE '2024-03-01' + i::INTEGER AS visited_on,
+               TIMESTAMP '2024-03-01 10:30:00' + INTERVAL (i) HOUR AS visited_at,
+               INTERVAL (i) DAY AS waited, i % 2 = 0 AS even,
+               [i, i + 1] AS pair, {'species': 'canine', 'weight': i} AS pet, MAP {'visits': i} AS counts,
+               CASE WHEN i = 2 THEN NULL ELSE i END AS sometimes
+        FROM range(1, 4) t(i)
+        ORDER BY i
+      SQL
+    end
+
+    it 'returns the same values as row by row conversion' do
+      expect(database.pluck(query)).to eq(database.pluck(query, columnar: false))
+      expect(database.pluck_to_hash(query)).to eq(database.pluck_to_hash(query, columnar: false))
+    end
+
+    it 'converts values to ruby types' do
+      row = database.pluck_to_hash(query).first
+
+      expect(row[:total]).to eq(6)
+      expect(row[:amount]).to eq(BigDecimal('1.25'))
+      expect(row[:visited_on]).to eq(Date.new(2024, 3, 2))
+      expect(row[:visited_at]).to be_a(Time)
+      expect(row[:pair]).to eq([1, 2])
+      expect(row[:pet]).to eq('species' => 'canine', 'weight' => 1)
+    end
+
+    it 'tells DECIMAL(38, 0) from HUGEINT, which both are Decimal128(38, 0) in Arrow' do
+      query = <<~SQL
+        SELECT 12345678901234567890::DECIMAL(38, 0) AS amount, 12345678901234567890::HUGEINT AS total,
+               (i * 1.25)::DECIMAL(10, 2) AS fee
+        FROM range(1, 3) t(i)
+        ORDER BY i -- comment on the last line
+      SQL
+
+      rows = database.pluck_to_hash(query, hash: :plain)
+
+      expect(rows).to eq(database.pluck_to_hash(query, hash: :plain, columnar: false))
+      expect(rows.first.values.map(&:class)).to eq([BigDecimal, Integer, BigDecimal])
+      expect(rows.first).to eq(amount: BigDecimal('12345678901234567890'), total: 12_345_678_901_234_567_890, fee: BigDecimal('1.25'))
+      expect(database.pluck_columns(query)).to eq(database.pluck_columns(query, columnar: false))
+    end
+
+    it 'tells DECIMAL(38, 0) from HUGEINT nested in lists and structs' do
+      amount = BigDecimal('12345678901234567890')
+      total = 12_345_678_901_234_567_890
+
+      row = database.pluck_to_hash(<<~SQL, hash: :plain).first
+        SELECT [12345678901234567890::DECIMAL(38, 0)] AS amounts, [12345678901234567890::HUGEINT] AS totals,
+               {'amount': 12345678901234567890::DECIMAL(38, 0), 'total': 12345678901234567890::HUGEINT} AS pair,
+               [{'amounts': [12345678901234567890::DECIMAL(38, 0)]}] AS deep
+      SQL
+
+      expect(row).to eq(
+        amounts: [amount], totals: [total], pair: { 'amount' => amount, 'total' => total },
+        deep: [{ 'amounts' => [amount] }]
+      )
+      expect(row[:amounts].first).to be_a(BigDecimal)
+      expect(row[:totals].first).to be_a(Integer)
+    end
+
+    it 'tells DECIMAL(38, 0) from HUGEINT in results of statements other than SELECT' do
+      database.execute('CREATE TABLE payments (amount DECIMAL(38, 0), total HUGEINT)')
+
+      rows = database.pluck('INSERT INTO payments VALUES (1, 2) RETURNING amount, total')
+
+      expect(rows).to eq([[BigDecimal('1'), 2]])
+      expect(rows.first.map(&:class)).to eq([BigDecimal, Integer])
+    end
+
+    it 'plucks single column without wrapping values' do
+      expect(database.pluck('SELECT i FROM range(3) t(i) ORDER BY i')).to eq([0, 1, 2])
+    end
+
+    it 'converts results spanning several record batches' do
+      expect(database.pluck('SELECT i, i * 2 FROM range(100000) t(i) ORDER BY i').last).to eq([99_999, 199_998])
+    end
+
+    it 'returns empty results' do
+      expect(database.pluck('SELECT 1 WHERE false')).to eq([])
+      expect(database.pluck_to_hash('SELECT 1 AS one WHERE false')).to eq([])
+    end
+  end
+
+  describe 'pluck_to_hash keys' do
+    let(:query) { 'SELECT 1 AS sum_2024_11_practice_42, 2 AS other' }
+
+    it 'uses symbols by default' do
+      expect(database.pluck_to_hash(query).first[:sum_2024_11_practice_42]).to eq(1)
+    end
+
+    it 'uses frozen strings with keys: :string' do
+      row = database.pluck_to_hash(query, keys: :string).first
+
+      expect(row.keys).to eq(%w[sum_2024_11_practice_42 other])
+      expect(row.keys).to all(be_frozen)
+    end
+
+    it 'uses deduplicated strings with keys: :frozen_string' do
+      first, second = 2.times.map { database.pluck_to_hash(query, keys: :frozen_string).first }
+
+      expect(first.keys).to eq(%w[sum_2024_11_practice_42 other])
+      expect(first.keys.first).to equal(second.keys.first)
+    end
+
+    it 'uses the same keys when converting row by row' do
+      %i[symbol string frozen_string].each do |keys|
+        expect(database.pluck_to_hash(query, keys: keys, columnar: false)).to eq(database.pluck_to_hash(query, keys: keys))
+      end
+    end
+
+    it 'rejects unknown key modes' do
+      expect { database.pluck_to_hash(query, keys: :static) }.to raise_error(ArgumentError, /keys must be/)
+    end
+  end
+
+  describe 'pluck_to_hash hash flavours' do
+    let(:query) { 'SELECT 1 AS id, 2 AS visits' }
+
+    it 'returns hashes with indifferent access by default while ActiveSupport is loaded' do
+      row = database.pluck_to_hash(query).first
+
+      expect(row).to be_a(ActiveSupport::HashWithIndifferentAccess)
+      expect([row[:id], row['visits']]).to eq([1, 2])
+      expect(database.pluck_to_hash(query, columnar: false).first).to be_a(ActiveSupport::HashWithIndifferentAccess)
+    end
+
+    it 'returns plain hashes by default without ActiveSupport' do
+      hide_const('ActiveSupport::HashWithIndifferentAccess')
+
+      expect(database.pluck_to_hash(query).first.class).to eq(Hash)
+      expect { database.pluck_to_hash(query, hash: :indifferent) }
+        .to raise_error(StandardError, /hash: :indifferent requires ActiveSupport/)
+    end
+
+    it 'returns plain hashes' do
+      row = database.pluck_to_hash(query, hash: :plain).first
+
+      expect(row).to eq(id: 1, visits: 2)
+      expect(row.class).to eq(Hash)
+    end
+
+    it 'returns hashes with indifferent access' do
+      row = database.pluck_to_hash(query, hash: :indifferent).first
+
+      expect(row).to be_a(ActiveSupport::HashWithIndifferentAccess)
+      expect([row[:id], row['id']]).to eq([1, 1])
+      expect(row.keys).to eq(%w[id visits])
+    end
+
+    it 'returns frozen hashes' do
+      row = database.pluck_to_hash(query, hash: :frozen).first
+
+      expect(row).to eq(id: 1, visits: 2)
+      expect(row).to be_frozen
+    end
+
+    it 'returns the same rows when converting row by row' do
+      %i[plain indifferent frozen].each do |hash|
+        columnar = database.pluck_to_hash(query, hash: hash)
+        by_rows = database.pluck_to_hash(query, hash: hash, columnar: false)
+
+        expect(by_rows).to eq(columnar)
+        expect(by_rows.map(&:class)).to eq(columnar.map(&:class))
+      end
+    end
+
+    it 'rejects unknown flavours' do
+      expect { database.pluck_to_hash(query, hash: :ordered) }.to raise_error(ArgumentError, /hash must be/)
+    end
+  end
+
+  describe 'pluck_to_hash duplicate column names' do
+    before do
+      database.execute_batch(<<~SQL)
+        CREATE TABLE visits AS SELECT 1 AS id, 7 AS practice_id;
+        CREATE TABLE practices AS SELECT 7 AS id;
+      SQL
+    end
+
+    let(:query) { 'SELECT v.id, p.id, v.practice_id AS id_1 FROM visits v JOIN practices p ON p.id = v.practice_id' }
+
+    it 'raises instead of dropping values' do
+      expect { database.pluck_to_hash(query) }.to raise_error(SnowDuck::DuplicateColumnError, /"id".*duplicate_columns: :rename/m)
+      expect { database.pluck_to_hash(query, columnar: false) }.to raise_error(SnowDuck::DuplicateColumnError)
+    end
+
+    it 'renames later columns with duplicate_columns: :rename' do
+      expect(database.pluck_to_hash(query, hash: :plain, duplicate_columns: :rename))
+        .to eq([{ id: 1, id_2: 7, id_1: 7 }])
+      expect(database.pluck_to_hash(query, duplicate_columns: :rename).first.keys).to eq(%w[id id_2 id_1])
+    end
+
+    it 'leaves pluck alone' do
+      expect(database.pluck(query)).to eq([[1, 7, 7]])
+    end
+  end
+
+  describe 'row objects' do
+    let(:query) { "SELECT i AS id, 'species ' || i AS species FROM range(1, 3) t(i) ORDER BY i" }
+
+    it 'builds rows of a Struct generated for the result' do
+      rows = database.pluck_to_struct(query)
+
+      expect(rows.map(&:id)).to eq([1, 2])
+      expect(rows.first.species).to eq('species 1')
+      expect(rows.first.class.members).to eq(%i[id species])
+      expect(rows.map(&:class).uniq.size).to eq(1)
+      expect(database.pluck_to_struct(query, columnar: false).map(&:to_h)).to eq(rows.map(&:to_h))
+    end
+
+    it 'fills Struct members by name, whatever order columns are in' do
+      visit = Struct.new(:species, :id)
+
+      expect(database.pluck_to(visit, query)).to eq([visit.new('species 1', 1), visit.new('species 2', 2)])
+      expect(database.pluck_to(visit, query, columnar: false)).to eq(database.pluck_to(visit, query))
+    end
+
+    it 'passes columns as keywords to keyword_init Structs' do
+      visit = Struct.new(:species, :id, keyword_init: true)
+      subclass = Class.new(visit)
+
+      expect(database.pluck_to(visit, query)).to eq([visit.new(id: 1, species: 'species 1'), visit.new(id: 2, species: 'species 2')])
+      expect(database.pluck_to(visit, query, columnar: false)).to eq(database.pluck_to(visit, query))
+      expect(database.pluck_to(subclass, query).map(&:to_h)).to eq([{ species: 'species 1', id: 1 }, { species: 'species 2', id: 2 }])
+    end
+
+    it 'raises when columns do not match Struct members' do
+      expect { database.pluck_to(Struct.new(:id), query) }.to raise_error(ArgumentError, /species is not a member/)
+      expect { database.pluck_to(Struct.new(:id, :species, :weight), query) }.to raise_error(ArgumentError, /no column for .* weight/)
+    end
+
+    it 'passes columns as keywords to other classes' do
+      visit = Class.new do
+        attr_reader :id, :species
+
+        def initialize(id:, species:)
+          @id = id
+          @species = species
+        end
+      end
+
+      rows = database.pluck_to(visit, query)
+
+      expect(rows.first).to be_a(visit)
+      expect(rows.map(&:species)).to eq(['species 1', 'species 2'])
+    end
+
+    it 'renames duplicate columns to distinct members' do
+      row = database.pluck_to_struct('SELECT 1 AS id, 2 AS id', duplicate_columns: :rename).first
+
+      expect(row.to_h).to eq(id: 1, id_1: 2)
+      expect { database.pluck_to_struct('SELECT 1 AS id, 2 AS id') }.to raise_error(SnowDuck::DuplicateColumnError)
+    end
+
+    it 'does not take hash options' do
+      expect { database.pluck_to_struct(query, keys: :string) }.to raise_error(ArgumentError, /unknown keyword/)
+    end
+  end
+
+  describe 'pluck_columns' do
+    let(:query) { "SELECT i AS id, 'species ' || i AS species, i * 1.5 AS weight FROM range(1, 4) t(i) ORDER BY i" }
+
+    it 'returns values of every column keyed by column name' do
+      expect(database.pluck_columns(query)).to eq(
+        id: [1, 2, 3],
+        species: ['species 1', 'species 2', 'species 3'],
+        weight: [1.5, 3.0, 4.5]
+      )
+      expect(database.pluck_columns(query, columnar: false)).to eq(database.pluck_columns(query))
+    end
+
+    it 'returns only selected columns, in the order they are selected' do
+      columns = database.pluck_columns(query, columns: %i[weight id])
+
+      expect(columns).to eq(weight: [1.5, 3.0, 4.5], id: [1, 2, 3])
+      expect(columns.keys).to eq(%i[weight id])
+      expect(database.pluck_columns(query, columns: ['id'], columnar: false)).to eq(id: [1, 2, 3])
+    end
+
+    it 'raises for selected columns missing from the result' do
+      expect { database.pluck_columns(query, columns: %i[id name]) }.to raise_error(ArgumentError, /no column "name"/)
+    end
+
+    it 'joins values of several record batches' do
+      ids = database.pluck_columns('SELECT i AS id FROM range(100000) t(i) ORDER BY i')[:id]
+
+      expect(ids.size).to eq(100_000)
+      expect(ids.last).to eq(99_999)
+    end
+
+    it 'returns empty columns for empty results' do
+      expect(database.pluck_columns('SELECT 1 AS id, 2 AS other WHERE false')).to eq(id: [], other: [])
+    end
+
+    it 'takes the same key options as pluck_to_hash' do
+      expect(database.pluck_columns(query, columns: %i[id], keys: :string)).to eq('id' => [1, 2, 3])
+      expect(database.pluck_columns('SELECT 1 AS id, 2 AS id', duplicate_columns: :rename)).to eq(id: [1], id_1: [2])
+    end
+  end
+end
@@ -0,0 +1,37 @@
+# frozen_string_literal: true
+
+RSpec.describe DuckDatabase do
+  subject(:database) { described_class.new(FAKE_S3_CREDENTIALS) }
+
+  def s3_secrets
+    database.pluck("SELECT secret_string FROM duckdb_secrets() WHERE type = 's3'")
+  end
+
+  describe '#update_s3_credentials' do
+    it 'replaces the S3 secret' do
+      database.update_s3_credentials(
+        's3_region' => 'eu-west-1',
+        's3_access_key_id' => 'rotated-key',
+        's3_secret_access_key' => 'rotated-secret',
+        's3_session_token' => 'rotated-token'
+      )
+
+      expect(s3_secrets.size).to eq(1)
+      expect(s3_secrets.first).to include('key_id=rotated-key').and include('region=eu-west-1')
+    end
+
+    it 'is seen by connections to the same database' do
+      connection = database.connect
+
+      database.update_s3_credentials(FAKE_S3_CREDENTIALS.merge('s3_access_key_id' => 'rotated-key'))
+
+      expect(connection.pluck("SELECT secret_string FROM duckdb_secrets() WHERE type = 's3'").first)
+        .to include('key_id=rotated-key')
+    end
+
+    it 'requires region and keys' do
+      expect { database.update_s3_credentials('s3_region' => 'eu-west-1') }
+        .to raise_error(ArgumentError, /"s3_access_key_id" key required/)
+    end
+  end
+end
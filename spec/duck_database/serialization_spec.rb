@@ -0,0 +1,105 @@
+# frozen_string_literal: true
+
+require 'csv'
+require 'json'
+
+RSpec.describe DuckDatabase do
+  subject(:database) { described_class.new(FAKE_S3_CREDENTIALS) }
+
+  let(:query) do
+    <<~SQL
+      SELECT i AS id, 'visit "' || i || '", late' AS note, i / 4 AS ratio, (i * 1.25)::DECIMAL(10, 2) AS amount,
+             DATE '2024-03-01' + i::INTEGER AS visited_on, i % 2 = 0 AS even, INTERVAL (i) DAY AS waited,
+             CASE WHEN i = 2 THEN NULL ELSE i END AS sometimes, CASE WHEN i = 2 THEN '' END AS empty
+      FROM range(1, 4) t(i)
+      ORDER BY i
+    SQL
+  end
+
+  describe '#pluck_to_csv' do
+    it 'writes the same CSV ruby writes for plucked rows' do
+      rows = database.pluck(query)
+      headers = %w[id note ratio amount visited_on even waited sometimes empty]
+
+      expect(database.pluck_to_csv(query)).to eq(CSV.generate { |csv| [headers, *rows].each { |row| csv << row } })
+    end
+
+    it 'takes CSV options' do
+      csv = database.pluck_to_csv("SELECT 1 AS id, 'a;b' AS note", col_sep: ';', headers: false, row_sep: "\r\n")
+
+      expect(csv).to eq(%(1;"a;b"\r\n))
+      expect(database.pluck_to_csv('SELECT 1 AS id', force_quotes: true)).to eq(%("id"\n"1"\n))
+      expect { database.pluck_to_csv('SELECT 1', quote_char: "''") }.to raise_error(ArgumentError, /quote_char/)
+    end
+
+    it 'writes header of empty results' do
+      expect(database.pluck_to_csv('SELECT 1 AS id, 2 AS other WHERE false')).to eq("id,other\n")
+    end
+
+    it 'writes timestamps the way ruby writes plucked times and nested values as JSON' do
+      csv = database.pluck_to_csv(<<~SQL, headers: false)
+        SELECT TIMESTAMP '2024-03-01 10:30:00.250' AS visited_at, [1, 2] AS pair, {'species': 'canine'} AS pet
+      SQL
+
+      visited_at = database.pluck("SELECT TIMESTAMP '2024-03-01 10:30:00.250'").first
+      expect(csv).to eq(%(#{visited_at},"[1,2]","{""species"":""canine""}"\n))
+    end
+  end
+
+  context 'with time zone other than UTC' do
+    around do |example|
+      zone = ENV['TZ']
+      ENV['TZ'] = 'America/New_York'
+      example.run
+    ensure
+      ENV['TZ'] = zone
+    end
+
+    let(:query) { "SELECT TIMESTAMP '2024-03-01 15:30:00.250' AS visited_at" }
+
+    it 'writes timestamps in the local time zone' do
+      visited_at = database.pluck(query).first
+
+      expect(visited_at.to_s).to eq('2024-03-01 10:30:00 -0500')
+      expect(database.pluck_to_csv(query, headers: false)).to eq("#{visited_at}\n")
+      expect(database.pluck_to_json(query)).to eq('[{"visited_at":"2024-03-01T10:30:00.250-05:00"}]')
+      expect(database.pluck_to_json(query)).to eq(database.pluck_to_hash(query).to_json)
+    end
+  end
+
+  describe '#pluck_to_json' do
+    it 'writes the same JSON as pluck_to_hash rows encode to' do
+      expect(JSON.parse(database.pluck_to_json(query))).to eq(JSON.parse(database.pluck_to_hash(query).to_json))
+    end
+
+    it 'writes a row per line with format: :ndjson' do
+      json = database.pluck_to_json('SELECT i AS id FROM range(2) t(i) ORDER BY i', format: :ndjson)
+
+      expect(json).to eq(%({"id":0}\n{"id":1}\n))
+      expect(database.pluck_to_json('SELECT 1 AS id WHERE false', format: :ndjson)).to eq('')
+      expect(database.pluck_to_json('SELECT 1 AS id WHERE false')).to eq('[]')
+    end
+
+    it 'writes nested values, timestamps and special floats' do
+      row = JSON.parse(database.pluck_to_json(<<~SQL)).first
+        SELECT TIMESTAMP '2024-03-01 10:30:00.250' AS visited_at, [1, NULL] AS pair,
+               {'species': 'canine', 'weight': 1.5} AS pet, MAP {1: 'one'} AS names, 'nan'::DOUBLE AS missing,
+               1e20::DOUBLE AS big, E'tab\\t"quoted"' AS escaped
+      SQL
+
+      expect(row).to eq(
+        'visited_at' => Time.at(Time.utc(2024, 3, 1, 10, 30, 0, 250_000)).xmlschema(3), 'pair' => [1, nil],
+        'pet' => { 'species' => 'canine', 'weight' => 1.5 }, 'names' => { '1' => 'one' }, 'missing' => nil, 'big' => 1.0e20, 'escaped' => "tab\t\"quoted\""
+      )
+    end
+
+    it 'handles duplicate column names the way pluck_to_hash does' do
+      expect { database.pluck_to_json('SELECT 1 AS id, 2 AS id') }.to raise_error(SnowDuck::DuplicateColumnError)
+      expect(database.pluck_to_json('SELECT 1 AS id, 2 AS id', duplicate_columns: :rename)).to eq('[{"id":1,"id_1":2}]')
+    end
+
+    it 'rejects unknown formats' do
+      expect { database.pluck_to_json('SELECT 1', format: :xml) }.to raise_error(ArgumentError, /format must be/)
+    end
+  end
+end
@@ -0,0 +1,87 @@
+# frozen_string_literal: true
+
+RSpec.describe DuckDatabase do
+  subject(:database) { described_class.new(FAKE_S3_CREDENTIALS) }
+
+  describe '#register_table_function' do
+    it 'reads rows emitted by the block' do
+      database.register_table_function(:api_visits, id: :integer, species: :varchar) do |emit|
+        emit << [1, 'canine']
+        emit << { species: 'feline', id: 2 }
+        emit << { 'id' => 3, 'species' => nil }
+      end
+
+      expect(database.pluck('SELECT id, species FROM api_visits() ORDER BY id'))
+        .to eq([[1, 'canine'], [2, 'feline'], [3, nil]])
+    end
+
+    it 'stays registered when transaction it was created in rolls back' do
+      database.transaction do
+        database.register_table_function(:api_visits, id: :integer) { |emit| emit << [1] }
+        database.rollback
+      end
+
+      expect(database.pluck('SELECT id FROM api_visits()')).to eq([1])
+    end
+
+    it 'streams rows in chunks' do
+      database.register_table_function(:numbers, n: :bigint) do |emit|
+        100_000.times { |n| emit << [n] }
+      end
+
+      expect(database.pluck('SELECT count(*), sum(n) FROM numbers()')).to eq([[100_000, 4_999_950_000]])
+    end
+
+    it 'joins with tables and runs the block for every query' do
+      calls = 0
+      database.execute_batch("CREATE TABLE visits AS SELECT * FROM (VALUES (1, 10.0), (2, 20.0)) t(id, amount)")
+      database.register_table_function(:species, id: :integer, species: :varchar) do |emit|
+        calls += 1
+        emit << [1, 'canine']
+        emit << [2, 'feline']
+      end
+
+      2.times do
+        expect(database.pluck('SELECT species, amount FROM visits JOIN species() USING (id) ORDER BY id'))
+          .to eq([['canine', 10.0], ['feline', 20.0]])
+      end
+      expect(calls).to eq(2)
+    end
+
+    it 'raises errors of the block' do
+      database.register_table_function(:broken, id: :integer) { |_emit| raise 'API is down' }
+
+      expect { database.pluck('SELECT * FROM broken()') }.to raise_error(StandardError, /API is down/)
+    end
+
+    it 'raises for rows not matching the schema' do
+      database.register_table_function(:short_rows, id: :integer, species: :varchar) { |emit| emit << [1] }
+      database.register_table_function(:wrong_types, id: :integer) { |emit| emit << ['one'] }
+
+      expect { database.pluck('SELECT * FROM short_rows()') }.to raise_error(StandardError, /has to have 2 values/)
+      expect { database.pluck('SELECT * FROM wrong_types()') }.to raise_error(StandardError, /Expected/)
+    end
+
+    it 'validates the schema' do
+      expect { database.register_table_function(:empty, {}) { |_emit| nil } }.to raise_error(ArgumentError)
+      expect { database.register_table_function(:odd, id: :interval) { |_emit| nil } }.to raise_error(ArgumentError)
+    end
+  end
+
+  describe '#register_relation' do
+    it 'reads rows from enumerables' do
+      database.register_relation(:practices, [{ id: 1, name: 'Downtown' }, { id: 2, name: 'Uptown' }],
+                                 schema: { id: :integer, name: :varchar })
+
+      expect(database.pluck('SELECT name FROM practices() ORDER BY id')).to eq(%w[Downtown Uptown])
+    end
+
+    it 'reads records by their attributes' do
+      record = Struct.new(:attributes)
+      database.register_relation(:practices, [record.new({ 'id' => 1, 'name' => 'Downtown' })],
+                                 schema: { id: :integer, name: :varchar })
+
+      expect(database.pluck('SELECT id, name FROM practices()')).to eq([[1, 'Downtown']])
+    end
+  end
+end
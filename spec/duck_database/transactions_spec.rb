@@ -0,0 +1,126 @@
+# frozen_string_literal: true
+
+require 'spec_helper'
+require 'snow_duck/utils/rspec_isolation'
+
+RSpec.describe DuckDatabase do
+  let(:db) { DuckDatabase.new(FAKE_S3_CREDENTIALS) }
+
+  before { db.execute_batch('CREATE TABLE visits (id INTEGER);') }
+
+  def visit_ids
+    db.pluck('SELECT id FROM visits ORDER BY id').flatten
+  end
+
+  describe '#transaction' do
+    it 'commits when block finishes' do
+      db.transaction { |tx| tx.execute_batch('INSERT INTO visits VALUES (1), (2);') }
+
+      expect(db.in_transaction?).to be(false)
+      expect(visit_ids).to eq([1, 2])
+    end
+
+    it 'rolls back and re-raises when block raises' do
+      expect do
+        db.transaction do |tx|
+          tx.execute_batch('INSERT INTO visits VALUES (1);')
+          raise 'boom'
+        end
+      end.to raise_error(RuntimeError, 'boom')
+
+      expect(db.in_transaction?).to be(false)
+      expect(visit_ids).to eq([])
+    end
+
+    it 'returns the block value' do
+      expect(db.transaction { 42 }).to eq(42)
+    end
+
+    it 'keeps what the block committed on its own' do
+      db.transaction do |tx|
+        tx.execute_batch('INSERT INTO visits VALUES (1);')
+        tx.commit
+      end
+
+      expect(db.in_transaction?).to be(false)
+      expect(visit_ids).to eq([1])
+    end
+
+    it 'rolls back when block breaks out' do
+      db.transaction do |tx|
+        tx.execute_batch('INSERT INTO visits VALUES (1);')
+        break
+      end
+
+      expect(db.in_transaction?).to be(false)
+      expect(visit_ids).to eq([])
+    end
+
+    it 'refuses nested transaction and keeps the outer one going' do
+      db.transaction do |tx|
+        tx.execute_batch('INSERT INTO visits VALUES (1);')
+        expect { tx.transaction { tx.execute_batch('INSERT INTO visits VALUES (2);') } }
+          .to raise_error(ArgumentError, /Nested transactions are not supported/)
+        tx.execute_batch('INSERT INTO visits VALUES (3);')
+      end
+
+      expect(visit_ids).to eq([1, 3])
+    end
+
+    it 'refuses transaction opened with begin_transaction' do
+      db.begin_transaction
+
+      expect { db.transaction {} }.to raise_error(ArgumentError, /Nested transactions are not supported/)
+      expect(db.in_transaction?).to be(true)
+    ensure
+      db.rollback
+    end
+  end
+
+  describe '#isolate' do
+    it 'always rolls back' do
+      db.isolate { |isolated| isolated.execute_batch('INSERT INTO visits VALUES (1); CREATE TABLE leaked (id INTEGER);') }
+
+      expect(visit_ids).to eq([])
+      expect(db.pluck("SELECT count(*) FROM duckdb_tables() WHERE table_name = 'leaked'")).to eq([0])
+    end
+
+    it 'refuses transaction opened in the block' do
+      db.isolate do |isolated|
+        expect { isolated.transaction {} }.to raise_error(ArgumentError, /Nested transactions are not supported/)
+        isolated.execute_batch('INSERT INTO visits VALUES (1);')
+      end
+
+      expect(db.in_transaction?).to be(false)
+      expect(visit_ids).to eq([])
+    end
+
+    it 'can not be nested in a transaction' do
+      db.transaction do
+        expect { db.isolate {} }.to raise_error(SnowDuck::Error, /nested transactions/)
+      end
+    end
+  end
+
+  describe SnowDuck::Utils::RSpecIsolation do
+    extend SnowDuck::Utils::RSpecIsolation
+
+    # one database shared by the examples, table created by outer `before` is rolled back after each of them too
+    before(:all) { @shared_db = DuckDatabase.new(FAKE_S3_CREDENTIALS) }
+
+    let(:db) { @shared_db }
+
+    isolate_snow_duck_database { db }
+
+    # with random order either of these can run first, neither may see rows of the other one
+    it 'isolates first example' do
+      db.execute_batch('INSERT INTO visits VALUES (1);')
+      expect(visit_ids).to eq([1])
+    end
+
+    it 'isolates second example' do
+      db.execute_batch('INSERT INTO visits VALUES (2);')
+      expect(visit_ids).to eq([2])
+    end
+  end
+end
@@ -0,0 +1,19 @@
+# frozen_string_literal: true
+
+require 'snow_duck'
+
+# DuckDB does not touch S3 unless remote files are read, so empty credentials are fine for local queries
+FAKE_S3_CREDENTIALS = {
+  's3_region' => '',
+  's3_access_key_id' => '',
+  's3_secret_access_key' => ''
+}.freeze
+
+RSpec.configure do |config|
+  config.expect_with :rspec do |expectations|
+    expectations.include_chain_clauses_in_custom_matcher_descriptions = true
+  end
+
+  config.disable_monkey_patching!
+  config.order = :random
+end